@@ -1,4 +1,5 @@
 // SPDX-License-Identifier: GPL-3.0-only
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 fn main() {
@@ -6,4 +7,41 @@ fn main() {
         let git_hash = String::from_utf8(output.stdout).unwrap();
         println!("cargo:rustc-env=GIT_HASH={}", git_hash);
     }
+
+    // Version of reis actually built, reported to EIS clients over D-Bus
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    if let Some(lock_path) = find_lock(&manifest_dir) {
+        println!("cargo:rerun-if-changed={}", lock_path.display());
+        if let Ok(lock) = std::fs::read_to_string(&lock_path) {
+            let mut lines = lock.lines();
+            while let Some(line) = lines.next() {
+                if line == "name = \"reis\""
+                    && let Some(version) = lines
+                        .next()
+                        .and_then(|line| line.strip_prefix("version = \""))
+                        .and_then(|version| version.strip_suffix('"'))
+                {
+                    println!("cargo:rustc-env=REIS_VERSION={}", version);
+                    break;
+                }
+            }
+        }
+    }
+
+    // Naming a file above stops the rerun on any change in the package, so
+    // keep GIT_HASH following the checked out commit
+    for git_path in [".git/HEAD", ".git/refs", ".git/packed-refs"] {
+        let path = manifest_dir.join(git_path);
+        if path.exists() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+}
+
+/// `Cargo.lock` of the crate, or of the workspace it is built in.
+fn find_lock(manifest_dir: &Path) -> Option<PathBuf> {
+    manifest_dir
+        .ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|path| path.exists())
 }
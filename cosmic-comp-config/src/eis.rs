@@ -0,0 +1,358 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt::Display, ops::RangeInclusive};
+
+/// Highest evdev key or button code (KEY_MAX from linux/input-event-codes.h).
+pub const MAX_EVDEV_CODE: u32 = 0x2FF;
+
+/// Tuning for remote input injected through EIS (e.g. RemoteDesktop sessions)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EisConfig {
+    /// Maximum number of concurrent EIS connections
+    pub max_connections: usize,
+    /// Percentage of `max_connections` in use at which a warning is logged
+    /// and the `ConnectionLimitApproaching` D-Bus signal emitted, once until
+    /// usage drops below it again (0 disables)
+    pub connection_warning_percent: u32,
+    /// Offer remote keyboards and inject their key events
+    pub allow_keyboard: bool,
+    /// Offer remote pointers (relative, absolute and buttons) and inject
+    /// their events
+    pub allow_pointer: bool,
+    /// Offer relative pointer motion, if `allow_pointer` is set. Clients
+    /// sending both relative and absolute motion can make the cursor jump,
+    /// so one of them can be turned off to force a consistent motion model
+    pub allow_relative_pointer: bool,
+    /// Offer absolute pointer motion, if `allow_pointer` is set
+    pub allow_absolute_pointer: bool,
+    /// Offer remote touchscreens and inject their events
+    pub allow_touch: bool,
+    /// Offer remote scrolling and inject its events
+    pub allow_scroll: bool,
+    /// Multiplier applied to horizontal scroll deltas
+    pub scroll_multiplier_horizontal: f64,
+    /// Multiplier applied to vertical scroll deltas
+    pub scroll_multiplier_vertical: f64,
+    /// Scale scroll deltas up when scroll events arrive in quick succession
+    pub scroll_acceleration: bool,
+    /// Factor applied to relative pointer motion
+    pub pointer_sensitivity: f64,
+    /// Acceleration profile applied to relative pointer motion
+    pub pointer_acceleration: EisPointerAcceleration,
+    /// Move the pointer by the client's delta as-is, ignoring the sensitivity
+    /// and acceleration settings above
+    pub pointer_raw_motion: bool,
+    /// Share of relative pointer motion held back and spread over the
+    /// following motion, for smoother cursor movement over bursty networks.
+    /// Whatever is held back is applied within one frame. Absolute motion is
+    /// never smoothed (0 moves the cursor 1:1)
+    pub pointer_smoothing: f64,
+    /// What relative remote pointer motion does at the outer edges of the
+    /// output layout. Absolute motion is always placed as requested
+    pub pointer_edge: EisPointerEdge,
+    /// Drop remote button presses while a local pointer grab, like a
+    /// drag-and-drop, is in progress, instead of passing them to the grab
+    pub drop_buttons_during_grab: bool,
+    /// Swap the left and right buttons of remote pointers, after
+    /// `button_map` is applied
+    pub swap_left_right_buttons: bool,
+    /// Evdev button codes to replace remote button codes with, for clients
+    /// numbering their buttons differently
+    pub button_map: BTreeMap<u32, u32>,
+    /// Move the pointer to the center of the output when a session starts
+    /// emulating input
+    pub warp_pointer_on_start: bool,
+    /// Seconds after a disconnect during which a reconnecting client gets its
+    /// previous pointer position back (0 disables)
+    pub pointer_restore_window_secs: u64,
+    /// Give a session an exclusive keyboard grab once it starts emulating, so
+    /// local key presses are dropped until it disconnects
+    pub keyboard_grab: bool,
+    /// Hide the cursor on local outputs while a session is emulating a pointer
+    pub hide_local_cursor: bool,
+    /// XCursor theme to draw the cursor with while it is driven remotely, so
+    /// viewers can tell remote from local pointer movement
+    pub remote_cursor_theme: Option<String>,
+    /// Milliseconds without remote pointer input after which the cursor goes
+    /// back to the regular theme
+    pub remote_cursor_timeout_ms: u64,
+    /// Lock modifiers to switch to when a session starts emulating a keyboard.
+    /// `None` keeps the current state. Either way, the state is reported to
+    /// the client's keyboard devices.
+    pub lock_modifiers_on_start: Option<EisLockModifiers>,
+    /// Record how long the compositor takes to inject each remote input
+    /// event, reported by the `InjectionLatency` D-Bus method
+    pub measure_injection_latency: bool,
+    /// Type of the devices announced to clients, for clients and apps that
+    /// treat virtual devices differently
+    pub device_type: EisDeviceType,
+    /// Order of remote and local input arriving in the same event loop
+    /// iteration
+    pub remote_input_order: EisInputOrder,
+    /// Milliseconds after which queued remote pointer motion and scrolling
+    /// is dropped instead of injected, so a session catches up after the
+    /// compositor fell behind. Keys, buttons and touch are always injected.
+    /// Only applies with `remote_input_order` set to `LocalFirst` (0 disables)
+    pub stale_input_ms: u64,
+    /// File to record the input of all sessions to, with its timing, for
+    /// replaying it through the `ReplayInput` D-Bus method to reproduce bugs.
+    /// This captures everything typed remotely, passwords included, so only
+    /// set it while debugging
+    pub record_input_path: Option<String>,
+    /// What happens to repeated presses a session sends for a key it holds
+    /// down
+    pub key_repeat: EisKeyRepeat,
+    /// Drop the input of a session until the local user approved it through
+    /// the `GrantConsent` D-Bus method, which the `ConsentRequested` signal
+    /// asks for once the session starts emulating
+    pub require_consent: bool,
+    /// Seconds to wait for `GrantConsent` before disconnecting the session
+    pub consent_timeout_secs: u64,
+    /// Give each session a pointer and cursor of its own, on a seat of its
+    /// own, so remote users do not fight over one cursor. Keys and touch
+    /// still go to the shared seat
+    pub pointer_per_session: bool,
+    /// Largest remote pointer and scroll delta and absolute coordinate
+    /// accepted, in logical pixels. Events beyond it are dropped
+    pub max_input_magnitude: f64,
+    /// Compositor shortcuts remote keys trigger instead of reaching the
+    /// focused client. Remote keys never switch VTs, end the session or
+    /// trigger modifier-only shortcuts
+    pub remote_shortcuts: EisRemoteShortcuts,
+    /// Resolve remote input as usual, down to the output and surface it
+    /// targets, but only log what would be injected instead of injecting it,
+    /// for debugging coordinate mapping without disturbing the local user
+    pub dry_run: bool,
+}
+
+impl Default for EisConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 8,
+            connection_warning_percent: 75,
+            allow_keyboard: true,
+            allow_pointer: true,
+            allow_relative_pointer: true,
+            allow_absolute_pointer: true,
+            allow_touch: true,
+            allow_scroll: true,
+            scroll_multiplier_horizontal: 1.0,
+            scroll_multiplier_vertical: 1.0,
+            scroll_acceleration: false,
+            pointer_sensitivity: 1.0,
+            pointer_acceleration: EisPointerAcceleration::Flat,
+            pointer_raw_motion: false,
+            pointer_smoothing: 0.0,
+            pointer_edge: EisPointerEdge::Clamp,
+            drop_buttons_during_grab: false,
+            swap_left_right_buttons: false,
+            button_map: BTreeMap::new(),
+            warp_pointer_on_start: false,
+            pointer_restore_window_secs: 10,
+            keyboard_grab: false,
+            hide_local_cursor: false,
+            remote_cursor_theme: None,
+            remote_cursor_timeout_ms: 1500,
+            lock_modifiers_on_start: None,
+            measure_injection_latency: false,
+            device_type: EisDeviceType::Virtual,
+            remote_input_order: EisInputOrder::LocalFirst,
+            stale_input_ms: 100,
+            record_input_path: None,
+            key_repeat: EisKeyRepeat::Compositor,
+            require_consent: false,
+            consent_timeout_secs: 30,
+            pointer_per_session: false,
+            max_input_magnitude: 100_000.0,
+            remote_shortcuts: EisRemoteShortcuts::None,
+            dry_run: false,
+        }
+    }
+}
+
+impl EisConfig {
+    /// Reset values outside of their valid range to the default, returning a
+    /// description of each reset value.
+    pub fn validate(&mut self) -> Vec<String> {
+        let default = Self::default();
+        let mut invalid = Vec::new();
+        check(
+            &mut invalid,
+            "max_connections",
+            &mut self.max_connections,
+            1..=64,
+            default.max_connections,
+        );
+        check(
+            &mut invalid,
+            "connection_warning_percent",
+            &mut self.connection_warning_percent,
+            0..=100,
+            default.connection_warning_percent,
+        );
+        check(
+            &mut invalid,
+            "scroll_multiplier_horizontal",
+            &mut self.scroll_multiplier_horizontal,
+            0.01..=100.0,
+            default.scroll_multiplier_horizontal,
+        );
+        check(
+            &mut invalid,
+            "scroll_multiplier_vertical",
+            &mut self.scroll_multiplier_vertical,
+            0.01..=100.0,
+            default.scroll_multiplier_vertical,
+        );
+        check(
+            &mut invalid,
+            "pointer_sensitivity",
+            &mut self.pointer_sensitivity,
+            0.01..=100.0,
+            default.pointer_sensitivity,
+        );
+        check(
+            &mut invalid,
+            "pointer_smoothing",
+            &mut self.pointer_smoothing,
+            0.0..=0.9,
+            default.pointer_smoothing,
+        );
+        check(
+            &mut invalid,
+            "pointer_restore_window_secs",
+            &mut self.pointer_restore_window_secs,
+            0..=3600,
+            default.pointer_restore_window_secs,
+        );
+        check(
+            &mut invalid,
+            "consent_timeout_secs",
+            &mut self.consent_timeout_secs,
+            1..=600,
+            default.consent_timeout_secs,
+        );
+        check(
+            &mut invalid,
+            "max_input_magnitude",
+            &mut self.max_input_magnitude,
+            100.0..=10_000_000.0,
+            default.max_input_magnitude,
+        );
+        check(
+            &mut invalid,
+            "stale_input_ms",
+            &mut self.stale_input_ms,
+            0..=10_000,
+            default.stale_input_ms,
+        );
+        check(
+            &mut invalid,
+            "remote_cursor_timeout_ms",
+            &mut self.remote_cursor_timeout_ms,
+            100..=60_000,
+            default.remote_cursor_timeout_ms,
+        );
+        self.button_map.retain(|from, to| {
+            let valid = *from <= MAX_EVDEV_CODE && *to <= MAX_EVDEV_CODE;
+            if !valid {
+                invalid.push(format!(
+                    "button_map entry {from} => {to} is outside of 0..={MAX_EVDEV_CODE}, ignoring it"
+                ));
+            }
+            valid
+        });
+        invalid
+    }
+}
+
+fn check<T: PartialOrd + Display + Copy>(
+    invalid: &mut Vec<String>,
+    name: &str,
+    value: &mut T,
+    range: RangeInclusive<T>,
+    default: T,
+) {
+    // `contains` is false for NaN as well
+    if !range.contains(value) {
+        invalid.push(format!(
+            "{name} = {value} is outside of {}..={}, using {default}",
+            range.start(),
+            range.end()
+        ));
+        *value = default;
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EisLockModifiers {
+    pub num_lock: bool,
+    pub caps_lock: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EisPointerEdge {
+    /// The pointer stops at the edge, sliding along it
+    #[default]
+    Clamp,
+    /// The pointer reappears at the opposite edge of the layout
+    Wrap,
+    /// Motion that would leave the layout is ignored entirely
+    Release,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EisPointerAcceleration {
+    /// Deltas are only scaled by the sensitivity
+    #[default]
+    Flat,
+    /// Faster motion is scaled up further, like libinput's adaptive profile
+    Adaptive,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EisDeviceType {
+    /// Devices are announced as virtual, as befits remote input
+    #[default]
+    Virtual,
+    /// Devices are announced as physical, for clients expecting them
+    Physical,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EisInputOrder {
+    /// Remote input waits until local input of the same event loop iteration
+    /// was processed, so the local user wins a race
+    #[default]
+    LocalFirst,
+    /// Remote input is processed as soon as its socket is dispatched, which
+    /// may be before local input that arrived at the same time
+    Interleave,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EisKeyRepeat {
+    /// Repeated presses sent by the client are dropped. Held keys just stay
+    /// pressed, and Wayland clients repeat them on their own from the seat's
+    /// repeat info like local ones; the compositor sends no repeats itself
+    #[default]
+    Compositor,
+    /// Repeated presses sent by the client are delivered, each restarting
+    /// the key, so it repeats at the client's rate. Clients must repeat
+    /// faster than the seat's repeat delay for keys to not repeat twice
+    Client,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EisRemoteShortcuts {
+    /// Remote keys all go to the focused client
+    #[default]
+    None,
+    /// Shortcuts managing windows and workspaces, but not ones spawning
+    /// commands or running system actions like the launcher
+    WindowManagement,
+    /// All shortcuts, launchers and system actions included
+    All,
+}
@@ -6,6 +6,7 @@ use std::collections::HashMap;
 
 use crate::input::TouchpadOverride;
 
+pub mod eis;
 pub mod input;
 #[cfg(feature = "output")]
 pub mod output;
@@ -99,6 +100,8 @@ pub struct CosmicCompConfig {
     pub edge_snap_threshold: u32,
     pub accessibility_zoom: ZoomConfig,
     pub appearance_settings: AppearanceConfig,
+    /// Tuning for remote input injected through EIS
+    pub eis_config: eis::EisConfig,
 }
 
 impl Default for CosmicCompConfig {
@@ -135,6 +138,7 @@ impl Default for CosmicCompConfig {
             edge_snap_threshold: 0,
             accessibility_zoom: ZoomConfig::default(),
             appearance_settings: AppearanceConfig::default(),
+            eis_config: eis::EisConfig::default(),
         }
     }
 }
@@ -1051,13 +1051,22 @@ impl SurfaceThreadState {
             vrr = has_active_fullscreen;
         }
 
+        #[cfg(feature = "eis")]
+        let cursor_mode = if self.shell.read().hide_local_cursor {
+            CursorMode::None
+        } else {
+            CursorMode::All
+        };
+        #[cfg(not(feature = "eis"))]
+        let cursor_mode = CursorMode::All;
+
         let mut elements = output_elements(
             Some(&render_node),
             &mut renderer,
             &self.shell,
             self.clock.now(),
             self.mirroring.as_ref().unwrap_or(&self.output),
-            CursorMode::All,
+            cursor_mode,
             #[cfg(not(feature = "debug"))]
             None,
             #[cfg(feature = "debug")]
@@ -24,7 +24,7 @@ use smithay::{
     },
     wayland::compositor::{get_role, with_states},
 };
-use std::{collections::HashMap, io::Read, sync::Mutex};
+use std::{collections::HashMap, io::Read, sync::Mutex, time::Instant};
 use tracing::warn;
 use xcursor::{
     CursorTheme,
@@ -196,6 +196,11 @@ pub struct CursorStateInner {
     cursors: HashMap<CursorIcon, Cursor>,
     current_image: Option<Image>,
     image_cache: Vec<(Image, MemoryRenderBuffer)>,
+
+    /// Theme marking the cursor as remote-controlled, by name
+    remote_theme: Option<(String, CursorTheme)>,
+    remote_cursors: HashMap<CursorIcon, Cursor>,
+    remote_until: Option<Instant>,
 }
 
 impl CursorStateInner {
@@ -207,7 +212,35 @@ impl CursorStateInner {
         self.current_cursor = None;
     }
 
+    /// Draw named cursors from the cursor theme `theme` until `until`, to mark
+    /// the pointer as being driven remotely.
+    pub fn set_remote_theme(&mut self, theme: &str, until: Instant) {
+        if self
+            .remote_theme
+            .as_ref()
+            .is_none_or(|(name, _)| name != theme)
+        {
+            self.remote_theme = Some((theme.to_string(), CursorTheme::load(theme)));
+            self.remote_cursors.clear();
+        }
+        self.remote_until = Some(until);
+    }
+
+    pub fn remote_until(&self) -> Option<Instant> {
+        self.remote_until
+    }
+
     pub fn get_named_cursor(&mut self, shape: CursorIcon) -> &Cursor {
+        let remote = self
+            .remote_until
+            .is_some_and(|until| Instant::now() < until);
+        if let Some((_, theme)) = self.remote_theme.as_ref().filter(|_| remote) {
+            return self
+                .remote_cursors
+                .entry(shape)
+                .or_insert_with(|| Cursor::load(theme, shape, self.cursor_size));
+        }
+
         self.cursors
             .entry(shape)
             .or_insert_with(|| Cursor::load(&self.cursor_theme, shape, self.cursor_size))
@@ -246,6 +279,10 @@ impl Default for CursorStateInner {
             cursors: HashMap::new(),
             current_image: None,
             image_cache: Vec::new(),
+
+            remote_theme: None,
+            remote_cursors: HashMap::new(),
+            remote_until: None,
         }
     }
 }
@@ -47,6 +47,7 @@ pub use cosmic_comp_config::EdidProduct;
 use cosmic_comp_config::{
     AppearanceConfig, CosmicCompConfig, KeyboardConfig, TileBehavior, XkbConfig, XwaylandDescaling,
     XwaylandEavesdropping, ZoomConfig,
+    eis::EisConfig,
     input::{DeviceState as InputDeviceState, InputConfig, TouchpadOverride},
     output::comp::{
         OutputConfig, OutputInfo, OutputState, OutputsConfig, TransformDef, load_outputs,
@@ -178,7 +179,7 @@ impl Config {
             .expect("Failed to add cosmic-config to the event loop");
         let xdg = xdg::BaseDirectories::new();
 
-        let cosmic_comp_config =
+        let mut cosmic_comp_config =
             CosmicCompConfig::get_entry(&config).unwrap_or_else(|(errs, c)| {
                 if cfg!(debug_assertions) {
                     for err in errs {
@@ -187,6 +188,9 @@ impl Config {
                 }
                 c
             });
+        for invalid in cosmic_comp_config.eis_config.validate() {
+            warn!("Invalid eis_config value: {invalid}");
+        }
 
         // Listen for updates to the toolkit config
         if let Ok(tk_config) = cosmic_config::Config::new("com.system76.CosmicTk", 1) {
@@ -952,6 +956,36 @@ fn config_changed(config: cosmic_config::Config, keys: Vec<String>, state: &mut
                     }
                 }
             }
+            "eis_config" => {
+                let mut new = get_config::<EisConfig>(&config, "eis_config");
+                for invalid in new.validate() {
+                    warn!("Invalid eis_config value: {invalid}");
+                }
+                if new != state.common.config.cosmic_conf.eis_config {
+                    #[cfg(feature = "eis")]
+                    let keyboard_grab_changed = new.keyboard_grab
+                        != state.common.config.cosmic_conf.eis_config.keyboard_grab;
+                    #[cfg(feature = "eis")]
+                    let dry_run_changed =
+                        new.dry_run != state.common.config.cosmic_conf.eis_config.dry_run;
+                    state.common.config.cosmic_conf.eis_config = new;
+                    #[cfg(feature = "eis")]
+                    {
+                        if let Some(eis_state) = state.common.eis_state.as_mut() {
+                            eis_state.set_config(&state.common.config.cosmic_conf.eis_config);
+                        }
+                        if keyboard_grab_changed {
+                            state.eis_keyboard_grab_changed();
+                        }
+                        if dry_run_changed {
+                            let enabled = state.common.config.cosmic_conf.eis_config.dry_run;
+                            state.eis_set_dry_run(enabled);
+                        }
+                        state.eis_update_local_cursor();
+                        state.eis_seat_capabilities_changed();
+                    }
+                }
+            }
             _ => {}
         }
     }
@@ -6,33 +6,145 @@
 
 use calloop::channel;
 use futures_executor::ThreadPool;
+use futures_util::future::{Either, select};
+use std::os::fd::OwnedFd;
 use std::os::unix::net::UnixStream;
-use tracing::{error, info, warn};
-use zbus::message::Header;
+use std::sync::{Arc, atomic::Ordering};
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use zbus::{message::Header, object_server::SignalEmitter};
+
+use cosmic_comp_config::eis::{EisPointerEdge, MAX_EVDEV_CODE};
+
+use crate::input::eis::{EisPointerConfinement, EisProtocol, EisShared, RecordedInput};
+
+/// Requests delivered from the D-Bus interface to the compositor's calloop.
+pub enum EisMessage {
+    /// Server-side fd of a new EIS connection, and the name of the output to
+    /// keep its pointer on, if any.
+    Socket(UnixStream, Option<String>),
+    /// Toggle the exclusive keyboard grab of a client's session.
+    KeyboardGrab { client: String, enabled: bool },
+    /// Toggle pointer motion without focus changes for a client's session.
+    PointerNoFocus { client: String, enabled: bool },
+    /// Toggle whether input of a client's session resets the idle timers.
+    WakeOnInput { client: String, enabled: bool },
+    /// Bound the pointer of a client's session.
+    PointerConfinement {
+        client: String,
+        confinement: EisPointerConfinement,
+    },
+    /// Keep the pointer of a client's session on the named output, or on all
+    /// of them again.
+    TargetOutput {
+        client: String,
+        output: Option<String>,
+    },
+    /// Injection of remote input was paused or resumed.
+    InjectionPaused(bool),
+    /// Dry-run mode was switched on or off.
+    DryRun(bool),
+    /// Focus the first window with `app_id`, clicking its center with
+    /// `click`, and report whether one was found and clicked.
+    FocusWindow {
+        app_id: String,
+        click: bool,
+        found: async_channel::Sender<bool>,
+    },
+    /// Click `button` at `[x, y]`, and report once it was released.
+    Click {
+        button: u32,
+        position: [f64; 2],
+        done: async_channel::Sender<bool>,
+    },
+    /// Type a keysym, and report whether the keymap could produce it.
+    TypeKeysym {
+        keysym: u32,
+        typed: async_channel::Sender<bool>,
+    },
+    /// Replay recorded remote input.
+    Replay(Vec<RecordedInput>),
+    /// The local user approved the input of a connection.
+    GrantConsent(u64),
+    /// Offer the remote desktop's clipboard selection with these mime types,
+    /// or clear it.
+    SetSelection(Vec<String>),
+    /// Write the local clipboard selection as `mime_type` into `fd`.
+    ReadSelection { mime_type: String, fd: OwnedFd },
+}
 
 /// Channel sender for delivering EIS sockets to the compositor's calloop.
 #[derive(Clone)]
 pub struct EisSocketSender {
-    tx: channel::Sender<UnixStream>,
+    tx: channel::Sender<EisMessage>,
 }
 
 impl EisSocketSender {
-    pub fn new(tx: channel::Sender<UnixStream>) -> Self {
+    pub fn new(tx: channel::Sender<EisMessage>) -> Self {
         Self { tx }
     }
+
+    /// Deliver `msg` to the compositor, failing the D-Bus call if its event
+    /// loop is gone.
+    fn send(&self, msg: EisMessage) -> zbus::fdo::Result<()> {
+        self.tx
+            .send(msg)
+            .map_err(|_| zbus::fdo::Error::Failed("Compositor EIS channel closed".to_string()))
+    }
+}
+
+/// How long `FocusWindow` waits for the compositor to look up the window.
+const FOCUS_WINDOW_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long `Click` waits for the compositor to release the button.
+const CLICK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long `TypeKeysym` waits for the compositor to type the keysym.
+const TYPE_KEYSYM_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Confinement rectangle `[x, y, width, height]` of `SetPointerConfinement`
+/// and `SetPointerBounds`, or `None` for a width or height of 0, lifting it.
+fn confinement_region(
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+) -> zbus::fdo::Result<Option<[f64; 4]>> {
+    if ![x, y, width, height].iter().all(|value| value.is_finite()) || width < 0.0 || height < 0.0 {
+        return Err(zbus::fdo::Error::InvalidArgs(
+            "Confinement must be finite with a non-negative size".to_string(),
+        ));
+    }
+    Ok((width > 0.0 && height > 0.0).then_some([x, y, width, height]))
+}
+
+/// Wait up to `timeout` for the compositor to answer a request on `rx`,
+/// without blocking the executor serving the interface meanwhile.
+async fn reply(rx: async_channel::Receiver<bool>, timeout: Duration) -> zbus::fdo::Result<bool> {
+    let recv = std::pin::pin!(rx.recv());
+    match select(recv, async_io::Timer::after(timeout)).await {
+        Either::Left((Ok(answer), _)) => Ok(answer),
+        _ => Err(zbus::fdo::Error::Failed(
+            "Compositor did not respond".to_string(),
+        )),
+    }
 }
 
+/// Object path the interface is served at.
+const OBJECT_PATH: &str = "/com/system76/CosmicComp";
+
 /// Allowed D-Bus well-known names that may call `AcceptEisSocket`.
 const ALLOWED_CALLERS: &[&str] = &["org.freedesktop.impl.portal.desktop.cosmic"];
 
 /// D-Bus interface for the compositor to accept EIS socket fds.
 pub struct CosmicCompEis {
     sender: EisSocketSender,
+    shared: Arc<EisShared>,
 }
 
 impl CosmicCompEis {
-    pub fn new(sender: EisSocketSender) -> Self {
-        Self { sender }
+    pub fn new(sender: EisSocketSender, shared: Arc<EisShared>) -> Self {
+        Self { sender, shared }
     }
 }
 
@@ -50,72 +162,777 @@ impl CosmicCompEis {
         #[zbus(connection)] connection: &zbus::Connection,
         fd: zbus::zvariant::OwnedFd,
     ) -> zbus::fdo::Result<()> {
-        // Verify caller identity: resolve sender's unique name to well-known names
-        let sender = header
-            .sender()
-            .ok_or_else(|| zbus::fdo::Error::AccessDenied("no sender in D-Bus message".into()))?;
-
-        let dbus_proxy = zbus::fdo::DBusProxy::new(connection)
-            .await
-            .map_err(|e| zbus::fdo::Error::Failed(format!("D-Bus proxy error: {e}")))?;
-
-        // Check if the sender owns any of the allowed well-known names
-        let mut authorized = false;
-        for allowed in ALLOWED_CALLERS {
-            let bus_name: zbus::names::BusName<'_> = (*allowed)
-                .try_into()
-                .map_err(|e| zbus::fdo::Error::Failed(format!("invalid bus name: {e}")))?;
-            if let Ok(owner) = dbus_proxy.get_name_owner(bus_name).await
-                && owner.as_str() == sender.as_str()
-            {
-                authorized = true;
-                break;
+        let sender = authorize_caller(&header, connection, "AcceptEisSocket").await?;
+        let stream = stream_socket(fd, &sender, "AcceptEisSocket")?;
+        info!(sender = sender.as_str(), "Accepted EIS socket via D-Bus");
+        self.sender.send(EisMessage::Socket(stream, None))
+    }
+
+    /// Like `AcceptEisSocket`, for a session sharing the single output named
+    /// `output`: its pointer is kept on that output, and its absolute devices
+    /// only get a region for it, so the client maps its whole screen onto it.
+    async fn accept_eis_socket_for_output(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        fd: zbus::zvariant::OwnedFd,
+        output: String,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "AcceptEisSocketForOutput").await?;
+        let stream = stream_socket(fd, &sender, "AcceptEisSocketForOutput")?;
+        info!(
+            sender = sender.as_str(),
+            %output, "Accepted EIS socket for an output via D-Bus"
+        );
+        self.sender.send(EisMessage::Socket(stream, Some(output)))
+    }
+
+    /// Keep all pointer input of the session of `client` on the output named
+    /// `output`, announcing only that output to its absolute devices, or on
+    /// all outputs again if `output` is empty. Should the output go away, the
+    /// session gets all outputs until it is back. `client` is matched like
+    /// for `SetKeyboardGrab`.
+    async fn set_target_output(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        client: String,
+        output: String,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "SetTargetOutput").await?;
+        let output = (!output.is_empty()).then_some(output);
+        info!(
+            sender = sender.as_str(),
+            %client, ?output, "EIS target output set via D-Bus"
+        );
+        self.sender
+            .send(EisMessage::TargetOutput { client, output })
+    }
+
+    /// Give the session of `client` an exclusive keyboard grab, or release it.
+    /// `client` is the EIS client name, with the ` #N` suffix the compositor
+    /// logs for clients sharing a name; without the suffix the most recent
+    /// session of that name is picked. While the grab is held, local key
+    /// presses only reach the lock screen.
+    ///
+    /// The grab is released automatically when the session disconnects.
+    async fn set_keyboard_grab(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        client: String,
+        enabled: bool,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "SetKeyboardGrab").await?;
+
+        info!(
+            sender = sender.as_str(),
+            %client, enabled, "EIS keyboard grab toggled via D-Bus"
+        );
+        self.sender
+            .send(EisMessage::KeyboardGrab { client, enabled })
+    }
+
+    /// Let pointer motion of the session of `client` move the cursor without
+    /// giving surfaces pointer focus, for overlays such as annotation or
+    /// presentation tools, or return to regular motion. `client` is matched
+    /// like for `SetKeyboardGrab`.
+    async fn set_pointer_no_focus(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        client: String,
+        enabled: bool,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "SetPointerNoFocus").await?;
+
+        info!(
+            sender = sender.as_str(),
+            %client, enabled, "EIS pointer focus mode toggled via D-Bus"
+        );
+        self.sender
+            .send(EisMessage::PointerNoFocus { client, enabled })
+    }
+
+    /// Let input of the session of `client` reset the idle timers, as local
+    /// input does, or stop it from doing so, for passive sessions that must
+    /// not keep the machine awake. Enabled by default. `client` is matched
+    /// like for `SetKeyboardGrab`.
+    async fn set_wake_on_input(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        client: String,
+        enabled: bool,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "SetWakeOnInput").await?;
+
+        info!(
+            sender = sender.as_str(),
+            %client, enabled, "EIS wake on input toggled via D-Bus"
+        );
+        self.sender
+            .send(EisMessage::WakeOnInput { client, enabled })
+    }
+
+    /// Confine the pointer of the session of `client` to a rectangle in global
+    /// logical coordinates, e.g. a single window for presentation or kiosk
+    /// use. Relative and absolute motion is clamped to its edges. A width or
+    /// height of 0 lifts the confinement. `client` is matched like for
+    /// `SetKeyboardGrab`.
+    async fn set_pointer_confinement(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        client: String,
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "SetPointerConfinement").await?;
+        let region = confinement_region(x, y, width, height)?;
+
+        info!(
+            sender = sender.as_str(),
+            %client, ?region, "EIS pointer confinement set via D-Bus"
+        );
+        self.sender.send(EisMessage::PointerConfinement {
+            client,
+            confinement: EisPointerConfinement::Region(region),
+        })
+    }
+
+    /// Set how the pointer of the session of `client` is bounded, overriding
+    /// the config for it: `mode` is what relative motion does at the edges of
+    /// the output layout, `clamp`, `wrap` or `release`, or empty to follow
+    /// `pointer_edge` of the config. The pointer is confined to the rectangle
+    /// like for `SetPointerConfinement`, a width or height of 0 lifting it.
+    /// `client` is matched like for `SetKeyboardGrab`.
+    async fn set_pointer_bounds(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        client: String,
+        mode: String,
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "SetPointerBounds").await?;
+        let edge = match mode.as_str() {
+            "" => None,
+            "clamp" => Some(EisPointerEdge::Clamp),
+            "wrap" => Some(EisPointerEdge::Wrap),
+            "release" => Some(EisPointerEdge::Release),
+            _ => {
+                return Err(zbus::fdo::Error::InvalidArgs(format!(
+                    "Unknown edge mode {mode:?}, expected clamp, wrap, release or empty"
+                )));
             }
+        };
+        let region = confinement_region(x, y, width, height)?;
+
+        info!(
+            sender = sender.as_str(),
+            %client, ?edge, ?region, "EIS pointer bounds set via D-Bus"
+        );
+        self.sender.send(EisMessage::PointerConfinement {
+            client,
+            confinement: EisPointerConfinement::Bounds { edge, region },
+        })
+    }
+
+    /// Offer the clipboard selection of the remote desktop with `mime_types` on
+    /// the seat EIS input goes to. Pasting it emits `SelectionTransfer`. An
+    /// empty list clears it, if the remote desktop still owns it.
+    async fn set_selection(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        mime_types: Vec<String>,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "SetSelection").await?;
+        info!(
+            sender = sender.as_str(),
+            ?mime_types,
+            "EIS clipboard selection set via D-Bus"
+        );
+        self.sender.send(EisMessage::SetSelection(mime_types))
+    }
+
+    /// Read the local clipboard selection as `mime_type`, announced by
+    /// `SelectionOwnerChanged`, from the returned fd until EOF.
+    async fn selection_read(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        mime_type: String,
+    ) -> zbus::fdo::Result<zbus::zvariant::OwnedFd> {
+        let sender = authorize_caller(&header, connection, "SelectionRead").await?;
+        let (reader, writer) = std::io::pipe()
+            .map_err(|err| zbus::fdo::Error::Failed(format!("Failed to create pipe: {err}")))?;
+
+        debug!(
+            sender = sender.as_str(),
+            mime_type, "EIS clipboard selection read via D-Bus"
+        );
+        self.sender.send(EisMessage::ReadSelection {
+            mime_type,
+            fd: writer.into(),
+        })?;
+        Ok(OwnedFd::from(reader).into())
+    }
+
+    /// Fd to write the remote desktop's selection into for the paste
+    /// `serial` of `SelectionTransfer`, to be closed once written.
+    async fn selection_write(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        serial: u32,
+    ) -> zbus::fdo::Result<zbus::zvariant::OwnedFd> {
+        let sender = authorize_caller(&header, connection, "SelectionWrite").await?;
+        debug!(
+            sender = sender.as_str(),
+            serial, "EIS clipboard selection written via D-Bus"
+        );
+        self.shared
+            .selection_transfers
+            .lock()
+            .unwrap()
+            .remove(&serial)
+            .map(Into::into)
+            .ok_or_else(|| {
+                zbus::fdo::Error::InvalidArgs(format!("No pending selection transfer {serial}"))
+            })
+    }
+
+    /// Debug histogram of the compositor-side injection latency of remote
+    /// input, as (exclusive upper bound in µs, count) for each non-empty
+    /// bucket. Empty unless `measure_injection_latency` is set in `eis_config`.
+    async fn injection_latency(&self) -> Vec<(u64, u64)> {
+        self.shared.latency.lock().unwrap().buckets()
+    }
+
+    /// Debug snapshot of EIS activity as JSON: connection counts, processed
+    /// requests by type, dropped input by reason, and the paused and keyboard
+    /// grab state.
+    async fn statistics(&self) -> zbus::fdo::Result<String> {
+        let mut statistics = self.shared.statistics.lock().unwrap().clone();
+        statistics.injection_paused = self.shared.injection_paused.load(Ordering::Acquire);
+        serde_json::to_string(&statistics).map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+    }
+
+    /// Debug listing of connected clients as JSON: connection id, name,
+    /// application, the capabilities of the devices each bound and when it
+    /// last sent a request.
+    async fn sessions(&self) -> zbus::fdo::Result<String> {
+        let sessions = self
+            .shared
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        serde_json::to_string(&sessions).map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+    }
+
+    /// Pause or resume injection of remote input for all sessions. While
+    /// paused, sessions stay connected but their input events are dropped,
+    /// and input they held when pausing is released.
+    async fn set_injection_paused(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        paused: bool,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "SetInjectionPaused").await?;
+
+        if self.shared.injection_paused.swap(paused, Ordering::AcqRel) == paused {
+            return Ok(());
+        }
+        info!(
+            sender = sender.as_str(),
+            paused, "EIS input injection toggled via D-Bus"
+        );
+        if let Err(err) = self.injection_paused_changed(&emitter).await {
+            warn!(?err, "Failed to signal InjectionPaused change");
         }
+        self.sender.send(EisMessage::InjectionPaused(paused))
+    }
 
-        if !authorized {
-            warn!(
-                sender = sender.as_str(),
-                "Rejected AcceptEisSocket call from unauthorized D-Bus sender"
-            );
-            return Err(zbus::fdo::Error::AccessDenied(
-                "caller is not an authorized portal process".into(),
+    /// Switch dry-run mode on or off for all sessions. In dry-run mode,
+    /// remote input is resolved to the output and surface it targets and
+    /// logged at debug level, without key codes, but not injected, and
+    /// counted in `Statistics` as `dry_run_events`. Pointer motion thus
+    /// resolves from where the cursor stays. `FocusWindow`, `Click` and
+    /// `TypeKeysym` neither activate windows nor move the pointer either.
+    /// Overrides `dry_run` of `eis_config` until that changes.
+    async fn set_dry_run(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        enabled: bool,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "SetDryRun").await?;
+
+        self.shared.dry_run_requested.store(true, Ordering::Release);
+        if self.shared.dry_run.swap(enabled, Ordering::AcqRel) == enabled {
+            return Ok(());
+        }
+        info!(
+            sender = sender.as_str(),
+            enabled, "EIS dry run toggled via D-Bus"
+        );
+        if let Err(err) = self.dry_run_changed(&emitter).await {
+            warn!(?err, "Failed to signal DryRun change");
+        }
+        self.sender.send(EisMessage::DryRun(enabled))
+    }
+
+    /// Activate the first window with the given `app_id` and give it keyboard
+    /// focus, so remote keyboard input reaches it, and with `click` also click
+    /// at its center. Meant for automation, which cannot rely on what is under
+    /// the pointer. Fails if no window matches or injection is paused, and
+    /// with `click` if the click is not allowed, as for `Click`.
+    async fn focus_window(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        app_id: String,
+        click: bool,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "FocusWindow").await?;
+        if self.shared.injection_paused.load(Ordering::Acquire) {
+            return Err(zbus::fdo::Error::Failed(
+                "Remote input injection is paused".to_string(),
             ));
         }
+        info!(
+            sender = sender.as_str(),
+            app_id, click, "Window focus requested via D-Bus"
+        );
 
-        // Verify the fd is a UNIX stream socket (not a file, pipe, etc.)
-        let raw_fd = std::os::fd::OwnedFd::from(fd);
-        {
-            use std::os::fd::AsRawFd;
-            let mut sock_type: libc::c_int = 0;
-            let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
-            let ret = unsafe {
-                libc::getsockopt(
-                    raw_fd.as_raw_fd(),
-                    libc::SOL_SOCKET,
-                    libc::SO_TYPE,
-                    std::ptr::addr_of_mut!(sock_type).cast(),
-                    std::ptr::addr_of_mut!(len),
-                )
-            };
-            if ret != 0 || sock_type != libc::SOCK_STREAM {
-                warn!(
-                    sender = sender.as_str(),
-                    "Rejected AcceptEisSocket: fd is not a SOCK_STREAM socket"
-                );
-                return Err(zbus::fdo::Error::InvalidArgs(
-                    "fd must be a SOCK_STREAM Unix socket".into(),
-                ));
+        let (found_tx, found_rx) = async_channel::bounded(1);
+        self.sender.send(EisMessage::FocusWindow {
+            app_id: app_id.clone(),
+            click,
+            found: found_tx,
+        })?;
+        // The lookup is a single pass on the compositor's event loop
+        if reply(found_rx, FOCUS_WINDOW_TIMEOUT).await? {
+            Ok(())
+        } else {
+            Err(zbus::fdo::Error::Failed(format!(
+                "No window with app_id {app_id}, or its click was not allowed"
+            )))
+        }
+    }
+
+    /// Type the X keysym `keysym`, like the RemoteDesktop portal's
+    /// `NotifyKeyboardKeysym` for clients sending keysyms rather than
+    /// keycodes, pressing and releasing the key producing it along with the
+    /// modifiers its level needs. Keysyms the current layout lacks are typed
+    /// on a scratch key mapped to them. Like keyboard input of a session, it
+    /// fails if injection is paused, the keyboard is disabled in
+    /// `eis_config`, or no session has consent yet with `require_consent`.
+    async fn type_keysym(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        keysym: u32,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "TypeKeysym").await?;
+        if self.shared.injection_paused.load(Ordering::Acquire) {
+            return Err(zbus::fdo::Error::Failed(
+                "Remote input injection is paused".to_string(),
+            ));
+        }
+        if keysym == 0 {
+            return Err(zbus::fdo::Error::InvalidArgs(
+                "NoSymbol cannot be typed".to_string(),
+            ));
+        }
+        debug!(sender = sender.as_str(), keysym, "Keysym typed via D-Bus");
+
+        let (typed_tx, typed_rx) = async_channel::bounded(1);
+        self.sender.send(EisMessage::TypeKeysym {
+            keysym,
+            typed: typed_tx,
+        })?;
+        if reply(typed_rx, TYPE_KEYSYM_TIMEOUT).await? {
+            Ok(())
+        } else {
+            Err(zbus::fdo::Error::Failed(format!(
+                "Keysym {keysym:#x} cannot be typed or is not allowed"
+            )))
+        }
+    }
+
+    /// Move the pointer to `x`, `y` in global logical coordinates and click
+    /// `button` there, an evdev code like `BTN_LEFT` or a button index below
+    /// 8, pressing and releasing it with a short delay. Returns once the
+    /// button is released. Meant for automation, saving scripts the timing
+    /// of separate EIS events. The click is held to the policy of session
+    /// input: it fails if injection is paused, the pointer or absolute
+    /// pointer is disabled in `eis_config`, or no session has consent yet
+    /// with `require_consent`.
+    async fn click(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        button: u32,
+        x: f64,
+        y: f64,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "Click").await?;
+        if self.shared.injection_paused.load(Ordering::Acquire) {
+            return Err(zbus::fdo::Error::Failed(
+                "Remote input injection is paused".to_string(),
+            ));
+        }
+        if button > MAX_EVDEV_CODE || !x.is_finite() || !y.is_finite() {
+            return Err(zbus::fdo::Error::InvalidArgs(
+                "Button must be an evdev code and the position finite".to_string(),
+            ));
+        }
+        info!(
+            sender = sender.as_str(),
+            button, x, y, "Click requested via D-Bus"
+        );
+
+        let (done_tx, done_rx) = async_channel::bounded(1);
+        self.sender.send(EisMessage::Click {
+            button,
+            position: [x, y],
+            done: done_tx,
+        })?;
+        if reply(done_rx, CLICK_TIMEOUT).await? {
+            Ok(())
+        } else {
+            Err(zbus::fdo::Error::Failed(
+                "Click not allowed, or no output to click on".to_string(),
+            ))
+        }
+    }
+
+    /// Debug method replaying remote input recorded through
+    /// `record_input_path` in `eis_config` from the file at `path`, at its
+    /// original timing, to reproduce bugs. Fails if the file cannot be read,
+    /// or is larger than 64 MiB.
+    /// Replayed input bypasses `require_consent`, nobody is asked to approve
+    /// it.
+    async fn replay_input(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        path: String,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "ReplayInput").await?;
+        // Read on the blocking thread pool rather than stall the executor
+        // serving the interface on a large or slow file
+        let read_path = path.clone();
+        let events =
+            blocking::unblock(move || crate::input::eis::read_recording(read_path.as_ref()))
+                .await
+                .map_err(|e| zbus::fdo::Error::Failed(format!("{e:#}")))?;
+
+        info!(
+            sender = sender.as_str(),
+            %path,
+            events = events.len(),
+            "EIS input replay requested via D-Bus"
+        );
+        self.sender.send(EisMessage::Replay(events))
+    }
+
+    /// Let the input of session `id` through, after `ConsentRequested` asked
+    /// the local user to approve it. Without it, the session is disconnected
+    /// once `consent_timeout_secs` passed.
+    async fn grant_consent(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+        id: u64,
+    ) -> zbus::fdo::Result<()> {
+        let sender = authorize_caller(&header, connection, "GrantConsent").await?;
+
+        info!(
+            sender = sender.as_str(),
+            id, "EIS consent granted via D-Bus"
+        );
+        self.sender.send(EisMessage::GrantConsent(id))
+    }
+
+    /// Emitted with `require_consent` set in `eis_config` when session `id`
+    /// starts emulating, for the local user to approve its input through
+    /// `GrantConsent`. `application` is empty if unknown.
+    #[zbus(signal)]
+    async fn consent_requested(
+        emitter: SignalEmitter<'_>,
+        id: u64,
+        name: String,
+        application: String,
+    ) -> zbus::Result<()>;
+
+    /// The clipboard selection of the seat EIS input goes to changed to one
+    /// offering `mime_types`, empty if it was cleared, while EIS clients are
+    /// connected.
+    #[zbus(signal)]
+    async fn selection_owner_changed(
+        emitter: SignalEmitter<'_>,
+        mime_types: Vec<String>,
+    ) -> zbus::Result<()>;
+
+    /// A client pastes the remote desktop's selection as `mime_type`, to be
+    /// written into the fd `SelectionWrite` returns for `serial`.
+    #[zbus(signal)]
+    async fn selection_transfer(
+        emitter: SignalEmitter<'_>,
+        mime_type: String,
+        serial: u32,
+    ) -> zbus::Result<()>;
+
+    /// The number of connected clients reached `connection_warning_percent`
+    /// of `max_connections` in `eis_config`, so further connections will be
+    /// rejected soon. Sent again only after it dropped below the threshold.
+    #[zbus(signal)]
+    async fn connection_limit_approaching(
+        emitter: SignalEmitter<'_>,
+        active: u32,
+        max: u32,
+    ) -> zbus::Result<()>;
+
+    /// Whether injection of remote input is paused.
+    #[zbus(property)]
+    async fn injection_paused(&self) -> bool {
+        self.shared.injection_paused.load(Ordering::Acquire)
+    }
+
+    /// Whether remote input is resolved and logged but not injected.
+    #[zbus(property)]
+    async fn dry_run(&self) -> bool {
+        self.shared.dry_run.load(Ordering::Acquire)
+    }
+
+    /// Whether a remote client is emulating input right now, on any of its
+    /// devices, for status indicators.
+    #[zbus(property)]
+    async fn remote_input_active(&self) -> bool {
+        self.shared.remote_input_active.load(Ordering::Acquire)
+    }
+
+    /// Number of connected EIS clients.
+    #[zbus(property)]
+    async fn active_session_count(&self) -> u32 {
+        self.shared.active_sessions.load(Ordering::Acquire)
+    }
+
+    /// EIS protocol support as JSON: the reis version, the device interfaces
+    /// and capabilities offered and the request types handled.
+    #[zbus(property)]
+    async fn protocol(&self) -> zbus::fdo::Result<String> {
+        serde_json::to_string(&EisProtocol::current())
+            .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+    }
+}
+
+/// Emit `ConsentRequested` for session `id` from the compositor's event loop.
+pub fn consent_requested(
+    executor: &ThreadPool,
+    shared: &EisShared,
+    id: u64,
+    name: String,
+    application: String,
+) {
+    let Some(connection) = shared.dbus.get().cloned() else {
+        warn!(
+            id,
+            "EIS D-Bus interface not registered, cannot request consent"
+        );
+        return;
+    };
+    executor.spawn_ok(async move {
+        let result = match SignalEmitter::new(&connection, OBJECT_PATH) {
+            Ok(emitter) => CosmicCompEis::consent_requested(emitter, id, name, application).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            warn!(?err, "Failed to signal ConsentRequested");
+        }
+    });
+}
+
+/// Signal changes of the `RemoteInputActive` and `ActiveSessionCount`
+/// properties from the compositor's event loop.
+pub fn activity_changed(
+    executor: &ThreadPool,
+    shared: &EisShared,
+    active_changed: bool,
+    sessions_changed: bool,
+) {
+    let Some(connection) = shared.dbus.get().cloned() else {
+        return;
+    };
+    executor.spawn_ok(async move {
+        let result = async {
+            let iface = connection
+                .object_server()
+                .interface::<_, CosmicCompEis>(OBJECT_PATH)
+                .await?;
+            let emitter = iface.signal_emitter();
+            let iface = iface.get().await;
+            if active_changed {
+                iface.remote_input_active_changed(emitter).await?;
             }
+            if sessions_changed {
+                iface.active_session_count_changed(emitter).await?;
+            }
+            zbus::Result::Ok(())
+        };
+        if let Err(err) = result.await {
+            warn!(?err, "Failed to signal remote input activity change");
         }
+    });
+}
 
-        let stream = UnixStream::from(raw_fd);
-        info!(sender = sender.as_str(), "Accepted EIS socket via D-Bus");
-        self.sender
-            .tx
-            .send(stream)
-            .map_err(|_| zbus::fdo::Error::Failed("Compositor EIS channel closed".to_string()))
+/// Emit `ConnectionLimitApproaching` from the compositor's event loop.
+pub fn connection_limit_approaching(
+    executor: &ThreadPool,
+    shared: &EisShared,
+    active: u32,
+    max: u32,
+) {
+    let Some(connection) = shared.dbus.get().cloned() else {
+        return;
+    };
+    executor.spawn_ok(async move {
+        let result = match SignalEmitter::new(&connection, OBJECT_PATH) {
+            Ok(emitter) => CosmicCompEis::connection_limit_approaching(emitter, active, max).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            warn!(?err, "Failed to signal ConnectionLimitApproaching");
+        }
+    });
+}
+
+/// Emit `SelectionOwnerChanged` from the compositor's event loop.
+pub fn selection_owner_changed(executor: &ThreadPool, shared: &EisShared, mime_types: Vec<String>) {
+    let Some(connection) = shared.dbus.get().cloned() else {
+        return;
+    };
+    executor.spawn_ok(async move {
+        let result = match SignalEmitter::new(&connection, OBJECT_PATH) {
+            Ok(emitter) => CosmicCompEis::selection_owner_changed(emitter, mime_types).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            warn!(?err, "Failed to signal SelectionOwnerChanged");
+        }
+    });
+}
+
+/// Emit `SelectionTransfer` for paste `serial` from the compositor's event
+/// loop.
+pub fn selection_transfer(
+    executor: &ThreadPool,
+    shared: &EisShared,
+    mime_type: String,
+    serial: u32,
+) {
+    let Some(connection) = shared.dbus.get().cloned() else {
+        warn!(
+            serial,
+            "EIS D-Bus interface not registered, cannot transfer selection"
+        );
+        shared.selection_transfers.lock().unwrap().remove(&serial);
+        return;
+    };
+    executor.spawn_ok(async move {
+        let result = match SignalEmitter::new(&connection, OBJECT_PATH) {
+            Ok(emitter) => CosmicCompEis::selection_transfer(emitter, mime_type, serial).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            warn!(?err, "Failed to signal SelectionTransfer");
+        }
+    });
+}
+
+/// Verify `fd` is a UNIX stream socket (not a file, pipe, etc.), as passed to
+/// `method` by `sender`.
+fn stream_socket(
+    fd: zbus::zvariant::OwnedFd,
+    sender: &zbus::names::UniqueName<'_>,
+    method: &str,
+) -> zbus::fdo::Result<UnixStream> {
+    use std::os::fd::AsRawFd;
+
+    let raw_fd = std::os::fd::OwnedFd::from(fd);
+    let mut sock_type: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            raw_fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            std::ptr::addr_of_mut!(sock_type).cast(),
+            std::ptr::addr_of_mut!(len),
+        )
+    };
+    if ret != 0 || sock_type != libc::SOCK_STREAM {
+        warn!(
+            sender = sender.as_str(),
+            "Rejected {method}: fd is not a SOCK_STREAM socket"
+        );
+        return Err(zbus::fdo::Error::InvalidArgs(
+            "fd must be a SOCK_STREAM Unix socket".into(),
+        ));
     }
+    Ok(UnixStream::from(raw_fd))
+}
+
+/// Verify that the caller owns one of the `ALLOWED_CALLERS` well-known names,
+/// returning its unique name.
+async fn authorize_caller(
+    header: &Header<'_>,
+    connection: &zbus::Connection,
+    method: &str,
+) -> zbus::fdo::Result<zbus::names::UniqueName<'static>> {
+    // Verify caller identity: resolve sender's unique name to well-known names
+    let sender = header
+        .sender()
+        .ok_or_else(|| zbus::fdo::Error::AccessDenied("no sender in D-Bus message".into()))?;
+
+    let dbus_proxy = zbus::fdo::DBusProxy::new(connection)
+        .await
+        .map_err(|e| zbus::fdo::Error::Failed(format!("D-Bus proxy error: {e}")))?;
+
+    // Check if the sender owns any of the allowed well-known names
+    for allowed in ALLOWED_CALLERS {
+        let bus_name: zbus::names::BusName<'_> = (*allowed)
+            .try_into()
+            .map_err(|e| zbus::fdo::Error::Failed(format!("invalid bus name: {e}")))?;
+        if let Ok(owner) = dbus_proxy.get_name_owner(bus_name).await
+            && owner.as_str() == sender.as_str()
+        {
+            return Ok(sender.to_owned());
+        }
+    }
+
+    warn!(
+        sender = sender.as_str(),
+        method, "Rejected call from unauthorized D-Bus sender"
+    );
+    Err(zbus::fdo::Error::AccessDenied(
+        "caller is not an authorized portal process".into(),
+    ))
 }
 
 /// Initialize the EIS D-Bus interface and register it on the session bus.
@@ -127,35 +944,84 @@ pub fn init(
     evlh: &calloop::LoopHandle<'static, crate::state::State>,
     executor: &ThreadPool,
 ) -> anyhow::Result<()> {
-    let (socket_tx, socket_rx) = channel::channel::<UnixStream>();
+    let (socket_tx, socket_rx) = channel::channel::<EisMessage>();
+    let shared = Arc::new(EisShared::default());
+    let state_shared = shared.clone();
 
     // Register the socket receiver with calloop - when the portal sends
     // an EIS fd, this will deliver it to the compositor
-    evlh.insert_source(socket_rx, |event, _, state| {
-        if let channel::Event::Msg(stream) = event {
-            // Initialize EIS state if needed, then add connection
-            if state.common.eis_state.is_none() {
-                match crate::input::eis::EisState::new(&state.common.event_loop_handle) {
-                    Ok(eis_state) => {
-                        state.common.eis_state = Some(eis_state);
-                    }
-                    Err(err) => {
-                        error!("Failed to initialize EIS state: {}", err);
-                        return;
-                    }
-                }
+    evlh.insert_source(socket_rx, move |event, _, state| match event {
+        channel::Event::Msg(EisMessage::Socket(stream, target_output)) => {
+            // Initialize EIS state if needed, then add connection. Rejections
+            // are logged and counted in the statistics already
+            if let Some(eis_state) = ensure_eis_state(state, &state_shared) {
+                let _ = eis_state.add_connection(stream, target_output);
+                state.eis_update_activity();
             }
-            if let Some(eis_state) = &state.common.eis_state {
-                eis_state.add_connection(stream);
+        }
+        channel::Event::Msg(EisMessage::KeyboardGrab { client, enabled }) => {
+            state.eis_set_keyboard_grab(&client, enabled);
+        }
+        channel::Event::Msg(EisMessage::PointerNoFocus { client, enabled }) => {
+            state.eis_set_pointer_no_focus(&client, enabled);
+        }
+        channel::Event::Msg(EisMessage::WakeOnInput { client, enabled }) => {
+            state.eis_set_wake_on_input(&client, enabled);
+        }
+        channel::Event::Msg(EisMessage::PointerConfinement {
+            client,
+            confinement,
+        }) => {
+            state.eis_set_pointer_confinement(&client, confinement);
+        }
+        channel::Event::Msg(EisMessage::TargetOutput { client, output }) => {
+            state.eis_set_target_output(&client, output);
+        }
+        channel::Event::Msg(EisMessage::SetSelection(mime_types)) => {
+            state.eis_set_selection(mime_types);
+        }
+        channel::Event::Msg(EisMessage::ReadSelection { mime_type, fd }) => {
+            state.eis_read_selection(mime_type, fd);
+        }
+        channel::Event::Msg(EisMessage::InjectionPaused(paused)) => {
+            state.eis_injection_paused_changed(paused);
+        }
+        channel::Event::Msg(EisMessage::DryRun(enabled)) => {
+            state.eis_set_dry_run(enabled);
+        }
+        channel::Event::Msg(EisMessage::FocusWindow {
+            app_id,
+            click,
+            found,
+        }) => {
+            state.eis_focus_window(&app_id, click, found);
+        }
+        channel::Event::Msg(EisMessage::Click {
+            button,
+            position: [x, y],
+            done,
+        }) => {
+            state.eis_click(button, (x, y).into(), done);
+        }
+        channel::Event::Msg(EisMessage::TypeKeysym { keysym, typed }) => {
+            let _ = typed.try_send(state.eis_type_keysym(xkbcommon::xkb::Keysym::new(keysym)));
+        }
+        channel::Event::Msg(EisMessage::Replay(events)) => {
+            if ensure_eis_state(state, &state_shared).is_some() {
+                state.eis_replay(events);
             }
         }
+        channel::Event::Msg(EisMessage::GrantConsent(id)) => {
+            state.eis_grant_consent(id);
+        }
+        channel::Event::Closed => {}
     })
     .map_err(|e| anyhow::anyhow!("Failed to insert EIS socket channel: {}", e.error))?;
 
     // Spawn async D-Bus registration via the executor (same pattern as a11y)
     let sender = EisSocketSender::new(socket_tx);
     executor.spawn_ok(async move {
-        match register_dbus(sender).await {
+        match register_dbus(sender, shared).await {
             Ok(()) => info!("EIS D-Bus interface registered"),
             Err(err) => error!("Failed to register EIS D-Bus interface: {}", err),
         }
@@ -164,14 +1030,38 @@ pub fn init(
     Ok(())
 }
 
-async fn register_dbus(sender: EisSocketSender) -> anyhow::Result<()> {
+/// The EIS state of the compositor, initialized on first use.
+fn ensure_eis_state<'a>(
+    state: &'a mut crate::state::State,
+    shared: &Arc<EisShared>,
+) -> Option<&'a mut crate::input::eis::EisState> {
+    if state.common.eis_state.is_none() {
+        match crate::input::eis::EisState::new(
+            &state.common.event_loop_handle,
+            &state.common.config.cosmic_conf.eis_config,
+            shared.clone(),
+        ) {
+            Ok(eis_state) => {
+                state.common.eis_state = Some(eis_state);
+            }
+            Err(err) => {
+                error!("Failed to initialize EIS state: {}", err);
+                return None;
+            }
+        }
+    }
+    state.common.eis_state.as_mut()
+}
+
+async fn register_dbus(sender: EisSocketSender, shared: Arc<EisShared>) -> anyhow::Result<()> {
     let connection = zbus::Connection::session().await?;
-    let eis_interface = CosmicCompEis::new(sender);
+    let eis_interface = CosmicCompEis::new(sender, shared.clone());
 
     connection
         .object_server()
-        .at("/com/system76/CosmicComp", eis_interface)
+        .at(OBJECT_PATH, eis_interface)
         .await?;
+    let _ = shared.dbus.set(connection.clone());
 
     connection
         .request_name("com.system76.CosmicComp.RemoteDesktop")
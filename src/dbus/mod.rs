@@ -12,6 +12,7 @@ use tracing::{error, warn};
 use zbus::blocking::{Connection, fdo::DBusProxy};
 
 pub mod a11y_keyboard_monitor;
+#[cfg(feature = "eis")]
 pub mod eis;
 #[cfg(feature = "systemd")]
 pub mod logind;
@@ -25,6 +26,7 @@ pub fn init(
     let mut tokens = Vec::new();
 
     // Register EIS D-Bus interface for RemoteDesktop portal input injection
+    #[cfg(feature = "eis")]
     if let Err(err) = eis::init(evlh, executor) {
         tracing::info!(?err, "Failed to initialize EIS D-Bus interface");
     }
@@ -1,5 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
+#[cfg(feature = "eis")]
+use crate::input::eis::EisConnectionRequest;
 use crate::shell::element::stack::{
     CosmicStackInternal, DefaultDecorations as DefaultStackDecorations, Message as StackMessage,
 };
@@ -16,6 +18,8 @@ pub struct Hooks {
         Option<Arc<dyn Decorations<CosmicWindowInternal, WindowMessage> + Send + Sync>>,
     pub stack_decorations:
         Option<Arc<dyn Decorations<CosmicStackInternal, StackMessage> + Send + Sync>>,
+    #[cfg(feature = "eis")]
+    pub eis_connection_policy: Option<Arc<dyn EisConnectionPolicy + Send + Sync>>,
 }
 
 pub static HOOKS: OnceLock<Hooks> = OnceLock::new();
@@ -24,6 +28,14 @@ pub trait Decorations<Internal, Message>: std::fmt::Debug {
     fn view(&self, state: &Internal) -> cosmic::Element<'_, Message>;
 }
 
+/// Decides whether to accept a new EIS connection, e.g. by time of day or by
+/// asking an external policy daemon. Only consulted for connections within
+/// `max_connections`; without a policy all of those are accepted.
+#[cfg(feature = "eis")]
+pub trait EisConnectionPolicy: std::fmt::Debug {
+    fn accept(&self, request: &EisConnectionRequest<'_>) -> bool;
+}
+
 impl Decorations<CosmicWindowInternal, WindowMessage>
     for Option<Arc<dyn Decorations<CosmicWindowInternal, WindowMessage> + Send + Sync>>
 {
@@ -0,0 +1,1624 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Connections of EIS clients: accepting and setting them up, dispatching
+//! their requests and tearing them down.
+
+use calloop::timer::{TimeoutAction, Timer};
+use cosmic_comp_config::eis::{EisConfig, EisDeviceType, EisInputOrder, EisLockModifiers};
+use enumflags2::BitFlags;
+use reis::{calloop::EisRequestSourceEvent, eis, event::DeviceCapability, request::EisRequest};
+use smithay::{
+    backend::input::{ButtonState, KeyState},
+    input::{Seat, keyboard::ModifiersState},
+    output::Output,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::shell::Shell;
+use crate::state::State;
+use crate::utils::geometry::PointExt;
+use crate::utils::prelude::OutputExt;
+
+use super::{
+    BoundDevice, CAPSLOCK_SCANCODE, DEVICE_INTERFACES, EisConnection, EisConnectionRequest,
+    EisError, EisPeer, EisSession, EisState, FD_EXHAUSTION_ATTEMPTS, FD_EXHAUSTION_RETRY_DELAY,
+    INITIAL_SERIAL, MAX_CLIENT_NAME_CHARS, MAX_PROTOCOL_ERRORS, METRICS_TARGET, NUMLOCK_SCANCODE,
+    OutputRegion, RemoteInput, TOUCH_SLOTS_PER_CONNECTION,
+    grab::{release_keyboard_grab, set_keyboard_grab},
+    inject::{
+        InputInjector, SeatInjector, drop_input, inject_recorded, lend_pointer_seat, pointer_seat,
+        return_pointer_seat, warp_pointer, warp_pointer_to_output_center,
+    },
+    keysym::prepare_xkb_keymap_fd,
+    unix_millis,
+};
+
+impl EisState {
+    /// Accept a new EIS client connection from a UNIX socket fd, keeping its
+    /// pointer on the output named `target_output`, if any.
+    ///
+    /// Creates an `EisRequestSource` calloop event source that processes the
+    /// EIS protocol directly on the compositor's event loop. No background
+    /// threads are spawned. Rejected sockets are closed, every path owning
+    /// them dropping them. Rejections are logged and counted here already,
+    /// and a connection waiting to be retried for lack of file descriptors
+    /// counts as accepted until its last attempt.
+    pub fn add_connection(
+        &mut self,
+        socket: UnixStream,
+        target_output: Option<String>,
+    ) -> Result<(), EisError> {
+        self.try_add_connection(socket, target_output, 1)
+    }
+
+    /// `add_connection`, as its `attempt`th try.
+    fn try_add_connection(
+        &mut self,
+        socket: UnixStream,
+        target_output: Option<String>,
+        attempt: u32,
+    ) -> Result<(), EisError> {
+        let result = self.setup_connection(socket, target_output, attempt);
+        if let Err(err) = &result {
+            match err {
+                EisError::ConnectionLimit { .. } | EisError::PolicyDenied { .. } => {
+                    warn!("Rejecting EIS connection: {err}")
+                }
+                _ => error!("Rejecting EIS connection: {err}"),
+            }
+            let mut stats = self.stats();
+            stats.rejected_connections += 1;
+            if matches!(err, EisError::FdExhausted { .. }) {
+                stats.fd_exhausted_connections += 1;
+            }
+        }
+        result
+    }
+
+    /// Set up the connection of `socket`. Setting up a connection takes file
+    /// descriptors, so it is tried again after a delay while the compositor
+    /// is out of them.
+    fn setup_connection(
+        &mut self,
+        socket: UnixStream,
+        target_output: Option<String>,
+        attempt: u32,
+    ) -> Result<(), EisError> {
+        let current = self.stats().active_connections;
+        if current >= self.config.max_connections {
+            return Err(EisError::ConnectionLimit {
+                max: self.config.max_connections,
+            });
+        }
+        check_socket(&socket).map_err(EisError::InvalidSocket)?;
+        // A slow client must never block the event loop on a write
+        let writable = match socket
+            .set_nonblocking(true)
+            .and_then(|_| socket.try_clone())
+        {
+            Ok(writable) => writable,
+            Err(e) if fd_exhausted(&e) => {
+                return self.retry_add_connection(socket, target_output, attempt, e);
+            }
+            Err(e) => return Err(EisError::InvalidSocket(e)),
+        };
+        let peer = peer_credentials(&socket);
+        let application = peer.and_then(|peer| peer_application(peer.pid));
+        if let Some(policy) = crate::hooks::HOOKS
+            .get()
+            .and_then(|hooks| hooks.eis_connection_policy.as_ref())
+            && !policy.accept(&EisConnectionRequest {
+                peer,
+                application: application.as_deref(),
+                active_connections: current,
+            })
+        {
+            return Err(EisError::PolicyDenied { peer, application });
+        }
+        let context = match eis::Context::new(socket) {
+            Ok(ctx) => ctx,
+            // The failed context closed its socket, but the duplicate for
+            // flushing is another handle of the same one
+            Err(e) if fd_exhausted(&e) => {
+                return self.retry_add_connection(writable, target_output, attempt, e);
+            }
+            Err(e) => return Err(EisError::Context(e)),
+        };
+
+        let source = reis::calloop::EisRequestSource::new(context, INITIAL_SERIAL);
+
+        let id = self.next_connection_id;
+        self.next_connection_id += 1;
+        let touch_slot_base = free_touch_slot_base(self.used_touch_slot_bases());
+        self.connections.insert(
+            id,
+            EisConnection {
+                id,
+                socket: Some(writable),
+                touch_slot_base,
+                application,
+                last_activity: Arc::new(AtomicU64::new(unix_millis())),
+                target_output,
+                ..Default::default()
+            },
+        );
+
+        let token = match self
+            .evlh
+            .insert_source(source, move |event, connection, state| {
+                let event = match event {
+                    Ok(EisRequestSourceEvent::Request(request))
+                        if state.common.config.cosmic_conf.eis_config.remote_input_order
+                            == EisInputOrder::LocalFirst =>
+                    {
+                        if let Some(eis_state) = state.common.eis_state.as_mut() {
+                            eis_state.defer_request(id, request);
+                        }
+                        return Ok(calloop::PostAction::Continue);
+                    }
+                    event => event,
+                };
+                let Some(mut eis_connection) = state
+                    .common
+                    .eis_state
+                    .as_mut()
+                    .and_then(|eis_state| eis_state.connections.remove(&id))
+                else {
+                    return Ok(calloop::PostAction::Remove);
+                };
+
+                match event {
+                    Ok(EisRequestSourceEvent::Connected) => {
+                        handle_connected(state, &mut eis_connection, connection);
+                    }
+                    Ok(EisRequestSourceEvent::Request(request)) => {
+                        let arrival = Instant::now();
+                        handle_request(state, &mut eis_connection, connection, request, arrival);
+                    }
+                    Err(_)
+                        if eis_connection
+                            .socket
+                            .as_ref()
+                            .is_some_and(peer_hung_up) =>
+                    {
+                        info!(
+                            connection = id,
+                            name = %eis_connection.name,
+                            "EIS client hung up"
+                        );
+                        let time = state.common.clock.now().as_millis();
+                        end_session(state, &mut eis_connection, time);
+                        eis_connection.closed = true;
+                    }
+                    Err(e) => {
+                        let error = e.to_string();
+                        if let Some(suppressed) = eis_connection
+                            .protocol_errors
+                            .record(&error, Instant::now())
+                        {
+                            if suppressed > 0 {
+                                warn!(
+                                    connection = id,
+                                    "{suppressed} repeated EIS protocol errors suppressed"
+                                );
+                            }
+                            warn!(connection = id, "EIS protocol error: {error}");
+                        }
+                        // Never leave the local user locked out by a broken session
+                        release_keyboard_grab(state, id);
+
+                        if eis_connection.protocol_errors.limit_reached() {
+                            warn!(
+                                connection = id,
+                                name = %eis_connection.name,
+                                "Disconnecting EIS client after {MAX_PROTOCOL_ERRORS} protocol errors"
+                            );
+                            send_disconnect(
+                                &mut eis_connection,
+                                connection,
+                                TeardownReason::ProtocolErrors,
+                            );
+                            let time = state.common.clock.now().as_millis();
+                            end_session(state, &mut eis_connection, time);
+                            eis_connection.closed = true;
+                        }
+                    }
+                }
+
+                Ok(finish_dispatch(state, eis_connection, connection))
+            }) {
+            Ok(token) => token,
+            Err(e) => {
+                self.connections.remove(&id);
+                return Err(EisError::SourceInsertion(e.error));
+            }
+        };
+        if let Some(eis_connection) = self.connections.get_mut(&id) {
+            eis_connection.source = Some(token);
+        }
+        let mut stats = self.stats();
+        stats.active_connections += 1;
+        stats.total_connections += 1;
+        info!(
+            active = stats.active_connections,
+            "Accepting new EIS client connection"
+        );
+        Ok(())
+    }
+
+    /// Try adding the connection of `socket` again, after its `attempt`th try
+    /// failed with `error` for lack of file descriptors, or reject it for
+    /// good after the last attempt, closing `socket`.
+    pub(super) fn retry_add_connection(
+        &mut self,
+        socket: UnixStream,
+        target_output: Option<String>,
+        attempt: u32,
+        error: std::io::Error,
+    ) -> Result<(), EisError> {
+        if attempt >= FD_EXHAUSTION_ATTEMPTS {
+            return Err(EisError::FdExhausted {
+                attempts: attempt,
+                error,
+            });
+        }
+        warn!(
+            attempt,
+            "Out of file descriptors setting up EIS connection, retrying: {error}"
+        );
+        let mut socket = Some(socket);
+        self.evlh
+            .insert_source(
+                Timer::from_duration(FD_EXHAUSTION_RETRY_DELAY),
+                move |_, _, state| {
+                    if let Some(socket) = socket.take()
+                        && let Some(eis_state) = state.common.eis_state.as_mut()
+                    {
+                        // Rejections are logged and counted already
+                        let _ =
+                            eis_state.try_add_connection(socket, target_output.take(), attempt + 1);
+                    }
+                    TimeoutAction::Drop
+                },
+            )
+            .map_err(|e| EisError::SourceInsertion(e.error))?;
+        Ok(())
+    }
+
+    /// Run `f` on connection `id` taken out of the registry, with its protocol
+    /// connection, then put it back through `finish_dispatch`, removing its
+    /// event source if it closed meanwhile. `None` if there is no such
+    /// connection.
+    pub(super) fn with_connection<R>(
+        state: &mut State,
+        id: u64,
+        f: impl FnOnce(&mut State, &mut EisConnection, &mut reis::request::Connection) -> R,
+    ) -> Option<R> {
+        let (mut eis_connection, mut connection) =
+            state.common.eis_state.as_mut().and_then(|eis_state| {
+                let connection = eis_state.connections.get(&id)?.connection.clone()?;
+                Some((eis_state.connections.remove(&id)?, connection))
+            })?;
+        let result = f(state, &mut eis_connection, &mut connection);
+        let source = eis_connection.source;
+        if matches!(
+            finish_dispatch(state, eis_connection, &connection),
+            calloop::PostAction::Remove
+        ) && let Some(token) = source
+        {
+            state.common.event_loop_handle.remove(token);
+        }
+        Some(result)
+    }
+
+    /// Bases of the touch slot ranges of live and replayed connections.
+    pub(super) fn used_touch_slot_bases(&self) -> impl Iterator<Item = u32> + '_ {
+        self.connections
+            .values()
+            .map(|eis_connection| eis_connection.touch_slot_base)
+            .chain(self.replay_touch_slots.iter().copied())
+    }
+}
+
+/// Whether `active` connections crossed `percent` of the `max` connections
+/// while not yet `warned` about, updating `warned`. It is cleared once usage
+/// drops below the threshold, so crossing it again warns again.
+pub(super) fn connection_limit_crossed(
+    active: usize,
+    max: usize,
+    percent: u32,
+    warned: &mut bool,
+) -> bool {
+    if percent == 0 {
+        *warned = false;
+        return false;
+    }
+    let threshold = (max * percent as usize).div_ceil(100).max(1);
+    let above = active >= threshold;
+    let crossed = above && !*warned;
+    *warned = above;
+    crossed
+}
+
+/// Whether `error` is the process or the system running out of file
+/// descriptors, which passes once others are closed.
+pub(super) fn fd_exhausted(error: &std::io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::EMFILE | libc::ENFILE))
+}
+
+/// Lowest touch slot range not starting at any of `used`.
+pub(super) fn free_touch_slot_base(used: impl IntoIterator<Item = u32>) -> u32 {
+    let used = used.into_iter().collect::<BTreeSet<_>>();
+    let mut base = 0u32;
+    while used.contains(&base) {
+        // Running out takes more connections than there are file
+        // descriptors, but share the first range rather than wrap around
+        let Some(next) = base.checked_add(TOUCH_SLOTS_PER_CONNECTION) else {
+            return 0;
+        };
+        base = next;
+    }
+    base
+}
+
+/// Whether the client closed its end of `socket`, as opposed to sending
+/// something that could not be parsed.
+pub(super) fn peer_hung_up(socket: &UnixStream) -> bool {
+    match socket.peek(&mut [0; 1]) {
+        Ok(read) => read == 0,
+        Err(e) => matches!(
+            e.kind(),
+            std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::BrokenPipe
+        ),
+    }
+}
+
+/// Process `request` of `eis_connection`, which arrived at `arrival`, and
+/// record the latency of its injection if enabled. Input that was not
+/// injected, like dropped or queued input, is left out of the histogram.
+fn handle_request(
+    state: &mut State,
+    eis_connection: &mut EisConnection,
+    connection: &mut reis::request::Connection,
+    request: EisRequest,
+    arrival: Instant,
+) {
+    let is_input = request_capability(&request).is_some();
+    let injected = process_eis_request(state, eis_connection, connection, request);
+    if !is_input || !injected {
+        return;
+    }
+    if state
+        .common
+        .config
+        .cosmic_conf
+        .eis_config
+        .measure_injection_latency
+        && let Some(eis_state) = state.common.eis_state.as_ref()
+    {
+        eis_state
+            .shared
+            .latency
+            .lock()
+            .unwrap()
+            .record(arrival.elapsed());
+    }
+    trace!(
+        target: METRICS_TARGET,
+        histogram.eis_injection_latency_us = arrival.elapsed().as_micros() as u64
+    );
+}
+
+/// Wrap up processing an event of `eis_connection`: drop the connection if
+/// its session is over, else flush what was queued for the client and put
+/// the connection back into the registry.
+pub(super) fn finish_dispatch(
+    state: &mut State,
+    mut eis_connection: EisConnection,
+    connection: &reis::request::Connection,
+) -> calloop::PostAction {
+    // Dropping the source closes the socket
+    if eis_connection.closed {
+        if let Some(eis_state) = state.common.eis_state.as_ref() {
+            let mut stats = eis_state.stats();
+            stats.active_connections = stats.active_connections.saturating_sub(1);
+        }
+        state.eis_update_local_cursor();
+        state.eis_update_activity();
+        return calloop::PostAction::Remove;
+    }
+
+    if let Some(what) = eis_connection.unflushed.take() {
+        flush_connection(
+            &state.common.event_loop_handle,
+            &mut eis_connection,
+            connection,
+            what,
+        );
+    }
+    if let Some(eis_state) = state.common.eis_state.as_mut() {
+        eis_state
+            .connections
+            .insert(eis_connection.id, eis_connection);
+    }
+    state.eis_update_local_cursor();
+    state.eis_update_activity();
+    calloop::PostAction::Continue
+}
+
+/// Process the requests `EisState::defer_request` held back, in order.
+pub(super) fn process_deferred_requests(state: &mut State) {
+    let Some(eis_state) = state.common.eis_state.as_mut() else {
+        return;
+    };
+    let stale_after =
+        Duration::from_millis(state.common.config.cosmic_conf.eis_config.stale_input_ms);
+    for (id, request, arrival) in std::mem::take(&mut eis_state.deferred) {
+        // Motion and scrolling are superseded by what follows, so they are
+        // the input to shed when falling behind
+        if !stale_after.is_zero() && is_sheddable(&request) && arrival.elapsed() > stale_after {
+            if let Some(eis_state) = state.common.eis_state.as_ref() {
+                eis_state.count_dropped("stale");
+            }
+            continue;
+        }
+        // The connection may have ended since
+        EisState::with_connection(state, id, |state, eis_connection, connection| {
+            handle_request(state, eis_connection, connection, request, arrival);
+        });
+    }
+}
+
+/// Make sure a write to the socket of a client that went away fails with
+/// `EPIPE` instead of killing the compositor.
+///
+/// The Rust runtime ignores SIGPIPE for binaries, but that is not guaranteed
+/// for every embedder of this crate. A handler someone else installed is kept.
+pub(super) fn ignore_sigpipe() -> std::io::Result<()> {
+    unsafe {
+        let mut current: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(libc::SIGPIPE, std::ptr::null(), &mut current) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if current.sa_sigaction == libc::SIG_DFL {
+            let mut ignore: libc::sigaction = std::mem::zeroed();
+            ignore.sa_sigaction = libc::SIG_IGN;
+            if libc::sigaction(libc::SIGPIPE, &ignore, std::ptr::null_mut()) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Name of the type of `request`, for statistics. Names of handled requests
+/// are also listed in `HANDLED_REQUESTS`.
+fn request_name(request: &EisRequest) -> &'static str {
+    match request {
+        EisRequest::Disconnect => "disconnect",
+        EisRequest::Bind(_) => "bind",
+        EisRequest::Frame(_) => "frame",
+        EisRequest::DeviceStartEmulating(_) => "start_emulating",
+        EisRequest::DeviceStopEmulating(_) => "stop_emulating",
+        EisRequest::PointerMotion(_) => "pointer_motion",
+        EisRequest::PointerMotionAbsolute(_) => "pointer_motion_absolute",
+        EisRequest::Button(_) => "button",
+        EisRequest::ScrollDelta(_) => "scroll_delta",
+        EisRequest::ScrollStop(_) => "scroll_stop",
+        EisRequest::ScrollCancel(_) => "scroll_cancel",
+        EisRequest::ScrollDiscrete(_) => "scroll_discrete",
+        EisRequest::KeyboardKey(_) => "keyboard_key",
+        EisRequest::TouchDown(_) => "touch_down",
+        EisRequest::TouchMotion(_) => "touch_motion",
+        EisRequest::TouchUp(_) => "touch_up",
+        EisRequest::TouchCancel(_) => "touch_cancel",
+        _ => "other",
+    }
+}
+
+/// Name of the enum variant in the `Debug` output `debug` of a value.
+pub(super) fn variant_name(debug: &str) -> &str {
+    debug
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or(debug)
+}
+
+/// Log line summarizing `unhandled` requests, counted by kind.
+pub(super) fn summarize_unhandled(unhandled: &BTreeMap<String, u64>) -> String {
+    let total = unhandled.values().sum::<u64>();
+    let kinds = unhandled
+        .iter()
+        .map(|(kind, count)| format!("{kind} ({count})"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{total} unhandled EIS requests of kinds: {kinds}")
+}
+
+/// Capability `request` emulates input for, `None` for protocol requests.
+fn request_capability(request: &EisRequest) -> Option<DeviceCapability> {
+    Some(match request {
+        EisRequest::KeyboardKey(_) => DeviceCapability::Keyboard,
+        EisRequest::PointerMotion(_) => DeviceCapability::Pointer,
+        EisRequest::PointerMotionAbsolute(_) => DeviceCapability::PointerAbsolute,
+        EisRequest::Button(_) => DeviceCapability::Button,
+        EisRequest::ScrollDelta(_)
+        | EisRequest::ScrollDiscrete(_)
+        | EisRequest::ScrollStop(_)
+        | EisRequest::ScrollCancel(_) => DeviceCapability::Scroll,
+        EisRequest::TouchDown(_)
+        | EisRequest::TouchMotion(_)
+        | EisRequest::TouchUp(_)
+        | EisRequest::TouchCancel(_) => DeviceCapability::Touch,
+        _ => return None,
+    })
+}
+
+/// Names of `capabilities`, as their interface names without the `ei_` prefix.
+pub(super) fn capability_names(capabilities: BitFlags<DeviceCapability>) -> BTreeSet<&'static str> {
+    DEVICE_INTERFACES
+        .iter()
+        .filter(|(_, capability)| capabilities.contains(*capability))
+        .map(|(interface, _)| interface.trim_start_matches("ei_"))
+        .collect()
+}
+
+/// Whether `request` may be dropped when the compositor falls behind.
+fn is_sheddable(request: &EisRequest) -> bool {
+    matches!(
+        request,
+        EisRequest::PointerMotion(_)
+            | EisRequest::PointerMotionAbsolute(_)
+            | EisRequest::ScrollDelta(_)
+    )
+}
+
+/// Capabilities the config allows remote clients to use.
+pub(super) fn allowed_capabilities(config: &EisConfig) -> BitFlags<DeviceCapability> {
+    let mut capabilities = BitFlags::empty();
+    if config.allow_keyboard {
+        capabilities |= DeviceCapability::Keyboard;
+    }
+    if config.allow_pointer {
+        capabilities |= DeviceCapability::Button;
+        if config.allow_relative_pointer {
+            capabilities |= DeviceCapability::Pointer;
+        }
+        if config.allow_absolute_pointer {
+            capabilities |= DeviceCapability::PointerAbsolute;
+        }
+    }
+    if config.allow_scroll {
+        capabilities |= DeviceCapability::Scroll;
+    }
+    if config.allow_touch {
+        capabilities |= DeviceCapability::Touch;
+    }
+    capabilities
+}
+
+/// Capabilities `seat` can inject input of.
+fn supported_capabilities(seat: &Seat<State>) -> BitFlags<DeviceCapability> {
+    seat_capabilities(
+        seat.get_keyboard().is_some(),
+        seat.get_pointer().is_some(),
+        seat.get_touch().is_some(),
+    )
+}
+
+/// Capabilities of a seat with a keyboard, pointer and touchscreen as given.
+pub(super) fn seat_capabilities(
+    keyboard: bool,
+    pointer: bool,
+    touch: bool,
+) -> BitFlags<DeviceCapability> {
+    let mut capabilities = BitFlags::empty();
+    if keyboard {
+        capabilities |= DeviceCapability::Keyboard;
+    }
+    if pointer {
+        capabilities |= DeviceCapability::Pointer
+            | DeviceCapability::PointerAbsolute
+            | DeviceCapability::Button
+            | DeviceCapability::Scroll;
+    }
+    if touch {
+        capabilities |= DeviceCapability::Touch;
+    }
+    capabilities
+}
+
+/// Check that `socket` is a connected stream socket before handing it to reis.
+///
+/// A bad fd from the portal (closed, not a socket, a datagram socket or with
+/// a pending error) would otherwise fail confusingly inside reis, or leave an
+/// event source that never makes progress. The peer address is not looked at
+/// beyond its existence, so unnamed (socketpair), filesystem and
+/// abstract-namespace sockets, as some sandboxes hand out, are all fine.
+pub(super) fn check_socket(socket: &UnixStream) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let mut sock_type: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            std::ptr::addr_of_mut!(sock_type).cast(),
+            std::ptr::addr_of_mut!(len),
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if sock_type != libc::SOCK_STREAM {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "not a stream socket",
+        ));
+    }
+    // getpeername, fails with ENOTCONN for unconnected sockets
+    socket.peer_addr()?;
+    if let Some(e) = socket.take_error()? {
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Finish the EIS handshake of a new client by announcing our seat.
+fn handle_connected(
+    state: &mut State,
+    eis_connection: &mut EisConnection,
+    connection: &mut reis::request::Connection,
+) {
+    let client_name = truncate_client_name(connection.name().unwrap_or("<unknown>")).to_owned();
+    eis_connection.connection = Some(connection.clone());
+    eis_connection.name = match state.common.eis_state.as_ref() {
+        Some(eis_state) => unique_name(
+            &client_name,
+            eis_state
+                .connections
+                .values()
+                .filter(|c| c.session_key.is_some())
+                .map(|c| c.name.as_str()),
+        ),
+        None => client_name.clone(),
+    };
+    if eis_connection.name != client_name {
+        warn!(
+            connection = eis_connection.id,
+            client = %client_name,
+            name = %eis_connection.name,
+            "Another EIS client is connected under the same name"
+        );
+    }
+    info!(
+        connection = eis_connection.id,
+        name = %eis_connection.name,
+        application = eis_connection.application.as_deref().unwrap_or("<unknown>"),
+        "EIS client connected"
+    );
+    if let Some(eis_state) = state.common.eis_state.as_ref() {
+        eis_state.sessions().insert(
+            eis_connection.id,
+            EisSession {
+                id: eis_connection.id,
+                name: eis_connection.name.clone(),
+                application: eis_connection.application.clone(),
+                capabilities: BTreeSet::new(),
+                last_activity: eis_connection.last_activity.clone(),
+            },
+        );
+    }
+
+    // A client reconnecting shortly after dropping gets its previous pointer
+    // position back. The portal does not hand us a session token, so the
+    // client name is the key.
+    let window = Duration::from_secs(
+        state
+            .common
+            .config
+            .cosmic_conf
+            .eis_config
+            .pointer_restore_window_secs,
+    );
+    if !window.is_zero() {
+        eis_connection.restore_position = state
+            .common
+            .eis_state
+            .as_mut()
+            .and_then(|eis| eis.take_saved_pointer(&client_name, window));
+    }
+    eis_connection.session_key = Some(client_name);
+
+    // Only offer capabilities the client negotiated an interface for, the
+    // config allows and the seat input goes to can inject
+    let seat = state.common.shell.read().seats.last_active().clone();
+    for (interface, _) in DEVICE_INTERFACES {
+        if let Some(version) = connection.interface_version(interface) {
+            eis_connection.interface_versions.insert(interface, version);
+        }
+    }
+    let capabilities = eis_connection.negotiated_capabilities()
+        & allowed_capabilities(&state.common.config.cosmic_conf.eis_config)
+        & supported_capabilities(&seat);
+    info!(
+        connection = eis_connection.id,
+        versions = ?eis_connection.interface_versions,
+        capabilities = ?capability_names(capabilities),
+        "Negotiated EIS interface versions"
+    );
+
+    // Advertise the seat under the name clients also see on the wl_seat
+    eis_connection.seat = Some(connection.add_seat(Some(seat.name()), capabilities));
+    eis_connection.seat_capabilities = capabilities;
+    eis_connection.queue_flush("seat announcement");
+}
+
+/// `name` cut to its first `MAX_CLIENT_NAME_CHARS` characters, which never
+/// splits a multi-byte character. Names are truncated once, when the client
+/// connects, and the stored result is used from then on.
+pub(super) fn truncate_client_name(name: &str) -> &str {
+    match name.char_indices().nth(MAX_CLIENT_NAME_CHARS) {
+        Some((end, _)) => &name[..end],
+        None => name,
+    }
+}
+
+/// Credentials of the process that created the other end of `socket`.
+pub(super) fn peer_credentials(socket: &UnixStream) -> Option<EisPeer> {
+    use std::os::fd::AsRawFd;
+
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            std::ptr::addr_of_mut!(cred).cast(),
+            std::ptr::addr_of_mut!(len),
+        )
+    };
+    if ret != 0 || cred.pid <= 0 {
+        return None;
+    }
+    Some(EisPeer {
+        pid: cred.pid as u32,
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+/// Executable name of process `pid`.
+///
+/// The EIS handshake only carries a free-form client name, so this is the
+/// closest thing to an application id we get. For sockets brokered by the
+/// RemoteDesktop portal it names the portal, not the application behind it.
+pub(super) fn peer_application(pid: u32) -> Option<String> {
+    // The exe link is unreadable for processes of other users, comm is not
+    let mut name = std::fs::read_link(format!("/proc/{pid}/exe"))
+        .ok()
+        .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into_owned()))
+        .or_else(|| {
+            std::fs::read_to_string(format!("/proc/{pid}/comm"))
+                .ok()
+                .map(|comm| comm.trim_end().to_string())
+        })?;
+    name.truncate(truncate_client_name(&name).len());
+    (!name.is_empty()).then_some(name)
+}
+
+/// Make `client_name` unique among the names of live connections by
+/// appending ` #2`, ` #3`, ... on a collision.
+pub(super) fn unique_name<'a>(
+    client_name: &str,
+    taken: impl Iterator<Item = &'a str> + Clone,
+) -> String {
+    let mut name = client_name.to_string();
+    let mut suffix = 1;
+    while taken.clone().any(|taken| taken == name) {
+        suffix += 1;
+        name = format!("{client_name} #{suffix}");
+    }
+    name
+}
+
+/// Flush queued messages to the client without blocking.
+///
+/// If the socket is full, whatever could not be written stays buffered in the
+/// connection and a writability source on the socket finishes the flush once
+/// the client has caught up.
+pub(super) fn flush_connection(
+    evlh: &calloop::LoopHandle<'static, State>,
+    eis_connection: &mut EisConnection,
+    connection: &reis::request::Connection,
+    what: &str,
+) {
+    match connection.flush() {
+        Ok(()) => {}
+        Err(rustix::io::Errno::AGAIN) => {
+            if eis_connection.pending_flush.is_some() {
+                return;
+            }
+            let Some(socket) = eis_connection
+                .socket
+                .as_ref()
+                .and_then(|socket| socket.try_clone().ok())
+            else {
+                warn!("Failed to flush EIS {what}: socket unavailable");
+                return;
+            };
+            debug!(connection = eis_connection.id, "EIS {what} flush deferred");
+            let id = eis_connection.id;
+            let source = calloop::generic::Generic::new(
+                socket,
+                calloop::Interest::WRITE,
+                calloop::Mode::Level,
+            );
+            match evlh.insert_source(source, move |_, _, state| {
+                let Some(eis_connection) = state
+                    .common
+                    .eis_state
+                    .as_mut()
+                    .and_then(|eis_state| eis_state.connections.get_mut(&id))
+                else {
+                    return Ok(calloop::PostAction::Remove);
+                };
+                let result = eis_connection.connection.as_ref().map(|c| c.flush());
+                match result {
+                    Some(Err(rustix::io::Errno::AGAIN)) => Ok(calloop::PostAction::Continue),
+                    result => {
+                        if let Some(Err(e)) = result {
+                            warn!("Failed to flush EIS connection: {e}");
+                        }
+                        eis_connection.pending_flush = None;
+                        Ok(calloop::PostAction::Remove)
+                    }
+                }
+            }) {
+                Ok(token) => eis_connection.pending_flush = Some(token),
+                Err(e) => warn!("Failed to wait for EIS socket writability: {}", e.error),
+            }
+        }
+        // The read side sees the hangup as well and tears the connection down
+        Err(rustix::io::Errno::PIPE) => debug!(
+            connection = eis_connection.id,
+            "EIS client went away before its {what} was flushed"
+        ),
+        Err(e) => warn!("Failed to flush EIS {what}: {e}"),
+    }
+}
+
+/// Process a single EIS protocol request by injecting it into the compositor's
+/// Smithay input stack. Returns whether it was input that got injected, not
+/// dropped, queued until emulating or held back for consent.
+fn process_eis_request(
+    state: &mut State,
+    eis_connection: &mut EisConnection,
+    connection: &mut reis::request::Connection,
+    request: EisRequest,
+) -> bool {
+    let time = state.common.clock.now().as_millis();
+    eis_connection
+        .last_activity
+        .store(unix_millis(), Ordering::Relaxed);
+
+    if let Some(eis_state) = state.common.eis_state.as_ref() {
+        eis_state.count_request(request_name(&request));
+    }
+
+    let input = RemoteInput::from_request(&request);
+    if request_capability(&request).is_some()
+        && state.common.config.cosmic_conf.eis_config.require_consent
+        && !eis_connection.consent_granted
+    {
+        if let Some(eis_state) = state.common.eis_state.as_ref() {
+            eis_state.count_dropped("awaiting consent");
+        }
+        return false;
+    }
+    if let Some(capability) = request_capability(&request) {
+        let positioned = input.as_ref().is_some_and(RemoteInput::is_positioned);
+        if drop_input(state, capability, positioned) {
+            return false;
+        }
+    }
+
+    match request {
+        EisRequest::Disconnect => {
+            info!(
+                connection = eis_connection.id,
+                name = %eis_connection.name,
+                "EIS client disconnected"
+            );
+            end_session(state, eis_connection, time);
+            eis_connection.closed = true;
+        }
+        EisRequest::Bind(bind) => {
+            debug!(
+                "EIS client bound with capabilities: {:?}",
+                bind.capabilities
+            );
+            let seat = state.common.shell.read().seats.last_active().clone();
+            let allowed = allowed_capabilities(&state.common.config.cosmic_conf.eis_config);
+            let supported = supported_capabilities(&seat);
+            let capabilities = bind.capabilities & allowed & supported;
+            // Clients may bind more than the seat offered, or the config or
+            // seat changed since it was announced
+            let disallowed = bind.capabilities & !allowed;
+            let unsupported = bind.capabilities & allowed & !supported;
+            if !disallowed.is_empty() || !unsupported.is_empty() {
+                info!(
+                    connection = eis_connection.id,
+                    name = %eis_connection.name,
+                    disallowed = ?capability_names(disallowed),
+                    unsupported = ?capability_names(unsupported),
+                    "Stripped capabilities from EIS bind"
+                );
+            }
+            if capabilities.is_empty() {
+                info!(
+                    connection = eis_connection.id,
+                    name = %eis_connection.name,
+                    "Not creating EIS device, no bound capability is permitted"
+                );
+                return false;
+            }
+
+            // Prepare XKB keymap fd if keyboard capability is requested
+            let keymap_fd = if capabilities.contains(DeviceCapability::Keyboard) {
+                prepare_xkb_keymap_fd(&state.common.config.cosmic_conf.xkb_config)
+            } else {
+                None
+            };
+
+            eis_connection.regions = output_regions(
+                &state.common.shell.read(),
+                eis_connection.target_output.as_deref(),
+            );
+            let device = add_device(
+                &bind.seat,
+                state.common.config.cosmic_conf.eis_config.device_type,
+                capabilities,
+                keymap_fd.as_ref(),
+                &eis_connection.regions,
+            );
+            // Let the client know the current lock state, it cannot tell us
+            // about its own through the protocol
+            if capabilities.contains(DeviceCapability::Keyboard) {
+                let seat = state.common.shell.read().seats.last_active().clone();
+                if let Some(keyboard) = seat.get_keyboard() {
+                    send_modifiers(connection, &device, &keyboard.modifier_state());
+                }
+            }
+            eis_connection.devices.push(BoundDevice {
+                seat: bind.seat,
+                device,
+                capabilities,
+                emulating: false,
+            });
+            let bound = capability_names(
+                eis_connection
+                    .devices
+                    .iter()
+                    .map(|bound| bound.capabilities)
+                    .collect(),
+            );
+            info!(
+                connection = eis_connection.id,
+                name = %eis_connection.name,
+                capabilities = ?bound,
+                "EIS client bound devices"
+            );
+            if let Some(eis_state) = state.common.eis_state.as_ref()
+                && let Some(session) = eis_state.sessions().get_mut(&eis_connection.id)
+            {
+                session.capabilities = bound;
+            }
+            eis_connection.queue_flush("device announcement");
+        }
+        EisRequest::DeviceStartEmulating(evt) => {
+            eis_connection.set_emulating(&evt.device, true);
+            if !eis_connection.started_emulating {
+                if state.common.config.cosmic_conf.eis_config.require_consent
+                    && !eis_connection.consent_granted
+                {
+                    request_consent(state, eis_connection);
+                } else {
+                    start_emulating(state, eis_connection, connection, time);
+                }
+            }
+        }
+        EisRequest::DeviceStopEmulating(evt) => {
+            eis_connection.set_emulating(&evt.device, false);
+        }
+        request => match input {
+            // Fast clients may send input before they start emulating, which
+            // sets up the seat it goes to
+            Some(input) if !eis_connection.started_emulating => {
+                if !eis_connection.queue_early_input(input)
+                    && let Some(eis_state) = state.common.eis_state.as_ref()
+                {
+                    debug!(
+                        connection = eis_connection.id,
+                        "Dropping EIS input sent long before emulating"
+                    );
+                    eis_state.count_dropped("before emulating");
+                }
+            }
+            Some(input) => return inject_recorded(state, eis_connection, input, time),
+            None => {
+                debug!("Unhandled EIS request: {:?}", request);
+                if let Some(eis_state) = state.common.eis_state.as_mut() {
+                    eis_state.count_unhandled(&request);
+                }
+            }
+        },
+    }
+    false
+}
+
+/// Set up the session of `eis_connection` once one of its devices first
+/// starts emulating, and its input is let through.
+fn start_emulating(
+    state: &mut State,
+    eis_connection: &mut EisConnection,
+    connection: &reis::request::Connection,
+    time: u32,
+) {
+    eis_connection.started_emulating = true;
+    let lent = state
+        .common
+        .config
+        .cosmic_conf
+        .eis_config
+        .pointer_per_session
+        && eis_connection.has_pointer()
+        && lend_pointer_seat(state, eis_connection);
+    let seat = pointer_seat(state, eis_connection);
+    if let Some(position) = eis_connection.restore_position.take() {
+        debug!(
+            ?position,
+            "Restoring EIS pointer position from previous session"
+        );
+        warp_pointer(state, &seat, position, time);
+    } else if lent
+        || state
+            .common
+            .config
+            .cosmic_conf
+            .eis_config
+            .warp_pointer_on_start
+    {
+        // A pointer of its own starts out wherever it was left
+        warp_pointer_to_output_center(state, &seat, time);
+    }
+
+    if let Some(locks) = state
+        .common
+        .config
+        .cosmic_conf
+        .eis_config
+        .lock_modifiers_on_start
+        && eis_connection.has_keyboard()
+        && let Some(modifiers) = apply_lock_modifiers(state, locks)
+    {
+        for bound in &eis_connection.devices {
+            send_modifiers(connection, &bound.device, &modifiers);
+        }
+        eis_connection.queue_flush("modifier state");
+    }
+
+    if state.common.config.cosmic_conf.eis_config.keyboard_grab && eis_connection.has_keyboard() {
+        set_keyboard_grab(state, eis_connection.id);
+    }
+
+    let early_input = std::mem::take(&mut eis_connection.early_input);
+    if !early_input.is_empty() {
+        debug!(
+            connection = eis_connection.id,
+            events = early_input.len(),
+            "Injecting EIS input sent before emulating"
+        );
+    }
+    for input in early_input {
+        inject_recorded(state, eis_connection, input, time);
+    }
+}
+
+/// Ask the local user to approve the input of `eis_connection` through the
+/// `ConsentRequested` D-Bus signal, denying it once `consent_timeout_secs`
+/// passed without an answer.
+fn request_consent(state: &mut State, eis_connection: &mut EisConnection) {
+    if eis_connection.consent_timer.is_some() {
+        return;
+    }
+    let Some(eis_state) = state.common.eis_state.as_ref() else {
+        return;
+    };
+    let id = eis_connection.id;
+    let timeout = Duration::from_secs(
+        state
+            .common
+            .config
+            .cosmic_conf
+            .eis_config
+            .consent_timeout_secs,
+    );
+    match eis_state
+        .evlh
+        .insert_source(Timer::from_duration(timeout), move |_, _, state| {
+            deny_consent(state, id);
+            TimeoutAction::Drop
+        }) {
+        Ok(token) => eis_connection.consent_timer = Some(token),
+        Err(e) => {
+            error!("Failed to schedule EIS consent timeout: {}", e.error);
+            return;
+        }
+    }
+    info!(
+        connection = id,
+        name = %eis_connection.name,
+        "Requesting consent for EIS client input"
+    );
+    crate::dbus::eis::consent_requested(
+        &state.common.async_executor,
+        &eis_state.shared,
+        id,
+        eis_connection.name.clone(),
+        eis_connection.application.clone().unwrap_or_default(),
+    );
+}
+
+/// Disconnect connection `id` whose consent request timed out.
+fn deny_consent(state: &mut State, id: u64) {
+    EisState::with_connection(state, id, |state, eis_connection, connection| {
+        eis_connection.consent_timer = None;
+        // Consent may have been turned off in the meantime
+        if !state.common.config.cosmic_conf.eis_config.require_consent {
+            return;
+        }
+        warn!(
+            connection = id,
+            name = %eis_connection.name,
+            "Disconnecting EIS client, its input was not approved in time"
+        );
+        send_disconnect(eis_connection, connection, TeardownReason::ConsentTimeout);
+        let time = state.common.clock.now().as_millis();
+        end_session(state, eis_connection, time);
+        // The source is removed once closed, which closes the socket
+        eis_connection.closed = true;
+    });
+}
+
+/// Wind down the session of a disconnecting client: release what it holds
+/// and save its pointer position for a reconnect.
+fn end_session(state: &mut State, eis_connection: &mut EisConnection, time: u32) {
+    if let Some(eis_state) = state.common.eis_state.as_ref() {
+        eis_state.sessions().remove(&eis_connection.id);
+    }
+    release_held_input(
+        &mut SeatInjector::for_connection(state, eis_connection),
+        eis_connection,
+        time,
+    );
+    release_keyboard_grab(state, eis_connection.id);
+    if let Some(token) = eis_connection.pending_flush.take() {
+        state.common.event_loop_handle.remove(token);
+    }
+    if let Some(token) = eis_connection.consent_timer.take() {
+        state.common.event_loop_handle.remove(token);
+    }
+    if let Some(token) = eis_connection.held_motion_timer.take() {
+        state.common.event_loop_handle.remove(token);
+    }
+    for bound in &mut eis_connection.devices {
+        bound.emulating = false;
+    }
+    if let Some(key) = eis_connection.session_key.take() {
+        let seat = pointer_seat(state, eis_connection);
+        if let (Some(pointer), Some(eis_state)) =
+            (seat.get_pointer(), state.common.eis_state.as_mut())
+        {
+            eis_state.save_pointer(key, pointer.current_location().as_global());
+        }
+    }
+    return_pointer_seat(state, eis_connection, time);
+}
+
+/// Why the compositor ends a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TeardownReason {
+    /// The client kept sending invalid requests.
+    ProtocolErrors,
+    /// The local user did not approve remote control in time.
+    ConsentTimeout,
+    /// The compositor is shutting down.
+    Shutdown,
+}
+
+impl TeardownReason {
+    /// Reason and explanation of the EIS disconnect event, which clients can
+    /// show the user rather than a generic disconnect.
+    pub(super) fn disconnect_reason(self) -> (eis::connection::DisconnectReason, &'static str) {
+        use eis::connection::DisconnectReason;
+        match self {
+            Self::ProtocolErrors => (DisconnectReason::Protocol, "too many protocol errors"),
+            Self::ConsentTimeout => (
+                DisconnectReason::Disconnected,
+                "remote control was not approved",
+            ),
+            Self::Shutdown => (DisconnectReason::Disconnected, "compositor shutting down"),
+        }
+    }
+}
+
+/// Remove the devices of `eis_connection` and tell the client why it is
+/// disconnected, flushed out before its socket is closed.
+fn send_disconnect(
+    eis_connection: &mut EisConnection,
+    connection: &reis::request::Connection,
+    reason: TeardownReason,
+) {
+    remove_devices(eis_connection);
+    let (reason, explanation) = reason.disconnect_reason();
+    connection.disconnected(reason, Some(explanation));
+    if let Err(e) = connection.flush() {
+        debug!("Failed to flush EIS disconnect: {e}");
+    }
+}
+
+/// Tell the client its devices and seat are gone, ahead of disconnecting it,
+/// so it can tear down its side in order.
+fn remove_devices(eis_connection: &mut EisConnection) {
+    for bound in eis_connection.devices.drain(..) {
+        bound.device.remove();
+    }
+    if let Some(seat) = eis_connection.seat.take() {
+        seat.remove();
+    }
+}
+
+/// Undo the input `eis_connection` still holds on the seat.
+pub(super) fn release_held_input(
+    injector: &mut impl InputInjector,
+    eis_connection: &mut EisConnection,
+    time: u32,
+) {
+    // Scrolling of an unfinished frame is not held, it just never happened,
+    // nor is motion held back for smoothing
+    eis_connection.pending_scroll = None;
+    eis_connection.held_motion = (0.0, 0.0);
+
+    // Release exactly the keys this client still holds, so neither its
+    // modifiers get stuck nor local ones are cleared
+    for key in eis_connection.held_keys.release_all() {
+        let serial = eis_connection.next_serial();
+        injector.key(eis_connection.id, key, KeyState::Released, serial, time);
+    }
+
+    let buttons = std::mem::take(&mut eis_connection.held_buttons);
+    if !buttons.is_empty() && injector.has_capability("pointer") {
+        for button in buttons {
+            let serial = eis_connection.next_serial();
+            injector.button(button, ButtonState::Released, serial, time);
+        }
+        injector.pointer_frame();
+    }
+    let touches = std::mem::take(&mut eis_connection.active_touches);
+    let frame_pending = std::mem::take(&mut eis_connection.touch_frame_pending);
+    if (frame_pending || !touches.is_empty()) && injector.has_capability("touch") {
+        for slot in touches
+            .into_iter()
+            .filter_map(|touch_id| eis_connection.touch_slot(touch_id))
+        {
+            let serial = eis_connection.next_serial();
+            injector.touch_up(slot, serial, time);
+        }
+        injector.touch_frame();
+    }
+}
+
+/// Create and resume a device of `device_type` on `seat` with the given
+/// capabilities.
+///
+/// Keyboard devices get the compositor's keymap, and devices with absolute
+/// capabilities get one region per output so clients can map their
+/// coordinates onto the layout.
+pub(super) fn add_device(
+    seat: &reis::request::Seat,
+    device_type: EisDeviceType,
+    capabilities: BitFlags<DeviceCapability>,
+    keymap: Option<&(std::os::fd::OwnedFd, u32)>,
+    regions: &[OutputRegion],
+) -> reis::request::Device {
+    let device_type = match device_type {
+        EisDeviceType::Virtual => eis::device::DeviceType::Virtual,
+        EisDeviceType::Physical => eis::device::DeviceType::Physical,
+    };
+    let device = seat.add_device(Some("remote-input"), device_type, capabilities, |device| {
+        // Send compositor's XKB keymap to keyboard before device.done()
+        if let Some((fd, size)) = keymap
+            && let Some(keyboard) = device.interface::<eis::Keyboard>()
+        {
+            keyboard.keymap(eis::keyboard::KeymapType::Xkb, *size, fd.as_fd());
+        }
+        if capabilities.intersects(DeviceCapability::PointerAbsolute | DeviceCapability::Touch) {
+            for region in regions {
+                let geo = region.geometry;
+                // EIS regions can't express negative offsets
+                if let (Ok(x), Ok(y), Ok(w), Ok(h)) = (
+                    u32::try_from(geo.loc.x),
+                    u32::try_from(geo.loc.y),
+                    u32::try_from(geo.size.w),
+                    u32::try_from(geo.size.h),
+                ) {
+                    device.device().region(x, y, w, h, region.scale as f32);
+                }
+            }
+        }
+    });
+    device.resumed();
+    device
+}
+
+/// Report `modifiers`, including the lock state, to `device` if it is a
+/// keyboard.
+///
+/// Modifier state only flows this way: `ei_keyboard.modifiers` is an event,
+/// and the protocol has no request for a client to assert a modifier mask.
+/// Clients sync theirs by pressing and releasing the modifier keys, which
+/// `release_held_input` lets go of when they disconnect.
+fn send_modifiers(
+    connection: &reis::request::Connection,
+    device: &reis::request::Device,
+    modifiers: &ModifiersState,
+) {
+    if let Some(keyboard) = device.interface::<eis::Keyboard>() {
+        let mods = modifiers.serialized;
+        connection.with_next_serial(|serial| {
+            keyboard.modifiers(
+                serial,
+                mods.depressed,
+                mods.locked,
+                mods.latched,
+                mods.layout_effective,
+            )
+        });
+    }
+}
+
+/// Toggle NumLock and CapsLock of the active seat to match `locks`,
+/// returning the resulting modifier state.
+fn apply_lock_modifiers(state: &mut State, locks: EisLockModifiers) -> Option<ModifiersState> {
+    let seat = state.common.shell.read().seats.last_active().clone();
+    let keyboard = seat.get_keyboard()?;
+    let current = keyboard.modifier_state();
+    if current.num_lock != locks.num_lock {
+        crate::config::change_modifier_state(&keyboard, NUMLOCK_SCANCODE, state);
+    }
+    if current.caps_lock != locks.caps_lock {
+        crate::config::change_modifier_state(&keyboard, CAPSLOCK_SCANCODE, state);
+    }
+    debug!(?locks, "Applied EIS lock modifiers");
+    Some(keyboard.modifier_state())
+}
+
+/// Outputs the pointer of a session with `target_output` may move on: that
+/// one while it exists, else all of them.
+pub(super) fn session_outputs<'a>(
+    shell: &'a Shell,
+    target_output: Option<&str>,
+) -> Vec<&'a Output> {
+    let outputs = shell.outputs().collect::<Vec<_>>();
+    match target_output {
+        Some(name) if outputs.iter().any(|output| output.name() == name) => outputs
+            .into_iter()
+            .filter(|output| output.name() == name)
+            .collect(),
+        _ => outputs,
+    }
+}
+
+/// Current output layout in global logical coordinates, as seen by a session
+/// with `target_output`.
+pub(super) fn output_regions(shell: &Shell, target_output: Option<&str>) -> Vec<OutputRegion> {
+    session_outputs(shell, target_output)
+        .into_iter()
+        .map(|output| OutputRegion {
+            geometry: output.geometry(),
+            scale: output.current_scale().fractional_scale(),
+        })
+        .collect()
+}
+
+impl State {
+    /// Disconnect all EIS clients before the compositor exits, releasing the
+    /// input they hold so nothing stays stuck.
+    pub fn eis_shutdown(&mut self) {
+        let Some(eis_state) = self.common.eis_state.as_mut() else {
+            return;
+        };
+        let connections = std::mem::take(&mut eis_state.connections);
+        eis_state.deferred.clear();
+        eis_state.stats().active_connections = 0;
+        eis_state.sessions().clear();
+        if let Some(token) = eis_state.remote_cursor_timer.take() {
+            self.common.event_loop_handle.remove(token);
+        }
+        if let Some(token) = eis_state.unhandled_timer.take() {
+            self.common.event_loop_handle.remove(token);
+        }
+        eis_state.shared.selection_transfers.lock().unwrap().clear();
+
+        let time = self.common.clock.now().as_millis();
+        for (_, mut eis_connection) in connections {
+            release_held_input(
+                &mut SeatInjector::for_connection(self, &eis_connection),
+                &mut eis_connection,
+                time,
+            );
+            release_keyboard_grab(self, eis_connection.id);
+            if let Some(token) = eis_connection.pending_flush.take() {
+                self.common.event_loop_handle.remove(token);
+            }
+            if let Some(token) = eis_connection.consent_timer.take() {
+                self.common.event_loop_handle.remove(token);
+            }
+            if let Some(token) = eis_connection.held_motion_timer.take() {
+                self.common.event_loop_handle.remove(token);
+            }
+            if let Some(connection) = eis_connection.connection.clone()
+                && eis_connection.session_key.is_some()
+            {
+                send_disconnect(&mut eis_connection, &connection, TeardownReason::Shutdown);
+            }
+            // Dropping the source closes the socket
+            if let Some(token) = eis_connection.source.take() {
+                self.common.event_loop_handle.remove(token);
+            }
+        }
+        info!("Disconnected all EIS clients");
+    }
+
+    /// Let input of connection `id` through once the local user approved it,
+    /// as requested over D-Bus.
+    pub fn eis_grant_consent(&mut self, id: u64) {
+        let granted = EisState::with_connection(self, id, |state, eis_connection, connection| {
+            if let Some(token) = eis_connection.consent_timer.take() {
+                state.common.event_loop_handle.remove(token);
+            }
+            eis_connection.consent_granted = true;
+            info!(
+                connection = id,
+                name = %eis_connection.name,
+                "Granted consent for EIS client input"
+            );
+            // The client started emulating while waiting, finish what it missed
+            if !eis_connection.started_emulating
+                && eis_connection.devices.iter().any(|bound| bound.emulating)
+            {
+                let time = state.common.clock.now().as_millis();
+                start_emulating(state, eis_connection, connection, time);
+            }
+        });
+        if granted.is_none() {
+            warn!(connection = id, "No EIS connection to grant consent to");
+        }
+    }
+
+    /// Publish whether any device of a connected client is emulating input,
+    /// and how many clients are connected, for the D-Bus properties status
+    /// indicators watch. They are only signaled when they change.
+    pub fn eis_update_activity(&mut self) {
+        let Some(eis_state) = self.common.eis_state.as_mut() else {
+            return;
+        };
+        let active = eis_state
+            .connections
+            .values()
+            .any(|eis_connection| eis_connection.devices.iter().any(|bound| bound.emulating));
+        let sessions = eis_state.connections.len() as u32;
+        let (max, percent) = (
+            eis_state.config.max_connections,
+            eis_state.config.connection_warning_percent,
+        );
+        if connection_limit_crossed(
+            sessions as usize,
+            max,
+            percent,
+            &mut eis_state.connection_limit_warned,
+        ) {
+            warn!(
+                active = sessions,
+                max, "EIS connections approaching the limit"
+            );
+            crate::dbus::eis::connection_limit_approaching(
+                &self.common.async_executor,
+                &eis_state.shared,
+                sessions,
+                max as u32,
+            );
+        }
+        let shared = &eis_state.shared;
+        let active_changed = shared.remote_input_active.swap(active, Ordering::AcqRel) != active;
+        let sessions_changed = shared.active_sessions.swap(sessions, Ordering::AcqRel) != sessions;
+        if active_changed || sessions_changed {
+            debug!(active, sessions, "EIS remote input activity changed");
+            crate::dbus::eis::activity_changed(
+                &self.common.async_executor,
+                shared,
+                active_changed,
+                sessions_changed,
+            );
+        }
+    }
+
+    /// Announce the seat again to clients whose seat capabilities changed,
+    /// like after a local device was plugged in or `eis_config` changed
+    /// which capabilities are allowed.
+    ///
+    /// The capabilities of an EIS seat are fixed, so its devices and the seat
+    /// itself are removed, releasing the input the client held on them, and
+    /// a seat with the new capabilities is added for the client to bind.
+    pub fn eis_seat_capabilities_changed(&mut self) {
+        let Some(eis_state) = self.common.eis_state.as_ref() else {
+            return;
+        };
+        let ids = eis_state
+            .connections
+            .iter()
+            .filter(|(_, eis_connection)| eis_connection.seat.is_some())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        let seat = self.common.shell.read().seats.last_active().clone();
+        let allowed = allowed_capabilities(&self.common.config.cosmic_conf.eis_config)
+            & supported_capabilities(&seat);
+
+        for id in ids {
+            EisState::with_connection(self, id, |state, eis_connection, connection| {
+                let capabilities = eis_connection.negotiated_capabilities() & allowed;
+                let previous = eis_connection.seat_capabilities;
+                if capabilities == previous {
+                    return;
+                }
+                info!(
+                    connection = id,
+                    name = %eis_connection.name,
+                    added = ?capability_names(capabilities & !previous),
+                    removed = ?capability_names(previous & !capabilities),
+                    "Re-announcing EIS seat with changed capabilities"
+                );
+
+                let time = state.common.clock.now().as_millis();
+                release_held_input(
+                    &mut SeatInjector::for_connection(state, eis_connection),
+                    eis_connection,
+                    time,
+                );
+                remove_devices(eis_connection);
+                if let Some(eis_state) = state.common.eis_state.as_ref()
+                    && let Some(session) = eis_state.sessions().get_mut(&id)
+                {
+                    session.capabilities.clear();
+                }
+                eis_connection.seat = Some(connection.add_seat(Some(seat.name()), capabilities));
+                eis_connection.seat_capabilities = capabilities;
+                eis_connection.queue_flush("seat re-announcement");
+            });
+        }
+        self.eis_update_local_cursor();
+        self.eis_update_activity();
+    }
+}
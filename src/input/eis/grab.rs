@@ -0,0 +1,209 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Exclusive keyboard grab of an EIS connection.
+
+use smithay::{
+    backend::input::KeyState,
+    input::{
+        SeatHandler,
+        keyboard::{
+            GrabStartData as KeyboardGrabStartData, KeyboardGrab, KeyboardInnerHandle, Keycode,
+            ModifiersState,
+        },
+    },
+    utils::{SERIAL_COUNTER, Serial},
+};
+use tracing::{info, warn};
+
+use crate::state::State;
+
+use super::EisState;
+
+impl EisState {
+    /// Record `holder` as the connection with the keyboard grab.
+    fn set_keyboard_grab_holder(&mut self, holder: Option<u64>) {
+        self.keyboard_grab = holder;
+        self.stats().keyboard_grab = holder;
+    }
+}
+
+/// Keyboard grab giving an EIS connection exclusive control of the keyboard.
+///
+/// Only keys injected by the owning connection reach the focused client.
+/// Local key presses are intercepted earlier, in `filter_keyboard_input`
+/// (which still lets them through to the lock screen), and presses of other
+/// connections are dropped here. Releases always pass, so keys held down when
+/// the grab started don't get stuck.
+pub struct EisKeyboardGrab {
+    connection: u64,
+    start_data: KeyboardGrabStartData<State>,
+}
+
+impl KeyboardGrab<State> for EisKeyboardGrab {
+    fn input(
+        &mut self,
+        data: &mut State,
+        handle: &mut KeyboardInnerHandle<'_, State>,
+        keycode: Keycode,
+        state: KeyState,
+        modifiers: Option<ModifiersState>,
+        serial: Serial,
+        time: u32,
+    ) {
+        let (held, injecting) = data
+            .common
+            .eis_state
+            .as_ref()
+            .map(|eis_state| {
+                (
+                    eis_state.keyboard_grab == Some(self.connection),
+                    eis_state.injecting_keys,
+                )
+            })
+            .unwrap_or_default();
+
+        if !held {
+            // The connection went away without releasing the grab
+            handle.unset_grab(self, data, serial, true);
+            handle.input(data, keycode, state, modifiers, serial, time);
+            return;
+        }
+
+        let other_connection = injecting.is_some_and(|id| id != self.connection);
+        if !other_connection || state == KeyState::Released {
+            handle.input(data, keycode, state, modifiers, serial, time);
+        }
+    }
+
+    fn set_focus(
+        &mut self,
+        data: &mut State,
+        handle: &mut KeyboardInnerHandle<'_, State>,
+        focus: Option<<State as SeatHandler>::KeyboardFocus>,
+        serial: Serial,
+    ) {
+        handle.set_focus(data, focus, serial)
+    }
+
+    fn start_data(&self) -> &KeyboardGrabStartData<State> {
+        &self.start_data
+    }
+
+    fn unset(&mut self, data: &mut State) {
+        if let Some(eis_state) = data.common.eis_state.as_mut()
+            && eis_state.keyboard_grab == Some(self.connection)
+        {
+            eis_state.set_keyboard_grab_holder(None);
+        }
+    }
+}
+
+/// Give connection `id` the exclusive keyboard grab on the active seat.
+///
+/// Only one connection can hold the grab at a time.
+pub(super) fn set_keyboard_grab(state: &mut State, id: u64) {
+    let seat = state.common.shell.read().seats.last_active().clone();
+    let Some(keyboard) = seat.get_keyboard() else {
+        return;
+    };
+    let Some(eis_state) = state.common.eis_state.as_mut() else {
+        return;
+    };
+    match eis_state.keyboard_grab {
+        Some(holder) if holder == id => return,
+        Some(holder) => {
+            warn!(
+                connection = id,
+                holder, "EIS keyboard grab is already held by another connection"
+            );
+            return;
+        }
+        None => {}
+    }
+    eis_state.set_keyboard_grab_holder(Some(id));
+
+    let grab = EisKeyboardGrab {
+        connection: id,
+        start_data: KeyboardGrabStartData {
+            focus: keyboard.current_focus(),
+        },
+    };
+    keyboard.set_grab(state, grab, SERIAL_COUNTER.next_serial());
+    info!(
+        connection = id,
+        "EIS session took an exclusive keyboard grab"
+    );
+}
+
+/// Release the keyboard grab of connection `id`, if it holds it.
+pub(super) fn release_keyboard_grab(state: &mut State, id: u64) {
+    let Some(eis_state) = state.common.eis_state.as_mut() else {
+        return;
+    };
+    if eis_state.keyboard_grab != Some(id) {
+        return;
+    }
+    eis_state.set_keyboard_grab_holder(None);
+
+    let seats = state
+        .common
+        .shell
+        .read()
+        .seats
+        .iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    for seat in seats {
+        if let Some(keyboard) = seat.get_keyboard()
+            && keyboard.with_grab(|_, grab| grab.is::<EisKeyboardGrab>()) == Some(true)
+        {
+            keyboard.unset_grab(state);
+        }
+    }
+    info!(connection = id, "Released EIS keyboard grab");
+}
+
+impl State {
+    /// Toggle the exclusive keyboard grab for the connection named `client`,
+    /// or else the most recent connection of that client name, as requested
+    /// over D-Bus.
+    pub fn eis_set_keyboard_grab(&mut self, client: &str, enabled: bool) {
+        let Some(id) = self
+            .common
+            .eis_state
+            .as_ref()
+            .and_then(|eis_state| eis_state.connection_by_name(client))
+        else {
+            warn!(client, "No EIS connection to toggle the keyboard grab for");
+            return;
+        };
+
+        if enabled {
+            set_keyboard_grab(self, id);
+        } else {
+            release_keyboard_grab(self, id);
+        }
+    }
+
+    /// Apply a changed `keyboard_grab` setting to the live connections.
+    pub fn eis_keyboard_grab_changed(&mut self) {
+        let Some(eis_state) = self.common.eis_state.as_ref() else {
+            return;
+        };
+
+        if self.common.config.cosmic_conf.eis_config.keyboard_grab {
+            if eis_state.keyboard_grab.is_none()
+                && let Some(id) = eis_state
+                    .connections
+                    .values()
+                    .filter(|c| c.started_emulating && c.has_keyboard())
+                    .map(|c| c.id)
+                    .min()
+            {
+                set_keyboard_grab(self, id);
+            }
+        } else if let Some(id) = eis_state.keyboard_grab {
+            release_keyboard_grab(self, id);
+        }
+    }
+}
@@ -0,0 +1,2053 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Injection of remote input into the seats of the compositor.
+
+use calloop::timer::{TimeoutAction, Timer};
+use cosmic_comp_config::eis::{
+    EisConfig, EisKeyRepeat, EisPointerAcceleration, EisPointerEdge, EisRemoteShortcuts,
+};
+use cosmic_settings_config::shortcuts;
+use reis::event::DeviceCapability;
+use smithay::{
+    backend::input::{ButtonState, KeyState, TouchSlot},
+    input::{
+        Seat,
+        keyboard::{FilterResult, Keycode, ModifiersState},
+        pointer::RelativeMotionEvent,
+        touch::{DownEvent, MotionEvent as TouchMotionEvent, UpEvent},
+    },
+    output::Output,
+    utils::{Point, Rectangle, SERIAL_COUNTER, Serial},
+};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, trace, warn};
+use xkbcommon::xkb::Keysym;
+
+use crate::backend::render::cursor::CursorState;
+use crate::config::{Action, key_bindings::cosmic_modifiers_eq_smithay};
+use crate::shell::{SeatExt, focus::target::PointerFocusTarget};
+use crate::state::State;
+use crate::utils::geometry::{Global, PointExt, PointGlobalExt};
+use crate::utils::prelude::OutputExt;
+use crate::wayland::protocols::toplevel_management::ToplevelManagementHandler;
+
+use super::{
+    BTN_LEFT, BTN_RIGHT, CLICK_HOLD, EisConnection, EisPointerConfinement, EisState,
+    MAX_FRAME_TIME_AGE_MS, MAX_SESSION_POINTERS, METRICS_TARGET, MOTION_SMOOTHING_WINDOW,
+    MOUSE_BUTTONS, POINTER_ACCEL_MAX, POINTER_ACCEL_SLOPE, POINTER_ACCEL_THRESHOLD, RemoteInput,
+    SCROLL_ACCEL_MAX, SCROLL_ACCEL_WINDOW_MS, ScrollFrame, WHEEL_CLICK_DISTANCE,
+    connection::{allowed_capabilities, release_held_input, session_outputs},
+    grab::release_keyboard_grab,
+};
+
+/// Whether remote input is to be resolved as usual, but only logged instead
+/// of injected.
+pub(super) fn dry_run(state: &State) -> bool {
+    state
+        .common
+        .eis_state
+        .as_ref()
+        .is_some_and(|eis_state| eis_state.dry_run)
+}
+
+/// Log `event` as input a dry run resolved but did not inject, and count it.
+/// Only logged at debug level, and never with what keys were typed.
+pub(super) fn log_dry_run(state: &State, event: std::fmt::Arguments<'_>) {
+    debug!("EIS dry run, not injecting {event}");
+    if let Some(eis_state) = state.common.eis_state.as_ref() {
+        eis_state.stats().dry_run_events += 1;
+    }
+}
+
+/// Whether input of `capability` is to be dropped because the config disables
+/// it, injection is paused, or it is `positioned` while there is no output.
+/// Dropped input is counted by reason.
+pub(super) fn drop_input(state: &State, capability: DeviceCapability, positioned: bool) -> bool {
+    let reason = if !allowed_capabilities(&state.common.config.cosmic_conf.eis_config)
+        .contains(capability)
+    {
+        debug!(?capability, "Dropping EIS event for a disabled capability");
+        "disabled capability"
+    } else if state
+        .common
+        .eis_state
+        .as_ref()
+        .is_some_and(|eis_state| eis_state.shared.injection_paused.load(Ordering::Acquire))
+    {
+        "paused"
+    } else if positioned && state.common.shell.read().outputs().next().is_none() {
+        // Between the last output going away and the next one appearing
+        // there is no space to place the pointer or touch points in
+        debug!("Dropping positioned EIS event without any output");
+        "no output"
+    } else {
+        return false;
+    };
+    if let Some(eis_state) = state.common.eis_state.as_ref() {
+        eis_state.count_dropped(reason);
+    }
+    true
+}
+
+/// Whether input the D-Bus interface makes on behalf of remote sessions, not
+/// sent by any EIS connection, is to be dropped. It is held to the same
+/// policy as input of the connections: dropped like by `drop_input`, and with
+/// `require_consent` until the local user approved one of the sessions.
+pub(super) fn drop_dbus_input(
+    state: &State,
+    capability: DeviceCapability,
+    positioned: bool,
+) -> bool {
+    let Some(eis_state) = state.common.eis_state.as_ref() else {
+        return true;
+    };
+    if state.common.config.cosmic_conf.eis_config.require_consent
+        && !eis_state
+            .connections
+            .values()
+            .any(|eis_connection| eis_connection.consent_granted)
+    {
+        debug!(?capability, "Dropping D-Bus input without consent");
+        eis_state.count_dropped("awaiting consent");
+        return true;
+    }
+    drop_input(state, capability, positioned)
+}
+
+/// Inject `input` of `eis_connection` like `inject_remote_input`, and record
+/// it to `record_input_path` if it was injected. Input dropped before, for
+/// lack of consent, being paused or disabled, or being invalid, never makes it
+/// into the recording, so replaying it cannot let it through.
+pub(super) fn inject_recorded(
+    state: &mut State,
+    eis_connection: &mut EisConnection,
+    input: RemoteInput,
+    time: u32,
+) -> bool {
+    let recorded = state
+        .common
+        .eis_state
+        .as_ref()
+        .is_some_and(|eis_state| eis_state.recorder.is_some())
+        .then(|| input.clone());
+    let injected = inject_remote_input(state, eis_connection, input, time);
+    if injected
+        && let Some(input) = recorded
+        && let Some(eis_state) = state.common.eis_state.as_mut()
+    {
+        eis_state.record(eis_connection.id, &input);
+    }
+    injected
+}
+
+/// Inject `input` of `eis_connection` into the active seat. Returns whether it
+/// was injected, rather than rejected as invalid or only logged in dry-run
+/// mode.
+pub(super) fn inject_remote_input(
+    state: &mut State,
+    eis_connection: &mut EisConnection,
+    input: RemoteInput,
+    time: u32,
+) -> bool {
+    let max_magnitude = state
+        .common
+        .config
+        .cosmic_conf
+        .eis_config
+        .max_input_magnitude;
+    if let Err(invalid) = input.validate(max_magnitude) {
+        warn!(
+            connection = eis_connection.id,
+            ?input,
+            reason = invalid.reason(),
+            "Rejecting invalid EIS input"
+        );
+        if let Some(eis_state) = state.common.eis_state.as_ref() {
+            eis_state.count_dropped(invalid.reason());
+        }
+        return false;
+    }
+
+    // Clicks, keys and touch may restack or move windows
+    if !matches!(
+        input,
+        RemoteInput::PointerMotion { .. }
+            | RemoteInput::PointerMotionAbsolute { .. }
+            | RemoteInput::Frame
+    ) && let Some(eis_state) = state.common.eis_state.as_mut()
+    {
+        eis_state.under_cache = None;
+    }
+
+    // Remote input is user activity like local input, delaying screen
+    // blanking and suspend, unless it is not injected in dry run
+    if !eis_connection.no_wake && !dry_run(state) {
+        let seat = state.common.shell.read().seats.last_active().clone();
+        state.common.idle_notifier_state.notify_activity(&seat);
+    }
+
+    match input {
+        RemoteInput::PointerMotion { dx, dy, utime } => {
+            let dx = f64::from(dx);
+            let dy = f64::from(dy);
+            // EIS only carries a single relative delta, which is whatever the
+            // client chose to send (usually already accelerated on its side).
+            // It is forwarded untouched as the unaccelerated delta for
+            // relative-pointer clients, while the cursor itself moves by the
+            // delta after server-side sensitivity and acceleration, unless
+            // `pointer_raw_motion` asks to skip those.
+            let raw_delta = (dx, dy);
+            let eis_config = &state.common.config.cosmic_conf.eis_config;
+            let (dx, dy) = if eis_config.pointer_raw_motion {
+                eis_connection.last_motion_time = Some(time);
+                raw_delta
+            } else {
+                accelerate_motion(eis_config, eis_connection, time, dx, dy)
+            };
+
+            let relative = RelativeMotionEvent {
+                delta: (dx, dy).into(),
+                delta_unaccel: raw_delta.into(),
+                utime,
+            };
+
+            // Smoothing only spreads out the cursor movement, relative
+            // pointer clients get each delta as it arrives
+            let (dx, dy) = smooth_motion(
+                state.common.config.cosmic_conf.eis_config.pointer_smoothing,
+                &mut eis_connection.held_motion,
+                dx,
+                dy,
+            );
+            if eis_connection.held_motion != (0.0, 0.0) {
+                schedule_held_motion(state, eis_connection);
+            }
+            move_pointer_relative(state, eis_connection, (dx, dy), Some(relative), time);
+        }
+        RemoteInput::PointerMotionAbsolute { x, y } => {
+            let x = f64::from(x);
+            let y = f64::from(y);
+
+            // Absolute coordinates are relative to the device regions we
+            // announced, which are in global logical coordinates already.
+            // Clients cannot declare a coordinate space of their own, so it is
+            // up to them to map their resolution onto those regions.
+            // Output transforms, flipped ones included, are applied to the
+            // regions and to what screencasts show alike, so the coordinates
+            // need no rotating or flipping of their own.
+            // Relative motion held back for smoothing would move the cursor
+            // away from where it is placed
+            eis_connection.held_motion = (0.0, 0.0);
+            let seat = pointer_seat(state, eis_connection);
+            let shell = state.common.shell.read();
+            let outputs = session_outputs(&shell, eis_connection.target_output.as_deref());
+            let mut position: Point<f64, Global> = (x, y).into();
+            if let Some(region) = eis_connection.pointer_confinement {
+                position = confine(position, region);
+            }
+            let (output, position) = place_on_outputs(|| outputs.iter().copied(), position)
+                .unwrap_or_else(|| (seat.active_output(), position));
+            std::mem::drop(shell);
+            if dry_run(state) {
+                let under = (!eis_connection.pointer_no_focus)
+                    .then(|| surface_under(state, position, &output))
+                    .flatten()
+                    .map(|(target, _)| target);
+                log_dry_run(
+                    state,
+                    format_args!(
+                        "absolute pointer motion to {position:?} on {} over {under:?}",
+                        output.name()
+                    ),
+                );
+                return false;
+            }
+            let focus = !eis_connection.pointer_no_focus;
+            let serial = eis_connection.next_serial();
+            move_pointer(state, &seat, position, &output, focus, serial, time);
+            mark_remote_cursor(state, &seat);
+        }
+        input => inject_seat_input(
+            &mut SeatInjector::for_connection(state, eis_connection),
+            eis_connection,
+            input,
+            time,
+        ),
+    }
+    !dry_run(state)
+}
+
+/// Inject `input` of `eis_connection` through `injector`, apart from pointer
+/// motion, which is placed against the output layout by `inject_remote_input`.
+/// `input` is expected to have been validated there.
+pub(super) fn inject_seat_input(
+    injector: &mut impl InputInjector,
+    eis_connection: &mut EisConnection,
+    input: RemoteInput,
+    time: u32,
+) {
+    match input {
+        RemoteInput::Key { key, pressed } => {
+            handle_key(injector, eis_connection, key, pressed, time);
+        }
+        RemoteInput::Button { button, pressed } => {
+            handle_button(injector, eis_connection, button, pressed, time);
+        }
+        // Scroll kinetics in apps derive the velocity from the axis frame
+        // times, so those follow the client's frames rather than when the
+        // compositor got around to processing them
+        RemoteInput::ScrollDelta { dx, dy, utime } => {
+            let dx = f64::from(dx);
+            let dy = f64::from(dy);
+            let time = frame_time(utime, time);
+            handle_scroll(injector, eis_connection, dx, dy, time);
+        }
+        RemoteInput::ScrollDiscrete { dx, dy, utime } => {
+            let time = frame_time(utime, time);
+            handle_discrete_scroll(injector, eis_connection, dx, dy, time);
+        }
+        RemoteInput::TouchDown { touch_id, x, y } => {
+            let x = f64::from(x);
+            let y = f64::from(y);
+            if !injector.has_capability("touch") {
+                injector.missing_capability("touch");
+                return;
+            }
+            let Some(slot) = eis_connection.touch_slot(touch_id) else {
+                debug!(touch_id, "Dropping EIS touch down beyond the touch slots");
+                injector.dropped("touch slot out of range");
+                return;
+            };
+            injector.touch_down(slot, (x, y).into(), eis_connection.next_serial(), time);
+            eis_connection.touch_frame_pending = true;
+            eis_connection.active_touches.insert(touch_id);
+        }
+        RemoteInput::TouchMotion { touch_id, x, y } => {
+            let x = f64::from(x);
+            let y = f64::from(y);
+            if !injector.has_capability("touch") {
+                injector.missing_capability("touch");
+                return;
+            }
+            let Some(slot) = eis_connection.touch_slot(touch_id) else {
+                injector.dropped("touch slot out of range");
+                return;
+            };
+            injector.touch_motion(slot, (x, y).into(), time);
+            eis_connection.touch_frame_pending = true;
+        }
+        RemoteInput::TouchUp { touch_id } => {
+            // The seat would lift whatever touch point of another client the
+            // slot maps to, or one that is not down at all
+            if !eis_connection.active_touches.contains(&touch_id) {
+                debug!(touch_id, "Dropping EIS touch up of a touch point not down");
+                injector.dropped("touch not down");
+                return;
+            }
+            if !injector.has_capability("touch") {
+                injector.missing_capability("touch");
+                return;
+            }
+            // Only touch points that went down are active, so their slot
+            // did not overflow
+            let Some(slot) = eis_connection.touch_slot(touch_id) else {
+                return;
+            };
+            let serial = eis_connection.next_serial();
+            injector.touch_up(slot, serial, time);
+            eis_connection.touch_frame_pending = true;
+            eis_connection.active_touches.remove(&touch_id);
+        }
+        RemoteInput::TouchCancel => {
+            if !injector.has_capability("touch") {
+                injector.missing_capability("touch");
+                return;
+            }
+            injector.touch_cancel();
+            eis_connection.touch_frame_pending = true;
+            eis_connection.active_touches.clear();
+        }
+        RemoteInput::Frame => handle_frame(injector, eis_connection),
+        RemoteInput::PointerMotion { .. } | RemoteInput::PointerMotionAbsolute { .. } => {
+            debug!("Pointer motion needs the output layout to be injected");
+        }
+    }
+}
+
+/// Apply the configured scroll multipliers and, if enabled, rate-based
+/// acceleration to a remote scroll delta.
+///
+/// Multipliers are applied to the magnitude only, so the scroll direction the
+/// client sent (including any natural-scroll inversion it performed) is kept.
+fn scale_scroll(
+    config: &EisConfig,
+    eis_connection: &mut EisConnection,
+    time: u32,
+    dx: f64,
+    dy: f64,
+) -> (f64, f64) {
+    let accel = if config.scroll_acceleration {
+        let elapsed = eis_connection
+            .last_scroll_time
+            .map(|last| time.wrapping_sub(last))
+            .unwrap_or(u32::MAX);
+        scroll_acceleration(elapsed)
+    } else {
+        1.0
+    };
+    eis_connection.last_scroll_time = Some(time);
+
+    let multiplier = |value: f64| {
+        if value.is_finite() {
+            value.max(0.0)
+        } else {
+            1.0
+        }
+    };
+    (
+        dx * multiplier(config.scroll_multiplier_horizontal) * accel,
+        dy * multiplier(config.scroll_multiplier_vertical) * accel,
+    )
+}
+
+/// Timestamp in ms of an event whose client frame was stamped `utime`, in µs
+/// of `CLOCK_MONOTONIC`, like the compositor clock, or `now` if the stamp is
+/// missing or implausible: in the future or older than
+/// `MAX_FRAME_TIME_AGE_MS`. Both wrap like Wayland timestamps.
+pub(super) fn frame_time(utime: u64, now: u32) -> u32 {
+    let time = (utime / 1000) as u32;
+    if utime != 0 && now.wrapping_sub(time) <= MAX_FRAME_TIME_AGE_MS {
+        time
+    } else {
+        now
+    }
+}
+
+/// Acceleration factor for a scroll event arriving `elapsed_ms` after the
+/// previous one. Ramps linearly from `SCROLL_ACCEL_MAX` down to 1.0 over
+/// `SCROLL_ACCEL_WINDOW_MS`.
+fn scroll_acceleration(elapsed_ms: u32) -> f64 {
+    if elapsed_ms >= SCROLL_ACCEL_WINDOW_MS {
+        return 1.0;
+    }
+    let t = f64::from(elapsed_ms) / f64::from(SCROLL_ACCEL_WINDOW_MS);
+    SCROLL_ACCEL_MAX - (SCROLL_ACCEL_MAX - 1.0) * t
+}
+
+/// Move the pointer of `eis_connection` by `(dx, dy)` within the outputs of
+/// its session, sending `relative` to relative pointer clients first.
+fn move_pointer_relative(
+    state: &mut State,
+    eis_connection: &mut EisConnection,
+    (dx, dy): (f64, f64),
+    relative: Option<RelativeMotionEvent>,
+    time: u32,
+) {
+    let seat = pointer_seat(state, eis_connection);
+    if let Some(pointer) = seat.get_pointer() {
+        let current = pointer.current_location().as_global();
+        let current_output = seat.active_output();
+        let focus = if eis_connection.pointer_no_focus || relative.is_none() {
+            None
+        } else {
+            surface_under(state, current, &current_output)
+                .map(|(target, pos)| (target, pos.as_logical()))
+        };
+        let shell = state.common.shell.read();
+        let outputs = session_outputs(&shell, eis_connection.target_output.as_deref());
+        let mut position = current + Point::from((dx, dy));
+        let mut output = output_under(outputs.iter().copied(), position);
+        if output.is_none() {
+            let layout = outputs
+                .iter()
+                .map(|output| output.geometry())
+                .reduce(|a, b| a.merge(b));
+            position = edge_position(
+                eis_connection
+                    .pointer_edge
+                    .unwrap_or(state.common.config.cosmic_conf.eis_config.pointer_edge),
+                current,
+                position,
+                layout,
+            );
+            output = output_under(outputs.iter().copied(), position);
+        }
+        let mut output = output.unwrap_or(current_output);
+        let mut position = clamp_to_output(position, output.geometry());
+        if let Some(region) = eis_connection.pointer_confinement
+            && let Some(placed) =
+                place_on_outputs(|| outputs.iter().copied(), confine(position, region))
+        {
+            (output, position) = placed;
+        } else if eis_connection.target_output.is_some()
+            && let Some(placed) = place_on_outputs(|| outputs.iter().copied(), position)
+        {
+            // A pointer that was elsewhere when the target was set,
+            // and released at the edge, still ends up on it
+            (output, position) = placed;
+        }
+
+        std::mem::drop(shell);
+        if dry_run(state) {
+            let under = (!eis_connection.pointer_no_focus)
+                .then(|| surface_under(state, position, &output))
+                .flatten()
+                .map(|(target, _)| target);
+            log_dry_run(
+                state,
+                format_args!(
+                    "pointer motion by ({dx}, {dy}) to {position:?} on {} over {under:?}",
+                    output.name()
+                ),
+            );
+            return;
+        }
+        if let Some(relative) = relative {
+            pointer.relative_motion(state, focus, &relative);
+        }
+        let refocus = !eis_connection.pointer_no_focus;
+        let serial = eis_connection.next_serial();
+        move_pointer(state, &seat, position, &output, refocus, serial, time);
+        mark_remote_cursor(state, &seat);
+    } else {
+        missing_capability(state, "pointer");
+    }
+}
+
+/// Apply pointer smoothing to the motion `(dx, dy)`: `factor` of it and of the
+/// motion held back before is held back again in `held`, the rest returned to
+/// move the cursor by. A `factor` of 0 returns everything, 1:1.
+pub(super) fn smooth_motion(factor: f64, held: &mut (f64, f64), dx: f64, dy: f64) -> (f64, f64) {
+    let total = (held.0 + dx, held.1 + dy);
+    if !factor.is_finite() || factor <= 0.0 {
+        *held = (0.0, 0.0);
+        return total;
+    }
+    let factor = factor.min(1.0);
+    *held = (total.0 * factor, total.1 * factor);
+    (total.0 - held.0, total.1 - held.1)
+}
+
+/// Apply the motion `eis_connection` holds back for smoothing once
+/// `MOTION_SMOOTHING_WINDOW` passed, so it lags behind by a frame at most.
+fn schedule_held_motion(state: &mut State, eis_connection: &mut EisConnection) {
+    if eis_connection.held_motion_timer.is_some() {
+        return;
+    }
+    let Some(eis_state) = state.common.eis_state.as_ref() else {
+        return;
+    };
+    let id = eis_connection.id;
+    match eis_state.evlh.insert_source(
+        Timer::from_duration(MOTION_SMOOTHING_WINDOW),
+        move |_, _, state| {
+            apply_held_motion(state, id);
+            TimeoutAction::Drop
+        },
+    ) {
+        Ok(token) => eis_connection.held_motion_timer = Some(token),
+        Err(e) => {
+            error!(
+                "Failed to schedule smoothed EIS pointer motion: {}",
+                e.error
+            );
+            eis_connection.held_motion = (0.0, 0.0);
+        }
+    }
+}
+
+/// Move the pointer of connection `id` by the motion it held back for
+/// smoothing.
+fn apply_held_motion(state: &mut State, id: u64) {
+    EisState::with_connection(state, id, |state, eis_connection, _| {
+        eis_connection.held_motion_timer = None;
+        let held = std::mem::take(&mut eis_connection.held_motion);
+        if held != (0.0, 0.0) && !eis_connection.closed {
+            let time = state.common.clock.now().as_millis();
+            move_pointer_relative(state, eis_connection, held, None, time);
+        }
+    });
+}
+
+/// Apply the configured sensitivity and acceleration profile to a relative
+/// pointer delta.
+///
+/// The pointer location is tracked in `f64`, so fractional results are kept
+/// as-is rather than rounded away between events.
+fn accelerate_motion(
+    config: &EisConfig,
+    eis_connection: &mut EisConnection,
+    time: u32,
+    dx: f64,
+    dy: f64,
+) -> (f64, f64) {
+    let elapsed = eis_connection
+        .last_motion_time
+        .map(|last| time.wrapping_sub(last));
+    eis_connection.last_motion_time = Some(time);
+
+    let sensitivity = if config.pointer_sensitivity.is_finite() {
+        config.pointer_sensitivity.max(0.0)
+    } else {
+        1.0
+    };
+    let gain = match config.pointer_acceleration {
+        EisPointerAcceleration::Flat => 1.0,
+        EisPointerAcceleration::Adaptive => {
+            // Events in the same millisecond are treated as 1ms apart
+            let elapsed = elapsed.map_or(f64::INFINITY, |ms| f64::from(ms.max(1)));
+            adaptive_gain(dx.hypot(dy) / elapsed)
+        }
+    };
+    (dx * sensitivity * gain, dy * sensitivity * gain)
+}
+
+/// Gain of the adaptive acceleration profile for a pointer moving at `speed`
+/// logical px/ms.
+fn adaptive_gain(speed: f64) -> f64 {
+    if !speed.is_finite() || speed <= POINTER_ACCEL_THRESHOLD {
+        return 1.0;
+    }
+    (1.0 + (speed - POINTER_ACCEL_THRESHOLD) * POINTER_ACCEL_SLOPE).min(POINTER_ACCEL_MAX)
+}
+
+/// Move the pointer of `seat` to the center of the active output of the
+/// active seat.
+pub(super) fn warp_pointer_to_output_center(state: &mut State, seat: &Seat<State>, time: u32) {
+    let output = state
+        .common
+        .shell
+        .read()
+        .seats
+        .last_active()
+        .active_output();
+    let geom = output.geometry().to_f64();
+    debug!(output = %output.name(), "Warping EIS pointer to output center");
+    warp_pointer(
+        state,
+        seat,
+        geom.loc + geom.size.downscale(2.0).to_point(),
+        time,
+    );
+}
+
+/// Move the pointer of `seat` to `position`, updating pointer focus for the
+/// new location. Positions outside of every output are ignored.
+pub(super) fn warp_pointer(
+    state: &mut State,
+    seat: &Seat<State>,
+    position: Point<f64, Global>,
+    time: u32,
+) {
+    let shell = state.common.shell.read();
+    let Some(output) = output_under(shell.outputs(), position) else {
+        return;
+    };
+    std::mem::drop(shell);
+    let serial = SERIAL_COUNTER.next_serial();
+    move_pointer(state, seat, position, &output, true, serial, time);
+}
+
+/// Seat the pointer input of `eis_connection` goes to: its own with
+/// `pointer_per_session`, else the active seat.
+pub(super) fn pointer_seat(state: &State, eis_connection: &EisConnection) -> Seat<State> {
+    eis_connection
+        .pointer_seat
+        .clone()
+        .unwrap_or_else(|| state.common.shell.read().seats.last_active().clone())
+}
+
+/// Give `eis_connection` a seat of its own for its pointer, reusing one a
+/// previous session gave back. Returns `false` if all
+/// `MAX_SESSION_POINTERS` seats are taken.
+pub(super) fn lend_pointer_seat(state: &mut State, eis_connection: &mut EisConnection) -> bool {
+    let Some(eis_state) = state.common.eis_state.as_ref() else {
+        return false;
+    };
+    let free = eis_state
+        .pointer_seats
+        .iter()
+        .find(|seat| {
+            !eis_state
+                .connections
+                .values()
+                .any(|c| c.pointer_seat.as_ref() == Some(*seat))
+        })
+        .cloned();
+    let seat = match free {
+        Some(seat) => seat,
+        None if eis_state.pointer_seats.len() < MAX_SESSION_POINTERS => {
+            let output = state
+                .common
+                .shell
+                .read()
+                .seats
+                .last_active()
+                .active_output();
+            let name = format!("eis-pointer-{}", eis_state.pointer_seats.len());
+            let seat = crate::shell::create_seat(
+                &state.common.display_handle,
+                &mut state.common.seat_state,
+                &output,
+                &state.common.config,
+                name,
+            );
+            if let Some(eis_state) = state.common.eis_state.as_mut() {
+                eis_state.pointer_seats.push(seat.clone());
+            }
+            seat
+        }
+        None => {
+            warn!(
+                connection = eis_connection.id,
+                "All {MAX_SESSION_POINTERS} EIS session pointers are taken, sharing the seat's"
+            );
+            return false;
+        }
+    };
+    info!(
+        connection = eis_connection.id,
+        name = %eis_connection.name,
+        seat = %seat.name(),
+        "EIS client got a pointer of its own"
+    );
+    // Only seats in the shell get their cursor drawn
+    state.common.shell.write().seats.add_seat(seat.clone());
+    eis_connection.pointer_seat = Some(seat);
+    true
+}
+
+/// Take back the seat `eis_connection` got for its pointer, dropping its
+/// pointer focus and cursor.
+pub(super) fn return_pointer_seat(
+    state: &mut State,
+    eis_connection: &mut EisConnection,
+    time: u32,
+) {
+    let Some(seat) = eis_connection.pointer_seat.take() else {
+        return;
+    };
+    if let Some(pointer) = seat.get_pointer() {
+        let location = pointer.current_location();
+        pointer.motion(
+            state,
+            None,
+            &smithay::input::pointer::MotionEvent {
+                location,
+                serial: SERIAL_COUNTER.next_serial(),
+                time,
+            },
+        );
+        pointer.frame(state);
+    }
+    state.common.shell.write().seats.remove_seat(&seat);
+    let outputs = state
+        .common
+        .shell
+        .read()
+        .outputs()
+        .cloned()
+        .collect::<Vec<_>>();
+    for output in outputs {
+        state.backend.schedule_render(&output);
+    }
+}
+
+/// Sink for input injected on behalf of EIS clients.
+///
+/// The compositor injects into its active seat through `SeatInjector`; tests
+/// record the events instead to check exactly what a request injects. The
+/// capability names are the ones `missing_capability` counts.
+pub(super) trait InputInjector {
+    fn config(&self) -> &EisConfig;
+    /// Whether the seat has a "keyboard", "pointer" or "touch".
+    fn has_capability(&self, capability: &'static str) -> bool;
+    /// Count an event dropped because the seat lacks `capability`.
+    fn missing_capability(&mut self, capability: &'static str);
+    /// Count an event dropped for `reason`.
+    fn dropped(&mut self, reason: &'static str);
+    /// Whether `key` is down on the seat, by whomever.
+    fn key_down(&self, key: u32) -> bool;
+    /// Whether a pointer grab, like a drag-and-drop, is in progress.
+    fn pointer_grabbed(&self) -> bool;
+    fn key(&mut self, connection: u64, key: u32, key_state: KeyState, serial: Serial, time: u32);
+    fn button(&mut self, button: u32, button_state: ButtonState, serial: Serial, time: u32);
+    /// Scroll both axes of `scroll` in one axis frame.
+    fn axis(&mut self, scroll: ScrollFrame);
+    fn pointer_frame(&mut self);
+    /// Note pointer input of a remote client, for the remote cursor theme.
+    fn remote_pointer_activity(&mut self);
+    fn touch_down(&mut self, slot: u32, position: Point<f64, Global>, serial: Serial, time: u32);
+    fn touch_motion(&mut self, slot: u32, position: Point<f64, Global>, time: u32);
+    fn touch_up(&mut self, slot: u32, serial: Serial, time: u32);
+    fn touch_cancel(&mut self);
+    fn touch_frame(&mut self);
+}
+
+/// Injects into the active seat of the compositor.
+pub(super) struct SeatInjector<'a> {
+    state: &'a mut State,
+    seat: Seat<State>,
+    /// Seat pointer input goes to, the active one unless a session has a
+    /// pointer of its own.
+    pointer_seat: Seat<State>,
+    /// Whether to only log what would be injected.
+    dry_run: bool,
+}
+
+impl<'a> SeatInjector<'a> {
+    pub(super) fn new(state: &'a mut State) -> Self {
+        let seat = state.common.shell.read().seats.last_active().clone();
+        Self {
+            dry_run: dry_run(state),
+            state,
+            pointer_seat: seat.clone(),
+            seat,
+        }
+    }
+
+    /// Inject pointer input of `eis_connection` on its own seat, if it has one.
+    pub(super) fn for_connection(state: &'a mut State, eis_connection: &EisConnection) -> Self {
+        let mut injector = Self::new(state);
+        if let Some(seat) = &eis_connection.pointer_seat {
+            injector.pointer_seat = seat.clone();
+        }
+        injector
+    }
+}
+
+impl InputInjector for SeatInjector<'_> {
+    fn config(&self) -> &EisConfig {
+        &self.state.common.config.cosmic_conf.eis_config
+    }
+
+    fn has_capability(&self, capability: &'static str) -> bool {
+        match capability {
+            "keyboard" => self.seat.get_keyboard().is_some(),
+            "pointer" => self.pointer_seat.get_pointer().is_some(),
+            "touch" => self.seat.get_touch().is_some(),
+            _ => false,
+        }
+    }
+
+    fn missing_capability(&mut self, capability: &'static str) {
+        missing_capability(self.state, capability);
+    }
+
+    fn dropped(&mut self, reason: &'static str) {
+        if let Some(eis_state) = self.state.common.eis_state.as_ref() {
+            eis_state.count_dropped(reason);
+        }
+    }
+
+    fn key_down(&self, key: u32) -> bool {
+        self.seat
+            .get_keyboard()
+            .is_some_and(|keyboard| keyboard.pressed_keys().contains(&Keycode::new(key)))
+    }
+
+    fn pointer_grabbed(&self) -> bool {
+        self.pointer_seat
+            .get_pointer()
+            .is_some_and(|pointer| pointer.is_grabbed())
+    }
+
+    fn key(&mut self, connection: u64, key: u32, key_state: KeyState, serial: Serial, time: u32) {
+        if self.dry_run {
+            let focus = self
+                .seat
+                .get_keyboard()
+                .and_then(|keyboard| keyboard.current_focus());
+            // Which key is left out, or the log would hold what was typed
+            log_dry_run(
+                self.state,
+                format_args!("key {key_state:?} of connection {connection} to {focus:?}"),
+            );
+            return;
+        }
+        inject_key(self.state, connection, key, key_state, serial, time);
+    }
+
+    fn button(&mut self, button: u32, button_state: ButtonState, serial: Serial, time: u32) {
+        if self.dry_run {
+            let target = self
+                .pointer_seat
+                .get_pointer()
+                .map(|pointer| (pointer.current_location(), pointer.current_focus()));
+            log_dry_run(
+                self.state,
+                format_args!("button {button:#x} {button_state:?} at {target:?}"),
+            );
+            return;
+        }
+        if let Some(pointer) = self.pointer_seat.get_pointer() {
+            pointer.button(
+                self.state,
+                &smithay::input::pointer::ButtonEvent {
+                    button,
+                    state: button_state,
+                    serial,
+                    time,
+                },
+            );
+        }
+    }
+
+    fn axis(&mut self, scroll: ScrollFrame) {
+        if self.dry_run {
+            let target = self
+                .pointer_seat
+                .get_pointer()
+                .map(|pointer| (pointer.current_location(), pointer.current_focus()));
+            log_dry_run(self.state, format_args!("{scroll:?} at {target:?}"));
+            return;
+        }
+        if let Some(pointer) = self.pointer_seat.get_pointer() {
+            use smithay::backend::input::{Axis, AxisSource};
+            let source = if scroll.v120_x != 0 || scroll.v120_y != 0 {
+                AxisSource::Wheel
+            } else {
+                AxisSource::Continuous
+            };
+            let mut frame = smithay::input::pointer::AxisFrame::new(scroll.time).source(source);
+            for (axis, delta, v120) in [
+                (Axis::Vertical, scroll.dy, scroll.v120_y),
+                (Axis::Horizontal, scroll.dx, scroll.v120_x),
+            ] {
+                // Wheel clicks scroll by the distance of a click, unless the
+                // client sent the distance along with them
+                let value = if delta != 0.0 {
+                    delta
+                } else {
+                    f64::from(v120) / 120.0 * WHEEL_CLICK_DISTANCE
+                };
+                if value != 0.0 {
+                    frame = frame.value(axis, value);
+                }
+                if v120 != 0 {
+                    frame = frame.v120(axis, v120);
+                }
+            }
+            pointer.axis(self.state, frame);
+        }
+    }
+
+    fn pointer_frame(&mut self) {
+        if self.dry_run {
+            return;
+        }
+        if let Some(pointer) = self.pointer_seat.get_pointer() {
+            pointer.frame(self.state);
+        }
+    }
+
+    fn remote_pointer_activity(&mut self) {
+        if !self.dry_run {
+            mark_remote_cursor(self.state, &self.pointer_seat);
+        }
+    }
+
+    // `ei_touchscreen` only carries a touch id and position, so there is no
+    // pressure or contact size to pass on as touch shape events
+    fn touch_down(&mut self, slot: u32, position: Point<f64, Global>, serial: Serial, time: u32) {
+        let (seat, under) = resolve_touch_target(self.state, position.x, position.y);
+        if self.dry_run {
+            log_dry_run(
+                self.state,
+                format_args!("touch down of slot {slot} at {position:?} on {under:?}"),
+            );
+            return;
+        }
+        if let Some(touch_handle) = seat.get_touch() {
+            touch_handle.down(
+                self.state,
+                under,
+                &DownEvent {
+                    slot: TouchSlot::from(Some(slot)),
+                    location: position.as_logical(),
+                    serial,
+                    time,
+                },
+            );
+        }
+    }
+
+    fn touch_motion(&mut self, slot: u32, position: Point<f64, Global>, time: u32) {
+        let (seat, under) = resolve_touch_target(self.state, position.x, position.y);
+        if self.dry_run {
+            log_dry_run(
+                self.state,
+                format_args!("touch motion of slot {slot} to {position:?} on {under:?}"),
+            );
+            return;
+        }
+        if let Some(touch_handle) = seat.get_touch() {
+            touch_handle.motion(
+                self.state,
+                under,
+                &TouchMotionEvent {
+                    slot: TouchSlot::from(Some(slot)),
+                    location: position.as_logical(),
+                    time,
+                },
+            );
+        }
+    }
+
+    fn touch_up(&mut self, slot: u32, serial: Serial, time: u32) {
+        if self.dry_run {
+            log_dry_run(self.state, format_args!("touch up of slot {slot}"));
+            return;
+        }
+        if let Some(touch_handle) = self.seat.get_touch() {
+            touch_handle.up(
+                self.state,
+                &UpEvent {
+                    slot: TouchSlot::from(Some(slot)),
+                    time,
+                    serial,
+                },
+            );
+        }
+    }
+
+    fn touch_cancel(&mut self) {
+        if self.dry_run {
+            log_dry_run(self.state, format_args!("touch cancel"));
+            return;
+        }
+        if let Some(touch_handle) = self.seat.get_touch() {
+            touch_handle.cancel(self.state);
+        }
+    }
+
+    fn touch_frame(&mut self) {
+        if self.dry_run {
+            return;
+        }
+        if let Some(touch_handle) = self.seat.get_touch() {
+            touch_handle.frame(self.state);
+        }
+    }
+}
+
+/// Inject a key of `eis_connection`, unless that would unbalance the seat.
+///
+/// Repeated presses of a held key are handled according to `key_repeat`, so
+/// the key repeats once per source of repeats.
+pub(super) fn handle_key(
+    injector: &mut impl InputInjector,
+    eis_connection: &mut EisConnection,
+    key: u32,
+    pressed: bool,
+    time: u32,
+) {
+    if !injector.has_capability("keyboard") {
+        injector.missing_capability("keyboard");
+        return;
+    }
+    if eis_connection.held_keys.is_repeat(key, pressed) {
+        match injector.config().key_repeat {
+            EisKeyRepeat::Compositor => {
+                debug!(keycode = key, "Dropping EIS key repeat of the client");
+            }
+            EisKeyRepeat::Client => {
+                // A fresh press restarts the repeat delay of the focused
+                // client, so only the remote client's repeats get through
+                for key_state in [KeyState::Released, KeyState::Pressed] {
+                    let serial = eis_connection.next_serial();
+                    injector.key(eis_connection.id, key, key_state, serial, time);
+                }
+            }
+        }
+        return;
+    }
+    if !eis_connection
+        .held_keys
+        .filter(key, pressed, injector.key_down(key))
+    {
+        debug!(
+            keycode = key,
+            pressed, "Dropping EIS key event that would unbalance the seat"
+        );
+        return;
+    }
+    let key_state = if pressed {
+        KeyState::Pressed
+    } else {
+        KeyState::Released
+    };
+    let serial = eis_connection.next_serial();
+    injector.key(eis_connection.id, key, key_state, serial, time);
+}
+
+/// Inject a button of `eis_connection` after remapping it, keeping clear of
+/// pointer grabs the client did not start.
+pub(super) fn handle_button(
+    injector: &mut impl InputInjector,
+    eis_connection: &mut EisConnection,
+    button: u32,
+    pressed: bool,
+    time: u32,
+) {
+    if !injector.has_capability("pointer") {
+        injector.missing_capability("pointer");
+        return;
+    }
+    let button = map_button(injector.config(), button);
+    if pressed {
+        // A press while the client holds no button can only interfere with a
+        // grab someone else started
+        if eis_connection.held_buttons.is_empty()
+            && injector.pointer_grabbed()
+            && injector.config().drop_buttons_during_grab
+        {
+            debug!(button, "Dropping EIS button press during a pointer grab");
+            return;
+        }
+        eis_connection.held_buttons.insert(button);
+    } else if !eis_connection.held_buttons.remove(&button) {
+        // Releasing a button the client never pressed would end a local
+        // drag-and-drop or other grab
+        debug!(button, "Dropping EIS release of a button it does not hold");
+        return;
+    }
+    let button_state = if pressed {
+        ButtonState::Pressed
+    } else {
+        ButtonState::Released
+    };
+    let serial = eis_connection.next_serial();
+    injector.button(button, button_state, serial, time);
+    injector.pointer_frame();
+    injector.remote_pointer_activity();
+}
+
+/// Add a scroll of `eis_connection`, scaled according to the config, to the
+/// scrolling of its current frame.
+pub(super) fn handle_scroll(
+    injector: &mut impl InputInjector,
+    eis_connection: &mut EisConnection,
+    dx: f64,
+    dy: f64,
+    time: u32,
+) {
+    if !injector.has_capability("pointer") {
+        injector.missing_capability("pointer");
+        return;
+    }
+    let (dx, dy) = scale_scroll(injector.config(), eis_connection, time, dx, dy);
+    let scroll = eis_connection.pending_scroll.get_or_insert_default();
+    scroll.dx += dx;
+    scroll.dy += dy;
+    scroll.time = time;
+}
+
+/// End a frame of `eis_connection`.
+///
+/// Touch points of a multi-touch gesture arrive as separate requests, so they
+/// are grouped into a single touch frame, just like the client grouped them.
+/// Scrolling is grouped into a single axis frame the same way.
+pub(super) fn handle_frame(injector: &mut impl InputInjector, eis_connection: &mut EisConnection) {
+    if std::mem::take(&mut eis_connection.touch_frame_pending) {
+        injector.touch_frame();
+    }
+    if let Some(scroll) = eis_connection.pending_scroll.take() {
+        injector.axis(scroll);
+        injector.pointer_frame();
+        injector.remote_pointer_activity();
+    }
+}
+
+/// Add wheel clicks of `eis_connection`, in 1/120ths of a click, scaled
+/// according to the config like continuous scrolling, to the scrolling of
+/// its current frame.
+pub(super) fn handle_discrete_scroll(
+    injector: &mut impl InputInjector,
+    eis_connection: &mut EisConnection,
+    v120_x: i32,
+    v120_y: i32,
+    time: u32,
+) {
+    if !injector.has_capability("pointer") {
+        injector.missing_capability("pointer");
+        return;
+    }
+    let (dx, dy) = scale_scroll(
+        injector.config(),
+        eis_connection,
+        time,
+        f64::from(v120_x),
+        f64::from(v120_y),
+    );
+    // `as` saturates, so huge multipliers cannot wrap around
+    let (v120_x, v120_y) = (dx.round() as i32, dy.round() as i32);
+    if v120_x == 0 && v120_y == 0 {
+        return;
+    }
+    let scroll = eis_connection.pending_scroll.get_or_insert_default();
+    scroll.v120_x = scroll.v120_x.saturating_add(v120_x);
+    scroll.v120_y = scroll.v120_y.saturating_add(v120_y);
+    scroll.time = time;
+}
+
+/// Count and log an event dropped because the active seat has no `capability`.
+///
+/// Seats get all capabilities when they are created, so this should not
+/// happen, but input must not vanish without a trace if it does.
+fn missing_capability(state: &mut State, capability: &'static str) {
+    debug!(
+        capability,
+        "Dropping EIS event, active seat lacks capability"
+    );
+    if let Some(eis_state) = state.common.eis_state.as_ref() {
+        *eis_state
+            .stats()
+            .missing_capability
+            .entry(capability)
+            .or_default() += 1;
+    }
+    trace!(
+        target: METRICS_TARGET,
+        monotonic_counter.eis_missing_capability = 1u64,
+        capability
+    );
+}
+
+/// Inject a key event of connection `id` into the keyboard of the active seat.
+fn inject_key(
+    state: &mut State,
+    id: u64,
+    key: u32,
+    key_state: KeyState,
+    serial: Serial,
+    time: u32,
+) {
+    let seat = state.common.shell.read().seats.last_active().clone();
+    let Some(keyboard) = seat.get_keyboard() else {
+        return;
+    };
+    if let Some(eis_state) = state.common.eis_state.as_mut() {
+        eis_state.injecting_keys = Some(id);
+    }
+    // Remote keys bypass the compositor's key filter, so they reach clients
+    // without switching VTs or triggering shortcuts, unless
+    // `remote_shortcuts` lets them trigger some
+    let mode = state.common.config.cosmic_conf.eis_config.remote_shortcuts;
+    let shortcut = keyboard
+        .input(
+            state,
+            Keycode::new(key),
+            key_state,
+            serial,
+            time,
+            |state, modifiers, handle| {
+                if mode == EisRemoteShortcuts::None {
+                    return FilterResult::Forward;
+                }
+                if key_state == KeyState::Released {
+                    if let Some(tokens) = seat.supressed_keys().filter(&handle) {
+                        for token in tokens {
+                            state.common.event_loop_handle.remove(token);
+                        }
+                        return FilterResult::Intercept(None);
+                    }
+                    return FilterResult::Forward;
+                }
+                match remote_shortcut(
+                    state.common.config.shortcuts.iter(),
+                    mode,
+                    modifiers,
+                    handle.raw_syms(),
+                ) {
+                    Some(shortcut) => {
+                        seat.supressed_keys().add(&handle, None);
+                        FilterResult::Intercept(Some(shortcut))
+                    }
+                    None => FilterResult::Forward,
+                }
+            },
+        )
+        .flatten();
+    if let Some(eis_state) = state.common.eis_state.as_mut() {
+        eis_state.injecting_keys = None;
+    }
+    if let Some((action, binding)) = shortcut {
+        info!(connection = id, ?action, "EIS key triggered shortcut");
+        state.handle_action(Action::Shortcut(action), &seat, serial, time, binding, None);
+    }
+}
+
+/// Shortcut of `shortcuts` with a key that a remote press of a key producing
+/// `syms` with `modifiers` triggers, if `mode` lets remote keys trigger it.
+pub(super) fn remote_shortcut<'a>(
+    shortcuts: impl IntoIterator<Item = (&'a shortcuts::Binding, &'a shortcuts::Action)>,
+    mode: EisRemoteShortcuts,
+    modifiers: &ModifiersState,
+    syms: &[Keysym],
+) -> Option<(shortcuts::Action, shortcuts::Binding)> {
+    let allowed = |action: &shortcuts::Action| match action {
+        shortcuts::Action::Disable | shortcuts::Action::Terminate | shortcuts::Action::Debug => {
+            false
+        }
+        shortcuts::Action::Spawn(_) | shortcuts::Action::System(_) => {
+            mode == EisRemoteShortcuts::All
+        }
+        _ => mode != EisRemoteShortcuts::None,
+    };
+    shortcuts
+        .into_iter()
+        .find(|(binding, action)| {
+            binding.key.is_some_and(|key| syms.contains(&key))
+                && cosmic_modifiers_eq_smithay(&binding.modifiers, modifiers)
+                && allowed(action)
+        })
+        .map(|(binding, action)| (action.clone(), binding.clone()))
+}
+
+/// Draw the cursor of `seat` with the configured remote cursor theme, until no
+/// remote pointer input arrived for `remote_cursor_timeout_ms`.
+fn mark_remote_cursor(state: &mut State, seat: &Seat<State>) {
+    let eis_config = &state.common.config.cosmic_conf.eis_config;
+    let Some(theme) = eis_config.remote_cursor_theme.as_deref() else {
+        return;
+    };
+    let until = Instant::now() + Duration::from_millis(eis_config.remote_cursor_timeout_ms);
+    seat.user_data()
+        .get::<CursorState>()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .set_remote_theme(theme, until);
+
+    let Some(eis_state) = state.common.eis_state.as_mut() else {
+        return;
+    };
+    if eis_state.remote_cursor_timer.is_some() {
+        return;
+    }
+    eis_state.remote_cursor_timer = eis_state
+        .evlh
+        .insert_source(Timer::from_deadline(until), move |now, _, state| {
+            // Remote input kept coming in, wait for the new deadline, of
+            // whichever seat a session pointer moves
+            let until = state
+                .common
+                .shell
+                .read()
+                .seats
+                .iter()
+                .filter_map(|seat| {
+                    seat.user_data()
+                        .get::<CursorState>()
+                        .unwrap()
+                        .lock()
+                        .unwrap()
+                        .remote_until()
+                })
+                .max();
+            if let Some(until) = until.filter(|until| *until > now) {
+                return TimeoutAction::ToInstant(until);
+            }
+
+            if let Some(eis_state) = state.common.eis_state.as_mut() {
+                eis_state.remote_cursor_timer = None;
+            }
+            let outputs = state
+                .common
+                .shell
+                .read()
+                .outputs()
+                .cloned()
+                .collect::<Vec<_>>();
+            for output in outputs {
+                state.backend.schedule_render(&output);
+            }
+            TimeoutAction::Drop
+        })
+        .map_err(|e| warn!("Failed to schedule remote cursor reset: {}", e.error))
+        .ok();
+}
+
+/// Move the pointer of `seat` to `position` on `output`, the same way the
+/// local input path does.
+///
+/// With `focus`, focus is recomputed against the output the pointer ends up
+/// on, so Smithay sends leave/enter to the affected surfaces whenever it
+/// changes. Without it, no surface has pointer focus. Either way the seat's
+/// active output follows the pointer across outputs. Without the latter,
+/// later lookups would keep resolving against the old output.
+fn move_pointer(
+    state: &mut State,
+    seat: &Seat<State>,
+    position: Point<f64, Global>,
+    output: &Output,
+    focus: bool,
+    serial: Serial,
+    time: u32,
+) {
+    let Some(pointer) = seat.get_pointer() else {
+        missing_capability(state, "pointer");
+        return;
+    };
+    let under = if focus {
+        surface_under(state, position, output).map(|(target, pos)| (target, pos.as_logical()))
+    } else {
+        None
+    };
+
+    pointer.motion(
+        state,
+        under,
+        &smithay::input::pointer::MotionEvent {
+            location: position.as_logical(),
+            serial,
+            time,
+        },
+    );
+    pointer.frame(state);
+
+    let mut shell = state.common.shell.write();
+    shell.update_pointer_position(position.to_local(output), output);
+    if seat.active_output() != *output {
+        seat.set_active_output(output);
+    }
+}
+
+/// Result of the last `surface_under` lookup of remote pointer input.
+#[derive(Debug)]
+pub(super) struct CachedUnder {
+    position: Point<f64, Global>,
+    output: Output,
+    under: Option<(PointerFocusTarget, Point<f64, Global>)>,
+}
+
+/// `State::surface_under` for remote pointer input.
+///
+/// Relative motion looks up the focus at where the previous motion ended, a
+/// second walk through all windows per event under fast remote motion. The
+/// result of the previous lookup is reused for that until the event loop
+/// iteration ends, input other than remote pointer motion comes in, or
+/// Wayland clients were dispatched, as those can move or map windows within
+/// the same iteration.
+fn surface_under(
+    state: &mut State,
+    position: Point<f64, Global>,
+    output: &Output,
+) -> Option<(PointerFocusTarget, Point<f64, Global>)> {
+    let Some(eis_state) = state.common.eis_state.as_mut() else {
+        return State::surface_under(position, output, &state.common.shell.read());
+    };
+    eis_state.stats().focus_lookups += 1;
+    if let Some(cached) = &eis_state.under_cache
+        && cached.position == position
+        && cached.output == *output
+    {
+        eis_state.stats().focus_cache_hits += 1;
+        return cached.under.clone();
+    }
+
+    let under = State::surface_under(position, output, &state.common.shell.read());
+    if eis_state.under_cache.is_none() {
+        eis_state.evlh.insert_idle(|state| {
+            if let Some(eis_state) = state.common.eis_state.as_mut() {
+                eis_state.under_cache = None;
+            }
+        });
+    }
+    eis_state.under_cache = Some(CachedUnder {
+        position,
+        output: output.clone(),
+        under: under.clone(),
+    });
+    under
+}
+
+/// Where relative motion from `current` to `position`, outside of all
+/// outputs, ends up according to the configured edge behavior. The result
+/// still gets clamped to an output.
+pub(super) fn edge_position(
+    edge: EisPointerEdge,
+    current: Point<f64, Global>,
+    position: Point<f64, Global>,
+    layout: Option<Rectangle<i32, Global>>,
+) -> Point<f64, Global> {
+    match edge {
+        EisPointerEdge::Clamp => position,
+        EisPointerEdge::Release => current,
+        EisPointerEdge::Wrap => {
+            let Some(layout) = layout.filter(|layout| layout.size.w > 0 && layout.size.h > 0)
+            else {
+                return position;
+            };
+            let layout = layout.to_f64();
+            (
+                layout.loc.x + (position.x - layout.loc.x).rem_euclid(layout.size.w),
+                layout.loc.y + (position.y - layout.loc.y).rem_euclid(layout.size.h),
+            )
+                .into()
+        }
+    }
+}
+
+/// Clamp `position` to the output geometry `geom`.
+///
+/// Like the pointer position, `geom` is in global logical coordinates, so a
+/// scaled output is clamped to the whole of its area, not its mode size.
+/// Outputs can transiently report an empty size during mode changes, which
+/// would make for an inverted range, so `position` is kept as-is for those.
+pub(super) fn clamp_to_output(
+    mut position: Point<f64, Global>,
+    geom: Rectangle<i32, Global>,
+) -> Point<f64, Global> {
+    if geom.size.w <= 0 || geom.size.h <= 0 {
+        return position;
+    }
+    let max_x = geom.loc.x.saturating_add(geom.size.w - 1);
+    let max_y = geom.loc.y.saturating_add(geom.size.h - 1);
+    position.x = position.x.clamp(f64::from(geom.loc.x), f64::from(max_x));
+    position.y = position.y.clamp(f64::from(geom.loc.y), f64::from(max_y));
+    position
+}
+
+/// Translate a remote button code, normalized by `normalize_button`, through
+/// the configured `button_map` and left/right swap.
+pub(super) fn map_button(config: &EisConfig, button: u32) -> u32 {
+    let button = normalize_button(button);
+    let button = config.button_map.get(&button).copied().unwrap_or(button);
+    match button {
+        BTN_LEFT if config.swap_left_right_buttons => BTN_RIGHT,
+        BTN_RIGHT if config.swap_left_right_buttons => BTN_LEFT,
+        button => button,
+    }
+}
+
+/// Turn a logical button index, as some clients send, into its evdev code.
+///
+/// Indices count in evdev order, so 0 is BTN_LEFT, 1 BTN_RIGHT and 2
+/// BTN_MIDDLE. The codes below BTN_LEFT are keys, never buttons, so evdev
+/// codes are told apart safely and pass through.
+pub(super) fn normalize_button(button: u32) -> u32 {
+    if button < MOUSE_BUTTONS {
+        BTN_LEFT + button
+    } else {
+        button
+    }
+}
+
+/// Find the output whose geometry contains `position`.
+///
+/// Geometries are half-open, so an output's right and bottom edges belong to
+/// its neighbour. Positions exactly on an outer edge of the layout, like an
+/// absolute click on the far corner, still resolve to the output they touch.
+pub(super) fn output_under<'a>(
+    outputs: impl Iterator<Item = &'a Output>,
+    position: Point<f64, Global>,
+) -> Option<Output> {
+    let outputs = outputs.collect::<Vec<_>>();
+    outputs
+        .iter()
+        .find(|output| output.geometry().to_f64().contains(position))
+        .or_else(|| {
+            outputs.iter().find(|output| {
+                let geom = output.geometry().to_f64();
+                position.x >= geom.loc.x
+                    && position.y >= geom.loc.y
+                    && position.x <= geom.loc.x + geom.size.w
+                    && position.y <= geom.loc.y + geom.size.h
+            })
+        })
+        .map(|output| (*output).clone())
+}
+
+/// Output closest to `position`, with `position` clamped into it.
+pub(super) fn nearest_output<'a>(
+    outputs: impl Iterator<Item = &'a Output>,
+    position: Point<f64, Global>,
+) -> Option<(Output, Point<f64, Global>)> {
+    outputs
+        .map(|output| (output, clamp_to_output(position, output.geometry())))
+        .min_by(|(_, a), (_, b)| {
+            let distance = |p: &Point<f64, Global>| (p.x - position.x).hypot(p.y - position.y);
+            distance(a).total_cmp(&distance(b))
+        })
+        .map(|(output, clamped)| (output.clone(), clamped))
+}
+
+/// Output under `position`, or else the nearest output with `position`
+/// clamped into it, so the cursor stays visible if a client's resolution
+/// does not match the layout.
+pub(super) fn place_on_outputs<'a, I: Iterator<Item = &'a Output>>(
+    outputs: impl Fn() -> I,
+    position: Point<f64, Global>,
+) -> Option<(Output, Point<f64, Global>)> {
+    match output_under(outputs(), position) {
+        Some(output) => Some((output, position)),
+        None => nearest_output(outputs(), position),
+    }
+}
+
+/// Output and position to move a pointer at `position` to if it is no
+/// longer on any of `outputs`, like after a scale or mode change shrank the
+/// output it was on: the nearest output, with `position` clamped into it.
+/// `None` while it is still on one of them.
+pub(super) fn revalidate_pointer<'a, I: Iterator<Item = &'a Output>>(
+    outputs: impl Fn() -> I,
+    position: Point<f64, Global>,
+) -> Option<(Output, Point<f64, Global>)> {
+    if output_under(outputs(), position).is_some() {
+        return None;
+    }
+    nearest_output(outputs(), position)
+}
+
+/// Clamp `position` into the confinement `region`, edges included.
+pub(super) fn confine(
+    position: Point<f64, Global>,
+    region: Rectangle<f64, Global>,
+) -> Point<f64, Global> {
+    Point::from((
+        position
+            .x
+            .clamp(region.loc.x, region.loc.x + region.size.w.max(0.0)),
+        position
+            .y
+            .clamp(region.loc.y, region.loc.y + region.size.h.max(0.0)),
+    ))
+}
+
+/// Resolve the surface under a given position, acquiring and releasing the
+/// shell read lock before returning so callers can use `&mut State`.
+#[allow(clippy::type_complexity)]
+fn resolve_touch_target(
+    state: &State,
+    x: f64,
+    y: f64,
+) -> (
+    smithay::input::Seat<State>,
+    Option<(
+        <State as smithay::input::SeatHandler>::PointerFocus,
+        smithay::utils::Point<f64, smithay::utils::Logical>,
+    )>,
+) {
+    let shell = state.common.shell.read();
+    let seat = shell.seats.last_active().clone();
+    let position = (x, y).into();
+    // Edge-inclusive like pointer placement, so taps on the far edge of the
+    // layout land on the output they touch
+    let under = output_under(shell.outputs(), position).and_then(|output| {
+        State::surface_under(position, &output, &shell)
+            .map(|(target, pos)| (target, pos.as_logical()))
+    });
+    (seat, under)
+}
+
+impl State {
+    /// Apply a pause or resume of remote input injection requested over
+    /// D-Bus. Pausing lifts everything remote clients hold, including a
+    /// keyboard grab, so the local user has the seat to themselves.
+    pub fn eis_injection_paused_changed(&mut self, paused: bool) {
+        info!(
+            paused,
+            "EIS input injection {}",
+            if paused { "paused" } else { "resumed" }
+        );
+        if !paused {
+            return;
+        }
+        let Some(eis_state) = self.common.eis_state.as_mut() else {
+            return;
+        };
+        let ids = eis_state.connections.keys().copied().collect::<Vec<_>>();
+        let time = self.common.clock.now().as_millis();
+        for id in ids {
+            let Some(mut eis_connection) = self
+                .common
+                .eis_state
+                .as_mut()
+                .and_then(|eis_state| eis_state.connections.remove(&id))
+            else {
+                continue;
+            };
+            release_held_input(
+                &mut SeatInjector::for_connection(self, &eis_connection),
+                &mut eis_connection,
+                time,
+            );
+            release_keyboard_grab(self, id);
+            if let Some(eis_state) = self.common.eis_state.as_mut() {
+                eis_state.connections.insert(id, eis_connection);
+            }
+        }
+    }
+
+    /// Switch dry-run mode on or off, as requested through `dry_run` in
+    /// `eis_config` or over D-Bus. Input clients hold was injected or not in
+    /// the previous mode, so it is released in that mode first.
+    pub fn eis_set_dry_run(&mut self, enabled: bool) {
+        let Some(eis_state) = self.common.eis_state.as_mut() else {
+            return;
+        };
+        eis_state.shared.dry_run.store(enabled, Ordering::Release);
+        if eis_state.dry_run == enabled {
+            return;
+        }
+        info!(
+            "EIS dry run {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        let ids = eis_state.connections.keys().copied().collect::<Vec<_>>();
+        let time = self.common.clock.now().as_millis();
+        for id in ids {
+            let Some(mut eis_connection) = self
+                .common
+                .eis_state
+                .as_mut()
+                .and_then(|eis_state| eis_state.connections.remove(&id))
+            else {
+                continue;
+            };
+            release_held_input(
+                &mut SeatInjector::for_connection(self, &eis_connection),
+                &mut eis_connection,
+                time,
+            );
+            if let Some(eis_state) = self.common.eis_state.as_mut() {
+                eis_state.connections.insert(id, eis_connection);
+            }
+        }
+        if let Some(eis_state) = self.common.eis_state.as_mut() {
+            eis_state.dry_run = enabled;
+        }
+    }
+
+    /// Activate the first window with `app_id`, giving it keyboard focus, and
+    /// with `click` also click at its center, as requested over D-Bus for
+    /// automation that must not depend on what is under the pointer. `found`
+    /// is told whether a window matched, and with `click` whether the click
+    /// was made, once it was, like by `eis_click`.
+    pub fn eis_focus_window(
+        &mut self,
+        app_id: &str,
+        click: bool,
+        found: async_channel::Sender<bool>,
+    ) {
+        let window = self
+            .common
+            .shell
+            .read()
+            .mapped()
+            .flat_map(|mapped| mapped.windows().map(|(surface, _)| surface))
+            .find(|surface| surface.app_id() == app_id);
+        let Some(window) = window else {
+            warn!(app_id, "No window to target remote input at");
+            let _ = found.try_send(false);
+            return;
+        };
+
+        if dry_run(self) {
+            log_dry_run(
+                self,
+                format_args!("activation of window {app_id}, click {click}"),
+            );
+            let _ = found.try_send(true);
+            return;
+        }
+
+        let seat = self.common.shell.read().seats.last_active().clone();
+        let dh = self.common.display_handle.clone();
+        ToplevelManagementHandler::activate(self, &dh, &window, Some(seat.clone()));
+        info!(app_id, click, "Targeted remote input at window");
+        if !click {
+            let _ = found.try_send(true);
+            return;
+        }
+
+        let shell = self.common.shell.read();
+        let Some(geometry) = shell
+            .mapped()
+            .find(|mapped| mapped.windows().any(|(surface, _)| surface == window))
+            .and_then(|mapped| shell.element_geometry(mapped))
+        else {
+            // Activated, but not on any workspace to click at
+            let _ = found.try_send(true);
+            return;
+        };
+        let center = Point::from((
+            f64::from(geometry.loc.x) + f64::from(geometry.size.w) / 2.0,
+            f64::from(geometry.loc.y) + f64::from(geometry.size.h) / 2.0,
+        ));
+        std::mem::drop(shell);
+
+        // Clicked like through `Click`, held to the same policy
+        self.eis_click(BTN_LEFT, center, found);
+    }
+
+    /// Move the pointer to `position` and click `button` there, holding it
+    /// for `CLICK_HOLD`, as requested over D-Bus. `done` is told once the
+    /// release was injected, or right away whether the click could not be
+    /// made, for being dropped like pointer input of a session or for lack of
+    /// an output.
+    pub fn eis_click(
+        &mut self,
+        button: u32,
+        position: Point<f64, Global>,
+        done: async_channel::Sender<bool>,
+    ) {
+        if drop_dbus_input(self, DeviceCapability::Button, true)
+            || drop_dbus_input(self, DeviceCapability::PointerAbsolute, true)
+        {
+            let _ = done.try_send(false);
+            return;
+        }
+        let button = normalize_button(button);
+        let shell = self.common.shell.read();
+        let seat = shell.seats.last_active().clone();
+        let placed = place_on_outputs(|| shell.outputs(), position);
+        std::mem::drop(shell);
+        let Some((output, position)) = placed else {
+            warn!("No output to click on");
+            let _ = done.try_send(false);
+            return;
+        };
+        if dry_run(self) {
+            log_dry_run(
+                self,
+                format_args!(
+                    "click of button {button:#x} at {position:?} on {}",
+                    output.name()
+                ),
+            );
+            let _ = done.try_send(true);
+            return;
+        }
+        info!(button, ?position, "Clicking for remote input");
+
+        let time = self.common.clock.now().as_millis();
+        move_pointer(
+            self,
+            &seat,
+            position,
+            &output,
+            true,
+            SERIAL_COUNTER.next_serial(),
+            time,
+        );
+        let mut injector = SeatInjector::new(self);
+        injector.button(
+            button,
+            ButtonState::Pressed,
+            SERIAL_COUNTER.next_serial(),
+            time,
+        );
+        injector.pointer_frame();
+        injector.remote_pointer_activity();
+
+        let result = self.common.event_loop_handle.insert_source(
+            Timer::from_duration(CLICK_HOLD),
+            move |_, _, state| {
+                let time = state.common.clock.now().as_millis();
+                let mut injector = SeatInjector::new(state);
+                // The press went to this seat, even if another one became
+                // active meanwhile
+                injector.seat = seat.clone();
+                injector.pointer_seat = seat.clone();
+                injector.button(
+                    button,
+                    ButtonState::Released,
+                    SERIAL_COUNTER.next_serial(),
+                    time,
+                );
+                injector.pointer_frame();
+                let _ = done.try_send(true);
+                TimeoutAction::Drop
+            },
+        );
+        if let Err(err) = result {
+            warn!("Failed to schedule click release: {}", err.error);
+            // Better a click without the hold than a button stuck down
+            let mut injector = SeatInjector::new(self);
+            injector.button(
+                button,
+                ButtonState::Released,
+                SERIAL_COUNTER.next_serial(),
+                time,
+            );
+            injector.pointer_frame();
+        }
+    }
+
+    /// Toggle whether pointer motion of the connection named `client`, or else
+    /// the most recent connection of that client name, leaves pointer focus
+    /// alone, as requested over D-Bus.
+    pub fn eis_set_pointer_no_focus(&mut self, client: &str, enabled: bool) {
+        let Some(eis_connection) = self.common.eis_state.as_mut().and_then(|eis_state| {
+            eis_state.connection_by_name_mut(client, "toggle pointer focus for")
+        }) else {
+            return;
+        };
+        eis_connection.pointer_no_focus = enabled;
+        info!(
+            connection = eis_connection.id,
+            name = %eis_connection.name,
+            enabled,
+            "Toggled EIS pointer motion without focus"
+        );
+    }
+
+    /// Toggle whether input of the connection named `client`, or else the
+    /// most recent connection of that client name, resets the idle timers,
+    /// as requested over D-Bus.
+    pub fn eis_set_wake_on_input(&mut self, client: &str, enabled: bool) {
+        let Some(eis_connection) = self.common.eis_state.as_mut().and_then(|eis_state| {
+            eis_state.connection_by_name_mut(client, "toggle wake on input for")
+        }) else {
+            return;
+        };
+        eis_connection.no_wake = !enabled;
+        info!(
+            connection = eis_connection.id,
+            name = %eis_connection.name,
+            enabled,
+            "Toggled EIS wake on input"
+        );
+    }
+
+    /// Bound the pointer of the connection named `client`, or else the most
+    /// recent connection of that client name, as requested over D-Bus:
+    /// confine its motion to a region as `[x, y, width, height]`, or lift the
+    /// confinement, and with `Bounds` also set what relative motion does at
+    /// the edges of the layout.
+    pub fn eis_set_pointer_confinement(
+        &mut self,
+        client: &str,
+        confinement: EisPointerConfinement,
+    ) {
+        let Some(eis_connection) = self.common.eis_state.as_mut().and_then(|eis_state| {
+            eis_state.connection_by_name_mut(client, "confine the pointer of")
+        }) else {
+            return;
+        };
+        let region = match confinement {
+            EisPointerConfinement::Region(region) => region,
+            EisPointerConfinement::Bounds { edge, region } => {
+                eis_connection.pointer_edge = edge;
+                region
+            }
+        };
+        eis_connection.pointer_confinement =
+            region.map(|[x, y, w, h]| Rectangle::new((x, y).into(), (w, h).into()));
+        info!(
+            connection = eis_connection.id,
+            name = %eis_connection.name,
+            edge = ?eis_connection.pointer_edge,
+            region = ?eis_connection.pointer_confinement,
+            "Set EIS pointer confinement"
+        );
+    }
+
+    /// Keep all pointer input of the connection named `client`, or else the
+    /// most recent connection of that client name, on the output named
+    /// `output`, or on all of them again, as requested over D-Bus. Devices
+    /// with absolute capabilities are announced again with just that output.
+    pub fn eis_set_target_output(&mut self, client: &str, output: Option<String>) {
+        let Some(eis_connection) = self.common.eis_state.as_mut().and_then(|eis_state| {
+            eis_state.connection_by_name_mut(client, "set the target output of")
+        }) else {
+            return;
+        };
+        info!(
+            connection = eis_connection.id,
+            name = %eis_connection.name,
+            ?output,
+            "Set EIS target output"
+        );
+        eis_connection.target_output = output;
+        self.common.eis_outputs_changed();
+    }
+
+    /// Move remote pointers the output layout changed under back onto the
+    /// outputs of their session, like after the output they were on got
+    /// scaled up and shrank, so the cursor does not end up off-surface.
+    /// Moving them sends clients under them the corrective motion and frame.
+    pub fn eis_revalidate_pointers(&mut self) {
+        let Some(eis_state) = self.common.eis_state.as_ref() else {
+            return;
+        };
+        let mut pointers: Vec<(Seat<State>, Option<String>, bool)> = Vec::new();
+        for eis_connection in eis_state.connections.values() {
+            if !eis_connection.devices.iter().any(|bound| {
+                bound
+                    .capabilities
+                    .intersects(DeviceCapability::Pointer | DeviceCapability::PointerAbsolute)
+            }) {
+                continue;
+            }
+            // Sessions without a pointer of their own share the active seat's
+            let seat = pointer_seat(self, eis_connection);
+            if pointers.iter().all(|(other, _, _)| *other != seat) {
+                pointers.push((
+                    seat,
+                    eis_connection.target_output.clone(),
+                    !eis_connection.pointer_no_focus,
+                ));
+            }
+        }
+
+        for (seat, target_output, focus) in pointers {
+            let Some(pointer) = seat.get_pointer() else {
+                continue;
+            };
+            let position = pointer.current_location().as_global();
+            let shell = self.common.shell.read();
+            let outputs = session_outputs(&shell, target_output.as_deref());
+            let Some((output, revalidated)) =
+                revalidate_pointer(|| outputs.iter().copied(), position)
+            else {
+                continue;
+            };
+            std::mem::drop(shell);
+            debug!(
+                seat = seat.name(),
+                output = %output.name(),
+                ?position,
+                ?revalidated,
+                "Moving EIS pointer back onto the changed output layout"
+            );
+            let time = self.common.clock.now().as_millis();
+            move_pointer(
+                self,
+                &seat,
+                revalidated,
+                &output,
+                focus,
+                SERIAL_COUNTER.next_serial(),
+                time,
+            );
+        }
+    }
+
+    /// Hide the cursor on local outputs while any remote pointer is being
+    /// emulated, if `hide_local_cursor` is set, and show it again otherwise.
+    pub fn eis_update_local_cursor(&mut self) {
+        let hide = self.common.config.cosmic_conf.eis_config.hide_local_cursor
+            && self
+                .common
+                .eis_state
+                .as_ref()
+                .is_some_and(|eis_state| eis_state.active_pointers() > 0);
+        if self.common.shell.read().hide_local_cursor == hide {
+            return;
+        }
+
+        let mut shell = self.common.shell.write();
+        shell.hide_local_cursor = hide;
+        let outputs = shell.outputs().cloned().collect::<Vec<_>>();
+        std::mem::drop(shell);
+        debug!(hide, "Toggling local cursor for remote pointer");
+        for output in outputs {
+            self.backend.schedule_render(&output);
+        }
+    }
+}
@@ -0,0 +1,289 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Keymaps of EIS devices, and typing keysyms through them.
+
+use cosmic_comp_config::XkbConfig;
+use reis::event::DeviceCapability;
+use smithay::{
+    backend::input::KeyState,
+    input::keyboard::{FilterResult, KeyboardHandle, Keycode, ModifiersState},
+    utils::SERIAL_COUNTER,
+};
+use tracing::{debug, error, warn};
+use xkbcommon::xkb::{self, Keysym};
+
+use crate::state::State;
+
+use super::{
+    CAPSLOCK_SCANCODE, NUMLOCK_SCANCODE,
+    inject::{drop_dbus_input, dry_run, log_dry_run},
+};
+
+/// Compile the keymap of the compositor's XKB configuration (RMLVO names).
+fn compile_keymap(conf: &XkbConfig) -> Option<xkb::Keymap> {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    xkb::Keymap::new_from_names(
+        &context,
+        &conf.rules,
+        &conf.model,
+        &conf.layout,
+        &conf.variant,
+        conf.options.clone(),
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    )
+}
+
+/// Modifiers that select the shift levels of a key, as the keysyms of the
+/// keys asserting them.
+const LEVEL_MODIFIERS: [Keysym; 3] = [
+    Keysym::Shift_L,
+    Keysym::ISO_Level3_Shift,
+    Keysym::ISO_Level5_Shift,
+];
+
+/// Key presses producing a keysym on a keymap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct KeysymPress {
+    /// Modifier keys to hold around `key`, e.g. AltGr for level 3.
+    modifiers: Vec<Keycode>,
+    key: Keycode,
+}
+
+fn keycodes(keymap: &xkb::Keymap) -> impl Iterator<Item = Keycode> {
+    (keymap.min_keycode().raw()..=keymap.max_keycode().raw()).map(Keycode::new)
+}
+
+/// Find a key producing `keysym` on the base level of `layout`.
+pub(super) fn find_key(keymap: &xkb::Keymap, layout: u32, keysym: Keysym) -> Option<Keycode> {
+    keycodes(keymap).find(|key| {
+        keymap
+            .key_get_syms_by_level(*key, layout, 0)
+            .contains(&keysym)
+    })
+}
+
+/// Find the key and level modifiers producing `keysym` on `layout`, with the
+/// `locked` modifiers (e.g. CapsLock) in effect.
+///
+/// Levels are reached by holding Shift, the level 3 shift (AltGr) and the
+/// level 5 shift, alone or combined. Each combination is tried on a scratch
+/// XKB state, fewest modifiers first, so `€` on a German layout resolves to
+/// AltGr+E and `e` to the plain key.
+pub(super) fn resolve_keysym(
+    keymap: &xkb::Keymap,
+    layout: u32,
+    locked: u32,
+    keysym: Keysym,
+) -> Option<KeysymPress> {
+    let modifier_keys = LEVEL_MODIFIERS.map(|modifier| find_key(keymap, layout, modifier));
+
+    let mut combinations = (0..1u32 << LEVEL_MODIFIERS.len()).collect::<Vec<_>>();
+    combinations.sort_by_key(|combination| combination.count_ones());
+
+    for combination in combinations {
+        // Skip levels whose modifiers the keymap has no key for
+        let Some(modifiers) = (0..LEVEL_MODIFIERS.len())
+            .filter(|i| combination & (1 << i) != 0)
+            .map(|i| modifier_keys[i])
+            .collect::<Option<Vec<_>>>()
+        else {
+            continue;
+        };
+
+        let mut xkb_state = xkb::State::new(keymap);
+        xkb_state.update_mask(0, 0, locked, 0, 0, layout);
+        for key in &modifiers {
+            xkb_state.update_key(*key, xkb::KeyDirection::Down);
+        }
+        if let Some(key) = keycodes(keymap).find(|key| xkb_state.key_get_one_sym(*key) == keysym) {
+            return Some(KeysymPress { modifiers, key });
+        }
+    }
+    None
+}
+
+/// Name of the key `scratch_keymap` maps keysyms on.
+const SCRATCH_KEY: &str = "EISK";
+
+/// `keymap` with a keycode no key uses mapped to `keysym`, as a keymap
+/// string, and that keycode, for typing keysyms no key of the layout
+/// produces.
+pub(super) fn scratch_keymap(keymap: &xkb::Keymap, keysym: Keysym) -> Option<(String, Keycode)> {
+    if keysym == Keysym::NoSymbol {
+        return None;
+    }
+    let key = keycodes(keymap).find(|key| keymap.key_get_name(*key).is_none())?;
+    let name = xkb::keysym_get_name(keysym);
+
+    let mut scratch = String::new();
+    for line in keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1).lines() {
+        scratch.push_str(line);
+        scratch.push('\n');
+        let line = line.trim_start();
+        if line.starts_with("xkb_keycodes") {
+            scratch.push_str(&format!("\t<{SCRATCH_KEY}> = {};\n", key.raw()));
+        } else if line.starts_with("xkb_symbols") {
+            scratch.push_str(&format!("\tkey <{SCRATCH_KEY}> {{ [ {name} ] }};\n"));
+        }
+    }
+
+    // Clients get the keymap as is, so it has to compile
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    xkb::Keymap::new_from_string(
+        &context,
+        scratch.clone(),
+        xkb::KEYMAP_FORMAT_TEXT_V1,
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    )?;
+    Some((scratch, key))
+}
+
+/// Prepare the compositor's XKB keymap as a sealed memfd for sending to EIS clients.
+///
+/// Compiles the keymap from the compositor's current XKB configuration (RMLVO names),
+/// writes it to a memfd with a null terminator, and seals the fd. Returns the fd and
+/// total size (including null terminator), or `None` if keymap creation fails.
+pub(super) fn prepare_xkb_keymap_fd(conf: &XkbConfig) -> Option<(std::os::fd::OwnedFd, u32)> {
+    use std::os::fd::FromRawFd;
+
+    let keymap = compile_keymap(conf)?;
+    let keymap_string = keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1);
+    let keymap_bytes = keymap_string.as_bytes();
+    let size = (keymap_bytes.len() + 1) as u32; // +1 for null terminator
+
+    // Create a sealed memfd for the keymap data
+    let name = c"eis-keymap";
+    let raw_fd =
+        unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+    if raw_fd < 0 {
+        warn!("Failed to create memfd for EIS keymap");
+        return None;
+    }
+    let mut file = unsafe { std::fs::File::from_raw_fd(raw_fd) };
+
+    // Write keymap string + null terminator
+    use std::io::Write;
+    if file.write_all(keymap_bytes).is_err() || file.write_all(&[0]).is_err() {
+        warn!("Failed to write keymap to memfd");
+        return None;
+    }
+
+    // Seal the memfd to prevent modification (best-effort)
+    unsafe {
+        libc::fcntl(
+            std::os::fd::AsRawFd::as_raw_fd(&file),
+            libc::F_ADD_SEALS,
+            libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL,
+        );
+    }
+
+    let owned_fd: std::os::fd::OwnedFd = file.into();
+    Some((owned_fd, size))
+}
+
+impl State {
+    /// Type `keysym` on the keyboard of the active seat, holding Shift, AltGr
+    /// (level 3 shift) or the level 5 shift around the key as the keymap
+    /// requires, e.g. for `€` on a German layout.
+    ///
+    /// Keysyms no key of the current layout produces are typed on a free
+    /// keycode, with a scratch keymap mapping it to `keysym` loaded for the
+    /// duration of the key press. Returns `false` if that fails as well, or
+    /// if the keys are dropped like keyboard input of a session.
+    pub fn eis_type_keysym(&mut self, keysym: Keysym) -> bool {
+        if drop_dbus_input(self, DeviceCapability::Keyboard, false) {
+            return false;
+        }
+        let seat = self.common.shell.read().seats.last_active().clone();
+        let Some(keyboard) = seat.get_keyboard() else {
+            return false;
+        };
+        if dry_run(self) {
+            let focus = keyboard.current_focus();
+            log_dry_run(self, format_args!("keysym to {focus:?}"));
+            return true;
+        }
+        let Some(keymap) = compile_keymap(&self.common.config.cosmic_conf.xkb_config) else {
+            return false;
+        };
+        let modifier_state = keyboard.modifier_state();
+        let mods = modifier_state.serialized;
+        let (press, scratch) =
+            match resolve_keysym(&keymap, mods.layout_effective, mods.locked, keysym) {
+                Some(press) => (press, false),
+                None => {
+                    debug!(?keysym, "No key produces keysym on the current layout");
+                    let Some((scratch, key)) = scratch_keymap(&keymap, keysym) else {
+                        warn!(?keysym, "Failed to map keysym on a scratch key");
+                        return false;
+                    };
+                    if let Err(err) = keyboard.set_keymap_from_string(self, scratch) {
+                        warn!(?err, "Failed to load scratch keymap for keysym");
+                        return false;
+                    }
+                    let press = KeysymPress {
+                        modifiers: Vec::new(),
+                        key,
+                    };
+                    (press, true)
+                }
+            };
+
+        let time = self.common.clock.now().as_millis();
+        let input = |state: &mut State, key: Keycode, key_state: KeyState| {
+            keyboard.input(
+                state,
+                key,
+                key_state,
+                SERIAL_COUNTER.next_serial(),
+                time,
+                |_, _, _| FilterResult::Forward::<bool>,
+            );
+        };
+        for key in &press.modifiers {
+            input(self, *key, KeyState::Pressed);
+        }
+        input(self, press.key, KeyState::Pressed);
+        input(self, press.key, KeyState::Released);
+        for key in press.modifiers.iter().rev() {
+            input(self, *key, KeyState::Released);
+        }
+
+        if scratch {
+            restore_keymap(self, &keyboard, &keymap, &modifier_state);
+        }
+        true
+    }
+}
+
+/// Load the keymap of the config on `keyboard` again once a keysym was typed
+/// on a scratch keymap, falling back to `keymap` the scratch keymap was made
+/// from, so the scratch key never stays mapped for other clients. The lock
+/// modifiers are set back to those `before` typing.
+fn restore_keymap(
+    state: &mut State,
+    keyboard: &KeyboardHandle<State>,
+    keymap: &xkb::Keymap,
+    before: &ModifiersState,
+) {
+    let xkb_config = state.common.config.cosmic_conf.xkb_config.clone();
+    if let Err(err) = keyboard.set_xkb_config(state, crate::config::xkb_config_to_wl(&xkb_config)) {
+        warn!(
+            ?err,
+            "Failed to load the configured keymap after typing a keysym"
+        );
+        if let Err(err) =
+            keyboard.set_keymap_from_string(state, keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1))
+        {
+            error!(?err, "Failed to restore keymap after typing a keysym");
+        }
+    }
+    // Like for a config change, loading the keymap may lose the locks
+    let restored = keyboard.modifier_state();
+    if restored.num_lock != before.num_lock {
+        crate::config::change_modifier_state(keyboard, NUMLOCK_SCANCODE, state);
+    }
+    if restored.caps_lock != before.caps_lock {
+        crate::config::change_modifier_state(keyboard, CAPSLOCK_SCANCODE, state);
+    }
+}
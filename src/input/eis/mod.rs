@@ -0,0 +1,1224 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! EIS (Emulated Input Server) receiver for remote desktop input injection.
+//!
+//! Accepts input events from EIS clients (e.g., xdg-desktop-portal-cosmic
+//! RemoteDesktop sessions) and injects them into the compositor's input stack
+//! using the same Smithay APIs as the normal input pipeline.
+//!
+//! Uses `reis::calloop::EisRequestSource` to process EIS protocol events
+//! directly on the compositor's calloop event loop (no background threads).
+
+use calloop::timer::{TimeoutAction, Timer};
+use cosmic_comp_config::{
+    XkbConfig,
+    eis::{EisConfig, EisPointerEdge, MAX_EVDEV_CODE},
+};
+use enumflags2::BitFlags;
+use reis::{eis, event::DeviceCapability, request::EisRequest};
+use serde::{Deserialize, Serialize};
+use smithay::{
+    input::Seat,
+    utils::{Point, Rectangle, SERIAL_COUNTER, Serial},
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::sync::{
+    Arc, Mutex, MutexGuard, OnceLock,
+    atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info, trace, warn};
+
+use crate::shell::Shell;
+use crate::state::{Common, State};
+use crate::utils::geometry::Global;
+
+mod connection;
+mod grab;
+mod inject;
+mod keysym;
+mod replay;
+mod selection;
+#[cfg(test)]
+mod tests;
+pub use self::replay::{RecordedInput, read_recording};
+use self::{
+    connection::{
+        add_device, capability_names, flush_connection, ignore_sigpipe, output_regions,
+        process_deferred_requests, summarize_unhandled, variant_name,
+    },
+    inject::CachedUnder,
+    keysym::prepare_xkb_keymap_fd,
+    replay::InputRecorder,
+};
+
+/// Linux codes of the primary pointer buttons.
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+
+/// How long `Click` holds the button down, like a quick click by hand.
+const CLICK_HOLD: Duration = Duration::from_millis(60);
+
+/// Buttons of the evdev mouse range, BTN_LEFT to BTN_TASK.
+const MOUSE_BUTTONS: u32 = 8;
+
+/// Linux scancodes of the lock keys (KEY_NUMLOCK, KEY_CAPSLOCK).
+const NUMLOCK_SCANCODE: u32 = 69;
+const CAPSLOCK_SCANCODE: u32 = 58;
+
+/// Client names and applications are truncated to this many characters to
+/// prevent log flooding.
+const MAX_CLIENT_NAME_CHARS: usize = 128;
+
+/// Maximum touch slot ID (generous upper bound; real devices rarely exceed 20).
+const MAX_TOUCH_ID: u32 = 256;
+
+/// Continuous scroll distance of one wheel click, as libinput reports it.
+const WHEEL_CLICK_DISTANCE: f64 = 15.0;
+
+/// Size of the range of seat touch slots each connection gets.
+const TOUCH_SLOTS_PER_CONNECTION: u32 = MAX_TOUCH_ID + 1;
+
+/// Scroll events closer together than this (in ms) are accelerated.
+const SCROLL_ACCEL_WINDOW_MS: u32 = 100;
+
+/// Upper bound of the scroll acceleration factor for back-to-back events.
+const SCROLL_ACCEL_MAX: f64 = 3.0;
+
+/// Pointer speed (logical px/ms) below which adaptive acceleration is 1:1.
+const POINTER_ACCEL_THRESHOLD: f64 = 0.4;
+
+/// Additional gain per px/ms above `POINTER_ACCEL_THRESHOLD`.
+const POINTER_ACCEL_SLOPE: f64 = 0.8;
+
+/// Upper bound of the adaptive pointer acceleration factor.
+const POINTER_ACCEL_MAX: f64 = 3.0;
+
+/// Time after which relative motion held back by `pointer_smoothing` is
+/// applied, one frame at 60Hz.
+const MOTION_SMOOTHING_WINDOW: Duration = Duration::from_millis(16);
+
+/// Serial of the first event sent to a client. Interface versions are not
+/// pinned here, reis negotiates them during the handshake.
+const INITIAL_SERIAL: u32 = 0;
+
+/// Identical protocol errors of a client are logged at most once per interval.
+const PROTOCOL_ERROR_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Protocol errors after which a client is disconnected.
+const MAX_PROTOCOL_ERRORS: u32 = 100;
+
+/// Requests of kinds not handled here are summarized at most once per
+/// interval.
+const UNHANDLED_SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Attempts at setting up a connection while the compositor is out of file
+/// descriptors, and the delay between them, so a momentary spike does not
+/// lose the session.
+const FD_EXHAUSTION_ATTEMPTS: u32 = 5;
+const FD_EXHAUSTION_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Input events a connection may send before its first device starts
+/// emulating, held back until then. Further ones are dropped.
+const MAX_EARLY_INPUT: usize = 64;
+
+/// Pastes of the remote selection kept waiting for `SelectionWrite`, the
+/// oldest are dropped beyond this.
+const MAX_SELECTION_TRANSFERS: usize = 16;
+
+/// Size of recordings `ReplayInput` reads, larger files are refused.
+const MAX_RECORDING_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Sessions getting a pointer of their own with `pointer_per_session`, any
+/// further ones share the pointer of the active seat.
+const MAX_SESSION_POINTERS: usize = 4;
+
+/// Target of the metric events, in the field naming of metrics layers like
+/// `tracing-opentelemetry`'s. They are emitted at trace level, so they cost
+/// next to nothing unless a subscriber enables this target.
+const METRICS_TARGET: &str = "cosmic_comp::eis::metrics";
+
+/// Age in ms beyond which a client's frame timestamp is distrusted, as the
+/// client's clock then cannot be the compositor's.
+const MAX_FRAME_TIME_AGE_MS: u32 = 1000;
+
+/// Number of power-of-two injection latency buckets.
+const LATENCY_BUCKETS: usize = 21;
+
+/// Device interfaces offered to clients and the seat capability each backs.
+const DEVICE_INTERFACES: [(&str, DeviceCapability); 6] = [
+    ("ei_keyboard", DeviceCapability::Keyboard),
+    ("ei_pointer", DeviceCapability::Pointer),
+    ("ei_pointer_absolute", DeviceCapability::PointerAbsolute),
+    ("ei_button", DeviceCapability::Button),
+    ("ei_scroll", DeviceCapability::Scroll),
+    ("ei_touchscreen", DeviceCapability::Touch),
+];
+
+/// Requests handled here, as named by `request_name`. Others are only
+/// counted as unhandled.
+const HANDLED_REQUESTS: [&str; 17] = [
+    "disconnect",
+    "bind",
+    "frame",
+    "start_emulating",
+    "stop_emulating",
+    "pointer_motion",
+    "pointer_motion_absolute",
+    "button",
+    "scroll_delta",
+    "scroll_stop",
+    "scroll_cancel",
+    "scroll_discrete",
+    "keyboard_key",
+    "touch_down",
+    "touch_motion",
+    "touch_up",
+    "touch_cancel",
+];
+
+/// Per-connection state, kept in the `EisState` registry.
+///
+/// While one of its requests is processed, a connection is taken out of the
+/// registry so handlers can hold `&mut State` and `&mut EisConnection` at once.
+#[derive(Debug, Default)]
+struct EisConnection {
+    /// Id of this connection in the registry.
+    id: u64,
+    /// Handle to the client connection, set once the handshake completed.
+    connection: Option<reis::request::Connection>,
+    /// Seat announced to the client once the handshake completed.
+    seat: Option<reis::request::Seat>,
+    /// Capabilities the seat was announced with.
+    seat_capabilities: BitFlags<DeviceCapability>,
+    /// Devices created for the client's seat binds.
+    devices: Vec<BoundDevice>,
+    /// Output layout last announced to this client as device regions.
+    regions: Vec<OutputRegion>,
+    /// Time (ms) of the last scroll event, used for rate-based acceleration.
+    last_scroll_time: Option<u32>,
+    /// Time (ms) of the last relative motion event, used for adaptive acceleration.
+    last_motion_time: Option<u32>,
+    /// Relative motion held back by `pointer_smoothing`, not applied yet.
+    held_motion: (f64, f64),
+    /// Timer applying `held_motion` once `MOTION_SMOOTHING_WINDOW` passed.
+    held_motion_timer: Option<calloop::RegistrationToken>,
+    /// Whether any device of this connection has started emulating yet.
+    started_emulating: bool,
+    /// Input that arrived before any device started emulating, injected once
+    /// the session is set up.
+    early_input: Vec<RemoteInput>,
+    /// Seat of its own the pointer of this client moves on, with
+    /// `pointer_per_session`.
+    pointer_seat: Option<Seat<State>>,
+    /// Whether the local user approved this client's input, with
+    /// `require_consent`.
+    consent_granted: bool,
+    /// Timer denying consent once `consent_timeout_secs` passed, while the
+    /// client waits for it.
+    consent_timer: Option<calloop::RegistrationToken>,
+    /// Whether pointer motion of this client leaves pointer focus alone,
+    /// moving only the cursor, for overlays driving it.
+    pointer_no_focus: bool,
+    /// Whether input of this client leaves the idle timers alone, for
+    /// passive sessions that must not keep the machine awake.
+    no_wake: bool,
+    /// Region the pointer motion of this client is confined to.
+    pointer_confinement: Option<Rectangle<f64, Global>>,
+    /// What relative motion of this client does at the edges of the layout,
+    /// overriding `pointer_edge` of the config.
+    pointer_edge: Option<EisPointerEdge>,
+    /// Name of the output all pointer input of this client is kept on, and
+    /// the only one announced to it, for sessions sharing a single output.
+    target_output: Option<String>,
+    /// Key identifying this client across reconnects (its truncated name).
+    session_key: Option<String>,
+    /// Executable of the process that created the client's socket, if known.
+    application: Option<String>,
+    /// Name of this connection in logs and D-Bus calls: the client name, with
+    /// a suffix if another live connection uses the same name.
+    name: String,
+    /// Pointer position saved by a previous connection of the same client,
+    /// applied once this connection starts emulating.
+    restore_position: Option<Point<f64, Global>>,
+    /// Keys this client holds down.
+    held_keys: HeldKeys,
+    /// Pointer buttons this client holds down.
+    held_buttons: BTreeSet<u32>,
+    /// Touch points this client has down, by the client's touch id.
+    active_touches: BTreeSet<u32>,
+    /// First seat touch slot of the range this connection's touch ids are
+    /// mapped into, so touches of different clients never collide.
+    touch_slot_base: u32,
+    /// Whether touch events were injected since the last frame, which is
+    /// sent once the client ends its frame.
+    touch_frame_pending: bool,
+    /// Scrolling since the last frame, injected once the client ends its
+    /// frame.
+    pending_scroll: Option<ScrollFrame>,
+    /// Calloop source processing this connection's requests.
+    source: Option<calloop::RegistrationToken>,
+    /// Protocol errors of this client, for throttling their logging.
+    protocol_errors: ProtocolErrors,
+    /// When the client last sent a request, in milliseconds since the Unix
+    /// epoch. Shared with its entry in the session listing.
+    last_activity: Arc<AtomicU64>,
+    /// Duplicate of the client socket, polled for writability when a flush
+    /// could not complete.
+    socket: Option<UnixStream>,
+    /// Writability source finishing a flush that hit `EAGAIN`.
+    pending_flush: Option<calloop::RegistrationToken>,
+    /// What was first queued for the client since the last flush, if
+    /// anything. Flushed once the current request is processed.
+    unflushed: Option<&'static str>,
+    /// Whether the session is over, either side having ended it. The
+    /// connection is dropped once the current request is processed.
+    closed: bool,
+    /// Versions of the device interfaces negotiated with the client, for
+    /// guarding version specific behavior.
+    interface_versions: HashMap<&'static str, u32>,
+    /// Serial of the last event injected for this client.
+    last_serial: Option<Serial>,
+}
+
+/// Protocol errors of a client, counted so a misbehaving client can neither
+/// flood the log nor keep going forever.
+#[derive(Debug, Default)]
+struct ProtocolErrors {
+    total: u32,
+    /// Last logged error and when it was logged.
+    last: Option<(String, Instant)>,
+    /// Errors identical to `last` not logged since.
+    suppressed: u32,
+}
+
+impl ProtocolErrors {
+    /// Count `error`. Returns `None` if it repeats the last logged error
+    /// within `PROTOCOL_ERROR_LOG_INTERVAL` and should not be logged, else the
+    /// number of errors suppressed before it.
+    fn record(&mut self, error: &str, now: Instant) -> Option<u32> {
+        self.total = self.total.saturating_add(1);
+        if let Some((last, logged)) = &self.last
+            && last == error
+            && now.duration_since(*logged) < PROTOCOL_ERROR_LOG_INTERVAL
+        {
+            self.suppressed += 1;
+            return None;
+        }
+        self.last = Some((error.to_string(), now));
+        Some(std::mem::take(&mut self.suppressed))
+    }
+
+    /// Whether the client made enough errors to be disconnected.
+    fn limit_reached(&self) -> bool {
+        self.total >= MAX_PROTOCOL_ERRORS
+    }
+}
+
+/// Keys an EIS client holds down, kept apart from the seat's own state.
+///
+/// Smithay tracks pressed keys per seat, so a remote press and release of a
+/// key the local user already holds (e.g. Shift) would clear the local press,
+/// and a remote press forwarded while the key is up must be the one released
+/// on disconnect. Each held key remembers whether its press reached the seat.
+#[derive(Debug, Default)]
+struct HeldKeys(HashMap<u32, bool>);
+
+impl HeldKeys {
+    /// Record a key event of the client and decide whether it reaches the
+    /// seat, given whether `key` is already down on the seat.
+    ///
+    /// Presses of keys the client or the seat already hold, and releases of
+    /// keys whose press didn't reach the seat, are dropped.
+    fn filter(&mut self, key: u32, pressed: bool, down_on_seat: bool) -> bool {
+        if pressed {
+            if self.0.contains_key(&key) {
+                return false;
+            }
+            self.0.insert(key, !down_on_seat);
+            !down_on_seat
+        } else {
+            self.0.remove(&key).unwrap_or(false)
+        }
+    }
+
+    /// Whether a press of `key` repeats one of the client that reached the
+    /// seat.
+    fn is_repeat(&self, key: u32, pressed: bool) -> bool {
+        pressed && self.0.get(&key).copied().unwrap_or(false)
+    }
+
+    /// Forget all held keys, returning those whose press reached the seat.
+    fn release_all(&mut self) -> Vec<u32> {
+        let mut keys = self
+            .0
+            .drain()
+            .filter_map(|(key, forwarded)| forwarded.then_some(key))
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    }
+}
+
+impl EisConnection {
+    /// Note that `what` was queued for the client, so a single flush covers
+    /// everything a request produces.
+    fn queue_flush(&mut self, what: &'static str) {
+        self.unflushed.get_or_insert(what);
+    }
+
+    /// Serial for the next event injected for this client.
+    ///
+    /// Like local input, every injected event gets a fresh serial from the
+    /// global counter, events of the same EIS frame included, so a release
+    /// always carries a later serial than its press and the serials of one
+    /// connection only ever increase.
+    fn next_serial(&mut self) -> Serial {
+        let serial = SERIAL_COUNTER.next_serial();
+        debug_assert!(self.last_serial.is_none_or(|last| serial > last));
+        self.last_serial = Some(serial);
+        serial
+    }
+
+    /// Seat touch slot of the client's `touch_id`, within the block of
+    /// `TOUCH_SLOTS_PER_CONNECTION` slots starting at `touch_slot_base` for a
+    /// validated `touch_id`, or `None` if it overflows for one that was not.
+    fn touch_slot(&self, touch_id: u32) -> Option<u32> {
+        self.touch_slot_base.checked_add(touch_id)
+    }
+
+    fn has_keyboard(&self) -> bool {
+        self.devices
+            .iter()
+            .any(|bound| bound.capabilities.contains(DeviceCapability::Keyboard))
+    }
+
+    fn has_pointer(&self) -> bool {
+        self.devices.iter().any(|bound| {
+            bound
+                .capabilities
+                .intersects(DeviceCapability::Pointer | DeviceCapability::PointerAbsolute)
+        })
+    }
+
+    /// Hold `input` back until the connection starts emulating, unless
+    /// `MAX_EARLY_INPUT` events are waiting already. Returns whether it was
+    /// queued.
+    fn queue_early_input(&mut self, input: RemoteInput) -> bool {
+        if self.early_input.len() >= MAX_EARLY_INPUT {
+            return false;
+        }
+        self.early_input.push(input);
+        true
+    }
+
+    /// Capabilities of the device interfaces negotiated with the client.
+    fn negotiated_capabilities(&self) -> BitFlags<DeviceCapability> {
+        DEVICE_INTERFACES
+            .iter()
+            .filter(|(interface, _)| self.interface_versions.contains_key(interface))
+            .fold(BitFlags::empty(), |capabilities, (_, capability)| {
+                capabilities | *capability
+            })
+    }
+
+    fn set_emulating(&mut self, device: &reis::request::Device, emulating: bool) {
+        if let Some(bound) = self
+            .devices
+            .iter_mut()
+            .find(|bound| &bound.device == device)
+        {
+            bound.emulating = emulating;
+        }
+    }
+
+    /// Number of pointer devices the client is currently emulating on.
+    fn active_pointers(&self) -> usize {
+        self.devices
+            .iter()
+            .filter(|bound| {
+                bound.emulating
+                    && bound
+                        .capabilities
+                        .intersects(DeviceCapability::Pointer | DeviceCapability::PointerAbsolute)
+            })
+            .count()
+    }
+}
+
+/// Scrolling of one frame of a client, after scaling. Clients scrolling
+/// diagonally may send each axis as a request of its own, and wheel clicks
+/// apart from the distance, so they are summed up to end up in a single axis
+/// frame, like from a local touchpad or wheel.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct ScrollFrame {
+    dx: f64,
+    dy: f64,
+    /// Wheel clicks, in 1/120ths of a click.
+    v120_x: i32,
+    v120_y: i32,
+    /// Time of the last scroll event of the frame.
+    time: u32,
+}
+
+/// A device we created in response to a client `Bind`.
+#[derive(Debug)]
+struct BoundDevice {
+    seat: reis::request::Seat,
+    device: reis::request::Device,
+    capabilities: BitFlags<DeviceCapability>,
+    /// Whether the client is between start and stop emulating on this device.
+    emulating: bool,
+}
+
+/// An output as announced to EIS clients through device regions.
+#[derive(Debug, Clone, PartialEq)]
+struct OutputRegion {
+    geometry: Rectangle<i32, Global>,
+    scale: f64,
+}
+
+/// Pointer position left behind by a disconnected client.
+#[derive(Debug)]
+struct SavedPointer {
+    position: Point<f64, Global>,
+    saved_at: Instant,
+}
+
+/// Manages EIS connections on the compositor's calloop event loop.
+#[derive(Debug)]
+pub struct EisState {
+    evlh: calloop::LoopHandle<'static, State>,
+    /// Tunables from the compositor config, kept in sync by `set_config`.
+    config: EisConfig,
+    /// Registry of live connections, by connection id.
+    connections: HashMap<u64, EisConnection>,
+    next_connection_id: u64,
+    /// Last pointer positions of recently disconnected clients, by session key.
+    saved_pointers: HashMap<String, SavedPointer>,
+    /// Connection holding the exclusive keyboard grab, if any.
+    keyboard_grab: Option<u64>,
+    /// Connection whose key event is currently being injected.
+    injecting_keys: Option<u64>,
+    /// Timer reverting the remote cursor theme after remote pointer input stops.
+    remote_cursor_timer: Option<calloop::RegistrationToken>,
+    /// Requests held back until the current event loop iteration dispatched
+    /// local input, with the connection id and the time they arrived.
+    deferred: Vec<(u64, EisRequest, Instant)>,
+    /// Recorder of remote input, while `record_input_path` is set.
+    recorder: Option<InputRecorder>,
+    /// Seats created for the pointers of sessions with
+    /// `pointer_per_session`, lent to one session at a time. Seats are kept
+    /// once created, as clients may have bound them.
+    pointer_seats: Vec<Seat<State>>,
+    /// Requests of kinds not handled here since the last summary, by variant
+    /// name.
+    unhandled: BTreeMap<String, u64>,
+    /// Timer logging the summary of `unhandled`.
+    unhandled_timer: Option<calloop::RegistrationToken>,
+    /// Serial of the next paste of the remote selection.
+    next_selection_serial: u32,
+    /// Last focus lookup of remote pointer input, reused within the current
+    /// event loop iteration.
+    under_cache: Option<CachedUnder>,
+    /// Whether the connections in use crossed `connection_warning_percent`
+    /// of the limit, and were warned about.
+    connection_limit_warned: bool,
+    /// Touch slot ranges, by base, of the connections being replayed, which
+    /// are not in `connections` but must not be shared with them.
+    replay_touch_slots: BTreeSet<u32>,
+    /// Whether remote input is resolved but not injected. Follows
+    /// `EisShared::dry_run` once the input held in the previous mode was
+    /// released.
+    dry_run: bool,
+    /// State shared with the D-Bus interface.
+    shared: Arc<EisShared>,
+}
+
+/// State shared between the compositor and the EIS D-Bus interface.
+#[derive(Debug, Default)]
+pub struct EisShared {
+    /// Injection latency, recorded with `measure_injection_latency`.
+    pub latency: Mutex<LatencyHistogram>,
+    /// Whether injection of remote input is paused.
+    pub injection_paused: AtomicBool,
+    /// Whether remote input is to be resolved but not injected, as last
+    /// requested through `dry_run` in `eis_config` or `SetDryRun`.
+    pub dry_run: AtomicBool,
+    /// Whether `dry_run` was set through `SetDryRun`, and so takes precedence
+    /// over `eis_config` when the EIS state is created.
+    pub dry_run_requested: AtomicBool,
+    /// Whether any device of a connected client is emulating input.
+    pub remote_input_active: AtomicBool,
+    /// Connections currently in the registry.
+    pub active_sessions: AtomicU32,
+    /// Counters, updated by `EisState` as it goes.
+    pub statistics: Mutex<EisStatistics>,
+    /// Clients past the handshake, by connection id.
+    pub sessions: Mutex<BTreeMap<u64, EisSession>>,
+    /// D-Bus connection the interface is served on, once registered, for
+    /// emitting signals from the compositor.
+    pub dbus: OnceLock<zbus::Connection>,
+    /// Pastes of the remote selection waiting for the remote desktop to write
+    /// it, by serial.
+    pub selection_transfers: Mutex<BTreeMap<u32, OwnedFd>>,
+}
+
+/// Credentials of the process that created a client's socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EisPeer {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// How the pointer of a session is to be bounded, as requested over D-Bus.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EisPointerConfinement {
+    /// Confine the pointer to `[x, y, width, height]`, or lift the
+    /// confinement, leaving the edge mode alone.
+    Region(Option<[f64; 4]>),
+    /// Set the edge mode, `None` following the config, along with the
+    /// region like `Region`.
+    Bounds {
+        edge: Option<EisPointerEdge>,
+        region: Option<[f64; 4]>,
+    },
+}
+
+/// Why the EIS receiver could not be set up, or a new connection was
+/// rejected.
+#[derive(Debug, thiserror::Error)]
+pub enum EisError {
+    /// Broken pipes could not be turned into errors instead of signals.
+    #[error("failed to ignore SIGPIPE: {0}")]
+    Sigpipe(std::io::Error),
+    /// `max_connections` connections are open already.
+    #[error("connection limit of {max} reached")]
+    ConnectionLimit { max: usize },
+    /// The fd is no connected stream socket, or could not be prepared.
+    #[error("unusable socket: {0}")]
+    InvalidSocket(std::io::Error),
+    /// The `EisConnectionPolicy` hook turned the connection away.
+    #[error("denied by policy (peer {peer:?}, application {application:?})")]
+    PolicyDenied {
+        peer: Option<EisPeer>,
+        application: Option<String>,
+    },
+    /// Still out of file descriptors after the last attempt.
+    #[error("out of file descriptors after {attempts} attempts: {error}")]
+    FdExhausted {
+        attempts: u32,
+        error: std::io::Error,
+    },
+    /// The EIS context could not be created on the socket.
+    #[error("failed to create EIS context: {0}")]
+    Context(std::io::Error),
+    /// The connection or its retry could not be added to the event loop.
+    #[error("failed to insert calloop source: {0}")]
+    SourceInsertion(calloop::Error),
+}
+
+/// A new connection, for the `EisConnectionPolicy` hook to decide on.
+#[derive(Debug)]
+pub struct EisConnectionRequest<'a> {
+    /// Credentials of the process that created the client's socket, if known.
+    pub peer: Option<EisPeer>,
+    /// Executable of that process, if known.
+    pub application: Option<&'a str>,
+    /// Connections currently open.
+    pub active_connections: usize,
+}
+
+/// A connected client, for monitoring.
+#[derive(Debug, Clone, Serialize)]
+pub struct EisSession {
+    /// Connection id.
+    pub id: u64,
+    /// Name of the connection in logs and `SetKeyboardGrab`.
+    pub name: String,
+    /// Executable of the process that created the client's socket: the portal
+    /// for sessions it brokered.
+    pub application: Option<String>,
+    /// Capabilities of the devices the client bound, by interface name
+    /// without the `ei_` prefix.
+    pub capabilities: BTreeSet<&'static str>,
+    /// When the client last sent a request, in milliseconds since the Unix
+    /// epoch, so idle sessions stand out.
+    #[serde(rename = "last_activity_ms", serialize_with = "serialize_activity")]
+    pub last_activity: Arc<AtomicU64>,
+}
+
+fn serialize_activity<S: serde::Serializer>(
+    activity: &Arc<AtomicU64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(activity.load(Ordering::Relaxed))
+}
+
+/// Milliseconds since the Unix epoch.
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Snapshot of EIS activity, for monitoring.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct EisStatistics {
+    /// Connections currently open.
+    pub active_connections: usize,
+    /// Connections accepted since the compositor started.
+    pub total_connections: u64,
+    /// Connections turned away (limit reached, unusable socket).
+    pub rejected_connections: u64,
+    /// Of those, the ones turned away because the compositor was still out
+    /// of file descriptors after retrying.
+    pub fd_exhausted_connections: u64,
+    /// Requests processed, by request type.
+    pub events: BTreeMap<&'static str, u64>,
+    /// Input events dropped because the config disables their capability,
+    /// injection is paused, there is no output to place them on, they went
+    /// stale in the queue, were invalid or too many of them arrived before
+    /// emulating, by reason.
+    pub dropped: BTreeMap<&'static str, u64>,
+    /// Input events dropped because the active seat lacks a capability, by
+    /// capability.
+    pub missing_capability: BTreeMap<&'static str, u64>,
+    /// Whether injection of remote input is paused.
+    pub injection_paused: bool,
+    /// Input events resolved but not injected in dry-run mode.
+    pub dry_run_events: u64,
+    /// Connection holding the exclusive keyboard grab.
+    pub keyboard_grab: Option<u64>,
+    /// Lookups of the surface under remote pointers.
+    pub focus_lookups: u64,
+    /// Of those, the ones answered by the previous lookup.
+    pub focus_cache_hits: u64,
+}
+
+/// EIS protocol support of the compositor, for clients deciding what to
+/// negotiate and for bug reports.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EisProtocol {
+    /// Version of the reis crate the compositor was built with.
+    pub reis_version: &'static str,
+    /// Device interfaces offered to clients. A session may be offered fewer,
+    /// depending on `eis_config` and the seat.
+    pub interfaces: Vec<&'static str>,
+    /// Capabilities of those interfaces, by interface name without the `ei_`
+    /// prefix.
+    pub capabilities: BTreeSet<&'static str>,
+    /// Requests handled, by request type as counted in `Statistics`.
+    pub requests: Vec<&'static str>,
+}
+
+impl EisProtocol {
+    /// Protocol support of this build.
+    pub fn current() -> Self {
+        Self {
+            reis_version: option_env!("REIS_VERSION").unwrap_or("unknown"),
+            interfaces: DEVICE_INTERFACES
+                .iter()
+                .map(|(interface, _)| *interface)
+                .collect(),
+            capabilities: capability_names(BitFlags::all()),
+            requests: HANDLED_REQUESTS.to_vec(),
+        }
+    }
+}
+
+/// Histogram of the time the compositor takes from dispatching an EIS input
+/// request to completing its injection into the seat. Network latency is not
+/// included.
+#[derive(Debug, Default, Clone)]
+pub struct LatencyHistogram {
+    /// Bucket `i` counts latencies of less than 2^i µs, the last one
+    /// everything above.
+    counts: [u64; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// Record one injected request.
+    pub fn record(&mut self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.counts[bucket.min(LATENCY_BUCKETS - 1)] += 1;
+    }
+
+    /// Non-empty buckets as (exclusive upper bound in µs, count), the last
+    /// bucket is unbounded and reported as `u64::MAX`.
+    pub fn buckets(&self) -> Vec<(u64, u64)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, count)| {
+                let bound = if i == LATENCY_BUCKETS - 1 {
+                    u64::MAX
+                } else {
+                    1 << i
+                };
+                (bound, *count)
+            })
+            .collect()
+    }
+}
+
+impl EisState {
+    /// Create a new EIS state.
+    pub fn new(
+        evlh: &calloop::LoopHandle<'static, State>,
+        config: &EisConfig,
+        shared: Arc<EisShared>,
+    ) -> Result<Self, EisError> {
+        ignore_sigpipe().map_err(EisError::Sigpipe)?;
+        info!("EIS input receiver initialized");
+        if !shared.dry_run_requested.load(Ordering::Acquire) {
+            shared.dry_run.store(config.dry_run, Ordering::Release);
+        }
+        let dry_run = shared.dry_run.load(Ordering::Acquire);
+        let mut eis_state = Self {
+            evlh: evlh.clone(),
+            config: config.clone(),
+            connections: HashMap::new(),
+            next_connection_id: 0,
+            saved_pointers: HashMap::new(),
+            keyboard_grab: None,
+            injecting_keys: None,
+            remote_cursor_timer: None,
+            deferred: Vec::new(),
+            recorder: None,
+            pointer_seats: Vec::new(),
+            unhandled: BTreeMap::new(),
+            unhandled_timer: None,
+            next_selection_serial: 0,
+            under_cache: None,
+            connection_limit_warned: false,
+            replay_touch_slots: BTreeSet::new(),
+            dry_run,
+            shared,
+        };
+        eis_state.update_recorder();
+        Ok(eis_state)
+    }
+
+    /// Update the tunables after the compositor config changed.
+    pub fn set_config(&mut self, config: &EisConfig) {
+        self.config = config.clone();
+        self.update_recorder();
+    }
+
+    /// Number of events dropped so far because the active seat had no
+    /// `capability` ("keyboard", "pointer" or "touch").
+    pub fn missing_capability_drops(&self, capability: &str) -> u64 {
+        self.stats()
+            .missing_capability
+            .get(capability)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Snapshot of the counters and current state.
+    pub fn statistics(&self) -> EisStatistics {
+        let mut statistics = self.stats().clone();
+        statistics.injection_paused = self.shared.injection_paused.load(Ordering::Acquire);
+        statistics
+    }
+
+    /// Id of the live connection named `client`, or else of the most recent
+    /// one of that client name.
+    fn connection_by_name(&self, client: &str) -> Option<u64> {
+        let live = || {
+            self.connections
+                .values()
+                .filter(|c| c.session_key.is_some())
+        };
+        live()
+            .find(|c| c.name == client)
+            .or_else(|| {
+                live()
+                    .filter(|c| c.session_key.as_deref() == Some(client))
+                    .max_by_key(|c| c.id)
+            })
+            .map(|c| c.id)
+    }
+
+    /// Connection found like by `connection_by_name`, warning that there is
+    /// none to `action` otherwise.
+    fn connection_by_name_mut(&mut self, client: &str, action: &str) -> Option<&mut EisConnection> {
+        let Some(id) = self.connection_by_name(client) else {
+            warn!(client, "No EIS connection to {action}");
+            return None;
+        };
+        self.connections.get_mut(&id)
+    }
+
+    /// Queue `request` of connection `id` until the event loop has dispatched
+    /// all sources, so local input arriving in the same iteration is
+    /// processed first.
+    fn defer_request(&mut self, id: u64, request: EisRequest) {
+        if self.deferred.is_empty() {
+            self.evlh.insert_idle(process_deferred_requests);
+        }
+        self.deferred.push((id, request, Instant::now()));
+    }
+
+    fn stats(&self) -> MutexGuard<'_, EisStatistics> {
+        self.shared.statistics.lock().unwrap()
+    }
+
+    /// Count a processed request of type `request`.
+    fn count_request(&self, request: &'static str) {
+        *self.stats().events.entry(request).or_default() += 1;
+        trace!(
+            target: METRICS_TARGET,
+            monotonic_counter.eis_requests = 1u64,
+            request
+        );
+    }
+
+    /// Count an input event dropped for `reason`.
+    fn count_dropped(&self, reason: &'static str) {
+        *self.stats().dropped.entry(reason).or_default() += 1;
+        trace!(
+            target: METRICS_TARGET,
+            monotonic_counter.eis_dropped_events = 1u64,
+            reason
+        );
+    }
+
+    /// Count `request` of a kind not handled here, scheduling a summary of
+    /// those at info level, so clients relying on requests reis added since
+    /// are noticed without logging each one.
+    fn count_unhandled(&mut self, request: &EisRequest) {
+        let debug = format!("{request:?}");
+        *self
+            .unhandled
+            .entry(variant_name(&debug).to_string())
+            .or_default() += 1;
+        if self.unhandled_timer.is_some() {
+            return;
+        }
+        self.unhandled_timer = self
+            .evlh
+            .insert_source(
+                Timer::from_duration(UNHANDLED_SUMMARY_INTERVAL),
+                |_, _, state| {
+                    if let Some(eis_state) = state.common.eis_state.as_mut() {
+                        eis_state.unhandled_timer = None;
+                        let unhandled = std::mem::take(&mut eis_state.unhandled);
+                        info!("{}", summarize_unhandled(&unhandled));
+                    }
+                    TimeoutAction::Drop
+                },
+            )
+            .map_err(|e| {
+                warn!(
+                    "Failed to schedule unhandled EIS request summary: {}",
+                    e.error
+                )
+            })
+            .ok();
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, BTreeMap<u64, EisSession>> {
+        self.shared.sessions.lock().unwrap()
+    }
+
+    /// Number of pointer devices currently emulated across all connections.
+    pub fn active_pointers(&self) -> usize {
+        self.connections
+            .values()
+            .map(EisConnection::active_pointers)
+            .sum()
+    }
+
+    /// Remember the pointer position of a disconnecting client.
+    fn save_pointer(&mut self, key: String, position: Point<f64, Global>) {
+        self.saved_pointers.insert(
+            key,
+            SavedPointer {
+                position,
+                saved_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Take the pointer position saved for `key`, if it was saved less than
+    /// `window` ago. Expired entries are pruned along the way.
+    fn take_saved_pointer(&mut self, key: &str, window: Duration) -> Option<Point<f64, Global>> {
+        self.saved_pointers
+            .retain(|_, saved| saved.saved_at.elapsed() < window);
+        self.saved_pointers.remove(key).map(|saved| saved.position)
+    }
+
+    /// Re-announce devices to clients after the output layout changed.
+    ///
+    /// Regions are fixed for the lifetime of an EIS device, so devices with
+    /// absolute capabilities are removed and added again with the new regions.
+    /// Clients learn about it through the regular device lifecycle events.
+    pub fn outputs_changed(&mut self, shell: &Shell, xkb_config: &XkbConfig) {
+        for (id, eis_connection) in &mut self.connections {
+            let regions = output_regions(shell, eis_connection.target_output.as_deref());
+            if eis_connection.regions == regions {
+                continue;
+            }
+            eis_connection.regions = regions.clone();
+
+            let mut reannounced = false;
+            for bound in eis_connection.devices.iter_mut().filter(|bound| {
+                bound
+                    .capabilities
+                    .intersects(DeviceCapability::PointerAbsolute | DeviceCapability::Touch)
+            }) {
+                bound.device.remove();
+                let keymap = if bound.capabilities.contains(DeviceCapability::Keyboard) {
+                    prepare_xkb_keymap_fd(xkb_config)
+                } else {
+                    None
+                };
+                bound.device = add_device(
+                    &bound.seat,
+                    self.config.device_type,
+                    bound.capabilities,
+                    keymap.as_ref(),
+                    &regions,
+                );
+                reannounced = true;
+            }
+
+            if reannounced {
+                debug!(
+                    connection = id,
+                    "Re-announced EIS devices for new output layout"
+                );
+                if let Some(connection) = eis_connection.connection.clone() {
+                    flush_connection(
+                        &self.evlh,
+                        eis_connection,
+                        &connection,
+                        "device re-announcement",
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Input of an EIS client, decoded from its request so it can be recorded
+/// and replayed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RemoteInput {
+    Key {
+        key: u32,
+        pressed: bool,
+    },
+    PointerMotion {
+        dx: f32,
+        dy: f32,
+        utime: u64,
+    },
+    PointerMotionAbsolute {
+        x: f32,
+        y: f32,
+    },
+    Button {
+        button: u32,
+        pressed: bool,
+    },
+    ScrollDelta {
+        dx: f32,
+        dy: f32,
+        /// Timestamp of the client's frame, in µs of `CLOCK_MONOTONIC`.
+        #[serde(default)]
+        utime: u64,
+    },
+    /// Wheel clicks, in 1/120ths of a click.
+    ScrollDiscrete {
+        dx: i32,
+        dy: i32,
+        #[serde(default)]
+        utime: u64,
+    },
+    TouchDown {
+        touch_id: u32,
+        x: f32,
+        y: f32,
+    },
+    TouchMotion {
+        touch_id: u32,
+        x: f32,
+        y: f32,
+    },
+    TouchUp {
+        touch_id: u32,
+    },
+    TouchCancel,
+    Frame,
+}
+
+/// Why remote input was rejected, counted as the reason it was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InvalidInput {
+    /// A coordinate or delta is NaN or infinite.
+    NonFinite,
+    /// A key or button code, touch ID, coordinate or delta is beyond what is
+    /// accepted.
+    OutOfRange,
+}
+
+impl InvalidInput {
+    fn reason(self) -> &'static str {
+        match self {
+            Self::NonFinite => "non-finite",
+            Self::OutOfRange => "out of range",
+        }
+    }
+}
+
+/// Check that `values` are finite with a magnitude of at most
+/// `max_magnitude`.
+fn check_values(values: [f32; 2], max_magnitude: f64) -> Result<(), InvalidInput> {
+    if !values.iter().all(|value| value.is_finite()) {
+        Err(InvalidInput::NonFinite)
+    } else if values
+        .iter()
+        .any(|value| f64::from(*value).abs() > max_magnitude)
+    {
+        Err(InvalidInput::OutOfRange)
+    } else {
+        Ok(())
+    }
+}
+
+impl RemoteInput {
+    /// Check that `self` can be injected, with codes and touch IDs in range
+    /// and coordinates and deltas finite and of a magnitude of at most
+    /// `max_magnitude`.
+    fn validate(&self, max_magnitude: f64) -> Result<(), InvalidInput> {
+        match *self {
+            Self::Key { key: code, .. } | Self::Button { button: code, .. }
+                if code > MAX_EVDEV_CODE =>
+            {
+                Err(InvalidInput::OutOfRange)
+            }
+            Self::TouchDown { touch_id, .. }
+            | Self::TouchMotion { touch_id, .. }
+            | Self::TouchUp { touch_id }
+                if touch_id > MAX_TOUCH_ID =>
+            {
+                Err(InvalidInput::OutOfRange)
+            }
+            Self::PointerMotion { dx, dy, .. } | Self::ScrollDelta { dx, dy, .. } => {
+                check_values([dx, dy], max_magnitude)
+            }
+            Self::PointerMotionAbsolute { x, y }
+            | Self::TouchDown { x, y, .. }
+            | Self::TouchMotion { x, y, .. } => check_values([x, y], max_magnitude),
+            _ => Ok(()),
+        }
+    }
+
+    /// Decode `request`, `None` for protocol requests and input we do not
+    /// inject.
+    fn from_request(request: &EisRequest) -> Option<Self> {
+        Some(match request {
+            EisRequest::KeyboardKey(key) => Self::Key {
+                key: key.key,
+                pressed: key.state == eis::keyboard::KeyState::Press,
+            },
+            EisRequest::PointerMotion(motion) => Self::PointerMotion {
+                dx: motion.dx,
+                dy: motion.dy,
+                utime: motion.time,
+            },
+            EisRequest::PointerMotionAbsolute(motion) => Self::PointerMotionAbsolute {
+                x: motion.dx_absolute,
+                y: motion.dy_absolute,
+            },
+            EisRequest::Button(button) => Self::Button {
+                button: button.button,
+                pressed: button.state == eis::button::ButtonState::Press,
+            },
+            EisRequest::ScrollDelta(scroll) => Self::ScrollDelta {
+                dx: scroll.dx,
+                dy: scroll.dy,
+                utime: scroll.time,
+            },
+            EisRequest::ScrollDiscrete(scroll) => Self::ScrollDiscrete {
+                dx: scroll.discrete_dx,
+                dy: scroll.discrete_dy,
+                utime: scroll.time,
+            },
+            EisRequest::TouchDown(touch) => Self::TouchDown {
+                touch_id: touch.touch_id,
+                x: touch.x,
+                y: touch.y,
+            },
+            EisRequest::TouchMotion(touch) => Self::TouchMotion {
+                touch_id: touch.touch_id,
+                x: touch.x,
+                y: touch.y,
+            },
+            EisRequest::TouchUp(touch) => Self::TouchUp {
+                touch_id: touch.touch_id,
+            },
+            EisRequest::TouchCancel(_) => Self::TouchCancel,
+            EisRequest::Frame(_) => Self::Frame,
+            _ => return None,
+        })
+    }
+
+    /// Capability this input emulates, `None` for frames.
+    fn capability(&self) -> Option<DeviceCapability> {
+        Some(match self {
+            Self::Key { .. } => DeviceCapability::Keyboard,
+            Self::PointerMotion { .. } => DeviceCapability::Pointer,
+            Self::PointerMotionAbsolute { .. } => DeviceCapability::PointerAbsolute,
+            Self::Button { .. } => DeviceCapability::Button,
+            Self::ScrollDelta { .. } | Self::ScrollDiscrete { .. } => DeviceCapability::Scroll,
+            Self::TouchDown { .. }
+            | Self::TouchMotion { .. }
+            | Self::TouchUp { .. }
+            | Self::TouchCancel => DeviceCapability::Touch,
+            Self::Frame => return None,
+        })
+    }
+
+    /// Whether this places the pointer or a touch point in the output layout.
+    fn is_positioned(&self) -> bool {
+        matches!(
+            self,
+            Self::PointerMotion { .. }
+                | Self::PointerMotionAbsolute { .. }
+                | Self::TouchDown { .. }
+                | Self::TouchMotion { .. }
+        )
+    }
+}
+
+impl Common {
+    /// Forget the surface last found under a remote pointer, as local input
+    /// or Wayland client requests may have changed the layout since.
+    pub fn eis_forget_surface_under(&mut self) {
+        if let Some(eis_state) = self.eis_state.as_mut() {
+            eis_state.under_cache = None;
+        }
+    }
+
+    /// Let connected EIS clients know the output layout changed, and move
+    /// their pointers back onto it once the change is complete.
+    pub fn eis_outputs_changed(&mut self) {
+        if let Some(eis_state) = self.eis_state.as_mut() {
+            eis_state.outputs_changed(&self.shell.read(), &self.config.cosmic_conf.xkb_config);
+            if !eis_state.connections.is_empty() {
+                self.event_loop_handle
+                    .insert_idle(|state| state.eis_revalidate_pointers());
+            }
+        }
+    }
+}
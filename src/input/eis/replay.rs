@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Recording of remote input, and its replay.
+
+use anyhow::Context;
+use calloop::timer::{TimeoutAction, Timer};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::state::State;
+
+use super::{
+    EisConnection, EisState, MAX_RECORDING_SIZE, RemoteInput,
+    connection::{free_touch_slot_base, release_held_input},
+    inject::{SeatInjector, drop_input, inject_remote_input},
+};
+
+impl EisState {
+    /// Start, stop or redirect the recording of remote input to match
+    /// `record_input_path`.
+    pub(super) fn update_recorder(&mut self) {
+        let path = self.config.record_input_path.as_deref();
+        if self
+            .recorder
+            .as_ref()
+            .map(|recorder| recorder.path.as_str())
+            == path
+        {
+            return;
+        }
+        if self.recorder.take().is_some() {
+            info!("Stopped recording remote input");
+        }
+        let Some(path) = path else {
+            return;
+        };
+        match InputRecorder::create(path) {
+            Ok(recorder) => {
+                warn!(
+                    path,
+                    "Recording remote input, including everything typed remotely"
+                );
+                self.recorder = Some(recorder);
+            }
+            Err(e) => error!(path, "Failed to start recording remote input: {e}"),
+        }
+    }
+
+    /// Record `input` of connection `id`, if recording. Recording stops on
+    /// the first write error.
+    pub(super) fn record(&mut self, id: u64, input: &RemoteInput) {
+        if let Some(recorder) = self.recorder.as_mut()
+            && let Err(e) = recorder.record(id, input)
+        {
+            error!(path = %recorder.path, "Stopped recording remote input: {e}");
+            self.recorder = None;
+        }
+    }
+}
+
+/// Input of a connection as recorded, one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedInput {
+    /// Id of the connection the input came from.
+    pub(super) connection: u64,
+    /// Time since the recording started, in µs.
+    pub(super) elapsed_us: u64,
+    #[serde(flatten)]
+    pub(super) input: RemoteInput,
+}
+
+/// Writes the input of all connections to the file at `record_input_path`,
+/// to be replayed with `State::eis_replay`.
+#[derive(Debug)]
+pub(super) struct InputRecorder {
+    path: String,
+    file: std::io::LineWriter<std::fs::File>,
+    started: Instant,
+}
+
+impl InputRecorder {
+    /// Start recording to `path`, replacing what it held. The file is only
+    /// readable by the user, as it captures everything remote clients type.
+    fn create(path: &str) -> std::io::Result<Self> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(Self {
+            path: path.to_string(),
+            file: std::io::LineWriter::new(file),
+            started: Instant::now(),
+        })
+    }
+
+    fn record(&mut self, connection: u64, input: &RemoteInput) -> std::io::Result<()> {
+        use std::io::Write;
+
+        let recorded = RecordedInput {
+            connection,
+            elapsed_us: u64::try_from(self.started.elapsed().as_micros()).unwrap_or(u64::MAX),
+            input: input.clone(),
+        };
+        serde_json::to_writer(&mut self.file, &recorded)?;
+        self.file.write_all(b"\n")
+    }
+}
+
+/// Read input recorded through `record_input_path` from `path`, refusing
+/// files larger than `MAX_RECORDING_SIZE`. This blocks on the file system.
+pub fn read_recording(path: &Path) -> anyhow::Result<Vec<RecordedInput>> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut recording = String::new();
+    file.take(MAX_RECORDING_SIZE + 1)
+        .read_to_string(&mut recording)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if recording.len() as u64 > MAX_RECORDING_SIZE {
+        anyhow::bail!(
+            "{} is larger than {MAX_RECORDING_SIZE} bytes",
+            path.display()
+        );
+    }
+    parse_recording(&recording)
+}
+
+pub(super) fn parse_recording(recording: &str) -> anyhow::Result<Vec<RecordedInput>> {
+    recording
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).with_context(|| format!("Invalid recording line {}", i + 1))
+        })
+        .collect()
+}
+
+/// Recorded input being replayed, one connection per recorded connection.
+struct Replay {
+    events: VecDeque<RecordedInput>,
+    connections: HashMap<u64, EisConnection>,
+    started: Instant,
+}
+
+impl Replay {
+    /// Inject the events that are due, returning when to continue. Once
+    /// through, what the connections still hold is released.
+    fn step(&mut self, state: &mut State) -> TimeoutAction {
+        while let Some(event) = self.events.pop_front() {
+            let due = self.started + Duration::from_micros(event.elapsed_us);
+            if due > Instant::now() {
+                self.events.push_front(event);
+                return TimeoutAction::ToInstant(due);
+            }
+            if let Some(eis_connection) = self.connections.get_mut(&event.connection) {
+                replay_input(state, eis_connection, event.input);
+            }
+        }
+
+        let time = state.common.clock.now().as_millis();
+        for (_, mut eis_connection) in self.connections.drain() {
+            release_held_input(&mut SeatInjector::new(state), &mut eis_connection, time);
+            if let Some(eis_state) = state.common.eis_state.as_mut() {
+                eis_state
+                    .replay_touch_slots
+                    .remove(&eis_connection.touch_slot_base);
+            }
+        }
+        info!("Finished replaying recorded EIS input");
+        TimeoutAction::Drop
+    }
+}
+
+/// Inject recorded `input` as if `eis_connection` sent it now, dropping it
+/// under the same conditions as live input.
+fn replay_input(state: &mut State, eis_connection: &mut EisConnection, input: RemoteInput) {
+    if let Some(capability) = input.capability()
+        && drop_input(state, capability, input.is_positioned())
+    {
+        return;
+    }
+    let time = state.common.clock.now().as_millis();
+    inject_remote_input(state, eis_connection, input, time);
+}
+
+impl State {
+    /// Replay input recorded through `record_input_path` at its original
+    /// timing, as requested over D-Bus. Each recorded connection is replayed
+    /// as a connection of its own, which releases what it still holds once
+    /// the recording is through.
+    ///
+    /// Replayed connections bypass `require_consent`: only input that was
+    /// injected is recorded, which had consent when it was, and the caller
+    /// passed the D-Bus authorization. Pausing and disabled capabilities
+    /// still apply.
+    pub fn eis_replay(&mut self, events: Vec<RecordedInput>) {
+        let Some(eis_state) = self.common.eis_state.as_mut() else {
+            return;
+        };
+        let mut connections = HashMap::<u64, EisConnection>::new();
+        for event in &events {
+            if connections.contains_key(&event.connection) {
+                continue;
+            }
+            let id = eis_state.next_connection_id;
+            eis_state.next_connection_id += 1;
+            // Reserved until the replay is through, so connections coming
+            // up meanwhile use other slots
+            let touch_slot_base = free_touch_slot_base(eis_state.used_touch_slot_bases());
+            eis_state.replay_touch_slots.insert(touch_slot_base);
+            connections.insert(
+                event.connection,
+                EisConnection {
+                    id,
+                    name: format!("replay of {}", event.connection),
+                    touch_slot_base,
+                    ..Default::default()
+                },
+            );
+        }
+        info!(
+            events = events.len(),
+            connections = connections.len(),
+            "Replaying recorded EIS input"
+        );
+
+        let touch_slots = connections
+            .values()
+            .map(|eis_connection| eis_connection.touch_slot_base)
+            .collect::<Vec<_>>();
+        let mut replay = Replay {
+            events: events.into(),
+            connections,
+            started: Instant::now(),
+        };
+        if let Err(e) = eis_state
+            .evlh
+            .insert_source(Timer::immediate(), move |_, _, state| replay.step(state))
+        {
+            warn!("Failed to schedule EIS input replay: {}", e.error);
+            for touch_slot_base in touch_slots {
+                eis_state.replay_touch_slots.remove(&touch_slot_base);
+            }
+        }
+    }
+}
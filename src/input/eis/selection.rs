@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Clipboard shared with the remote desktop over D-Bus.
+
+use smithay::{
+    input::Seat,
+    wayland::selection::{
+        SelectionTarget,
+        data_device::{
+            clear_data_device_selection, current_data_device_selection_userdata,
+            request_data_device_client_selection, set_data_device_selection,
+        },
+    },
+};
+use std::os::fd::OwnedFd;
+use tracing::{debug, info, warn};
+
+use crate::state::State;
+use crate::wayland::handlers::selection::SelectionOwner;
+
+use super::MAX_SELECTION_TRANSFERS;
+
+impl State {
+    /// Offer the clipboard selection of the remote desktop with `mime_types` on
+    /// the active seat, as requested over D-Bus. No mime types clear it, if
+    /// the remote desktop still owns it.
+    pub fn eis_set_selection(&mut self, mime_types: Vec<String>) {
+        let Some(eis_state) = self.common.eis_state.as_ref() else {
+            return;
+        };
+        // Pastes of the previous selection can no longer be served
+        eis_state.shared.selection_transfers.lock().unwrap().clear();
+
+        info!(?mime_types, "Set EIS clipboard selection");
+        let seat = self.common.shell.read().seats.last_active().clone();
+        if !mime_types.is_empty() {
+            set_data_device_selection(
+                &self.common.display_handle,
+                &seat,
+                mime_types,
+                SelectionOwner::Remote,
+            );
+        } else if current_data_device_selection_userdata(&seat).as_deref()
+            == Some(&SelectionOwner::Remote)
+        {
+            clear_data_device_selection(&self.common.display_handle, &seat);
+        }
+    }
+
+    /// Write the clipboard selection of the active seat as `mime_type` into
+    /// `fd` for the remote desktop, as requested over D-Bus.
+    pub fn eis_read_selection(&mut self, mime_type: String, fd: OwnedFd) {
+        let seat = self.common.shell.read().seats.last_active().clone();
+        match current_data_device_selection_userdata(&seat).as_deref() {
+            Some(SelectionOwner::Xwm(_)) => {
+                if let Some(xwm) = self
+                    .common
+                    .xwayland_state
+                    .as_mut()
+                    .and_then(|xstate| xstate.xwm.as_mut())
+                    && let Err(err) = xwm.send_selection(SelectionTarget::Clipboard, mime_type, fd)
+                {
+                    warn!(?err, "Failed to read Xwayland clipboard selection for EIS");
+                }
+            }
+            // The remote desktop reads back its own selection, it gets EOF
+            Some(SelectionOwner::Remote) => {}
+            None => {
+                if let Err(err) = request_data_device_client_selection(&seat, mime_type, fd) {
+                    warn!(?err, "Failed to read clipboard selection for EIS");
+                }
+            }
+        }
+    }
+
+    /// Hand a paste of the remote desktop's selection as `mime_type` to the
+    /// remote desktop, which writes it into `fd` once it fetched it with
+    /// `SelectionWrite`.
+    pub fn eis_send_selection(&mut self, mime_type: String, fd: OwnedFd) {
+        let Some(eis_state) = self.common.eis_state.as_mut() else {
+            return;
+        };
+        let serial = eis_state.next_selection_serial;
+        eis_state.next_selection_serial = serial.wrapping_add(1);
+        {
+            let mut transfers = eis_state.shared.selection_transfers.lock().unwrap();
+            while transfers.len() >= MAX_SELECTION_TRANSFERS {
+                transfers.pop_first();
+            }
+            transfers.insert(serial, fd);
+        }
+        debug!(serial, mime_type, "Requesting EIS clipboard selection");
+        crate::dbus::eis::selection_transfer(
+            &self.common.async_executor,
+            &eis_state.shared,
+            mime_type,
+            serial,
+        );
+    }
+
+    /// Let the remote desktop know the clipboard selection of `seat` changed
+    /// to one offering `mime_types`, none if it was cleared, while EIS clients
+    /// are connected and `seat` is the one their input goes to.
+    pub fn eis_selection_changed(&mut self, seat: &Seat<State>, mime_types: Vec<String>) {
+        let Some(eis_state) = self.common.eis_state.as_ref() else {
+            return;
+        };
+        if eis_state.connections.is_empty() || seat != self.common.shell.read().seats.last_active()
+        {
+            return;
+        }
+        crate::dbus::eis::selection_owner_changed(
+            &self.common.async_executor,
+            &eis_state.shared,
+            mime_types,
+        );
+    }
+}
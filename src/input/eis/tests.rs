@@ -0,0 +1,1919 @@
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{
+    BTN_LEFT, BTN_RIGHT, EisConnection, EisProtocol, EisSession, HANDLED_REQUESTS, HeldKeys,
+    InvalidInput, LatencyHistogram, MAX_EARLY_INPUT, MAX_TOUCH_ID, ProtocolErrors, RemoteInput,
+    ScrollFrame, TOUCH_SLOTS_PER_CONNECTION,
+    connection::{
+        TeardownReason, allowed_capabilities, capability_names, check_socket,
+        connection_limit_crossed, fd_exhausted, free_touch_slot_base, peer_application,
+        peer_credentials, peer_hung_up, release_held_input, seat_capabilities, summarize_unhandled,
+        truncate_client_name, unique_name, variant_name,
+    },
+    inject::{
+        InputInjector, clamp_to_output, confine, edge_position, frame_time, handle_button,
+        handle_discrete_scroll, handle_frame, handle_key, handle_scroll, inject_seat_input,
+        map_button, nearest_output, normalize_button, output_under, place_on_outputs,
+        remote_shortcut, revalidate_pointer, smooth_motion,
+    },
+    keysym::{KeysymPress, find_key, resolve_keysym, scratch_keymap},
+    replay::{RecordedInput, parse_recording},
+};
+use crate::config::key_bindings::cosmic_modifiers_from_smithay;
+use crate::utils::geometry::Global;
+use cosmic_comp_config::eis::{EisConfig, EisRemoteShortcuts};
+use cosmic_settings_config::shortcuts;
+use enumflags2::BitFlags;
+use reis::event::DeviceCapability;
+use smithay::{
+    backend::input::{ButtonState, KeyState},
+    input::keyboard::{Keycode, ModifiersState},
+    output::{Mode, Output, PhysicalProperties, Scale, Subpixel},
+    utils::{Point, Rectangle, SERIAL_COUNTER, Serial, Transform},
+};
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use xkbcommon::xkb::{self, Keysym};
+
+fn output(name: &str, loc: (i32, i32), size: (i32, i32)) -> Output {
+    scaled_output(name, loc, size, 1.0)
+}
+
+fn scaled_output(name: &str, loc: (i32, i32), size: (i32, i32), scale: f64) -> Output {
+    let output = Output::new(
+        name.to_string(),
+        PhysicalProperties {
+            size: (0, 0).into(),
+            subpixel: Subpixel::Unknown,
+            make: "COSMIC".to_string(),
+            model: name.to_string(),
+            serial_number: "Unknown".to_string(),
+        },
+    );
+    let mode = Mode {
+        size: size.into(),
+        refresh: 60_000,
+    };
+    output.change_current_state(
+        Some(mode),
+        None,
+        Some(Scale::Fractional(scale)),
+        Some(loc.into()),
+    );
+    output.set_preferred(mode);
+    output
+}
+
+#[test]
+fn test_motion_across_outputs() {
+    let left = output("left", (0, 0), (1920, 1080));
+    let right = output("right", (1920, 0), (1920, 1080));
+    let outputs = [left.clone(), right.clone()];
+
+    // Relative motion from the left output into the right one has to
+    // resolve focus against the right output, not the one it started on.
+    let start: Point<f64, Global> = (1910.0, 500.0).into();
+    assert_eq!(output_under(outputs.iter(), start), Some(left.clone()));
+    let end = start + Point::from((20.0, 0.0));
+    assert_eq!(output_under(outputs.iter(), end), Some(right.clone()));
+
+    // ...and back again
+    let back = end - Point::from((40.0, 0.0));
+    assert_eq!(output_under(outputs.iter(), back), Some(left));
+
+    // Positions outside of the layout belong to no output
+    assert_eq!(output_under(outputs.iter(), (100.0, 2000.0).into()), None);
+}
+
+#[test]
+fn test_output_far_corners() {
+    let left = output("left", (0, 0), (1920, 1080));
+    let right = output("right", (1920, 0), (1920, 1080));
+    let below = output("below", (0, 1080), (1920, 1080));
+    let outputs = [left.clone(), right.clone(), below.clone()];
+
+    for output in &outputs {
+        let geom = output.geometry().to_f64();
+        let corner = geom.loc + geom.size.to_point();
+        assert_eq!(
+            output_under(outputs.iter(), corner).as_ref(),
+            Some(output),
+            "far corner of {}",
+            output.name()
+        );
+    }
+
+    // Shared edges still belong to the output they start
+    assert_eq!(
+        output_under(outputs.iter(), (1920.0, 500.0).into()),
+        Some(right)
+    );
+    assert_eq!(
+        output_under(outputs.iter(), (500.0, 1080.0).into()),
+        Some(below)
+    );
+    assert_eq!(output_under(outputs.iter(), (3000.0, 1081.0).into()), None);
+}
+
+#[test]
+fn test_clamp_to_scaled_output() {
+    // A 4K mode at scale 2 spans 1920x1080 logical pixels, and logical
+    // coordinates are what the pointer moves in
+    let hidpi = scaled_output("hidpi", (0, 0), (3840, 2160), 2.0);
+    let lodpi = output("lodpi", (1920, 0), (1920, 1080));
+    assert_eq!(
+        hidpi.geometry(),
+        Rectangle::new((0, 0).into(), (1920, 1080).into())
+    );
+    let outputs = [hidpi.clone(), lodpi.clone()];
+
+    // Motion overshooting any corner stops right in it, on its output
+    for output in &outputs {
+        let geom = output.geometry().to_f64();
+        let center = geom.loc + geom.size.downscale(2.0).to_point();
+        let (x0, y0) = (geom.loc.x, geom.loc.y);
+        let (x1, y1) = (x0 + geom.size.w - 1.0, y0 + geom.size.h - 1.0);
+        for corner in [(x0, y0), (x1, y0), (x0, y1), (x1, y1)] {
+            let corner: Point<f64, Global> = corner.into();
+            let overshoot = corner
+                + Point::from((
+                    (corner.x - center.x).signum() * 500.0,
+                    (corner.y - center.y).signum() * 500.0,
+                ));
+            assert_eq!(clamp_to_output(overshoot, output.geometry()), corner);
+            assert_eq!(output_under(outputs.iter(), corner).as_ref(), Some(output));
+        }
+    }
+
+    // Absolute positions beyond the layout land in its outer corners
+    assert_eq!(
+        place_on_outputs(|| outputs.iter(), (-500.0, -500.0).into()),
+        Some((hidpi, Point::from((0.0, 0.0))))
+    );
+    assert_eq!(
+        place_on_outputs(|| outputs.iter(), (5000.0, 5000.0).into()),
+        Some((lodpi, Point::from((3839.0, 1079.0))))
+    );
+}
+
+#[test]
+fn test_absolute_motion_on_transformed_output() {
+    let left = output("left", (0, 0), (1920, 1080));
+    let rotated = [
+        Transform::_90,
+        Transform::_270,
+        Transform::Flipped90,
+        Transform::Flipped270,
+    ];
+    for transform in [
+        Transform::Normal,
+        Transform::_90,
+        Transform::_180,
+        Transform::_270,
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ] {
+        let right = output("right", (1920, 0), (1920, 1080));
+        right.change_current_state(None, Some(transform), None, None);
+        let outputs = [left.clone(), right.clone()];
+
+        // Regions span the transformed size, and flipping does not move
+        // content between outputs
+        let size = if rotated.contains(&transform) {
+            (1080, 1920)
+        } else {
+            (1920, 1080)
+        };
+        assert_eq!(
+            right.geometry(),
+            Rectangle::new((1920, 0).into(), size.into()),
+            "{transform:?}"
+        );
+
+        // Client coordinates are what the client sees in the screencast,
+        // where the transform was applied already, so they map onto the
+        // same global point
+        let position: Point<f64, Global> = (1920.0 + 1000.0, 1000.0).into();
+        assert_eq!(
+            place_on_outputs(|| outputs.iter(), position),
+            Some((right.clone(), position)),
+            "{transform:?}"
+        );
+        let far = (1920.0 + size.0 as f64 + 500.0, size.1 as f64 + 500.0).into();
+        assert_eq!(
+            place_on_outputs(|| outputs.iter(), far),
+            Some((
+                right,
+                Point::from((1920.0 + size.0 as f64 - 1.0, size.1 as f64 - 1.0))
+            )),
+            "{transform:?}"
+        );
+    }
+}
+
+#[test]
+fn test_absolute_motion_on_offset_output() {
+    use crate::utils::geometry::PointGlobalExt;
+
+    // The second output sits below and to the right of the first, with
+    // neither coordinate of its origin at 0
+    let left = output("left", (0, 0), (1920, 1080));
+    let right = scaled_output("right", (1920, 300), (3840, 2160), 2.0);
+    let outputs = [left.clone(), right.clone()];
+
+    let position: Point<f64, Global> = (2000.0, 400.0).into();
+    let (output, placed) = place_on_outputs(|| outputs.iter(), position).unwrap();
+    assert_eq!(output, right);
+    // Absolute coordinates are global already and stay as they are, the
+    // pointer location being the same point in logical coordinates
+    assert_eq!(placed, position);
+    assert_eq!(placed.as_logical(), Point::from((2000.0, 400.0)));
+    assert_eq!(placed.to_local(&output), Point::from((80.0, 100.0)));
+
+    // Above the second output's origin there is no output, so the point
+    // lands on the closest edge of the nearest one
+    let (output, placed) = place_on_outputs(|| outputs.iter(), (2500.0, 100.0).into()).unwrap();
+    assert_eq!(output, right);
+    assert_eq!(placed, Point::from((2500.0, 300.0)));
+}
+
+#[test]
+fn test_touch_corners_of_small_output() {
+    for size in [(1, 1), (4, 3)] {
+        let small = output("small", (100, 100), size);
+        let outputs = [small.clone()];
+        let geom = small.geometry().to_f64();
+        let (x0, y0) = (geom.loc.x, geom.loc.y);
+        let (x1, y1) = (x0 + geom.size.w, y0 + geom.size.h);
+        for corner in [(x0, y0), (x1, y0), (x0, y1), (x1, y1)] {
+            assert_eq!(
+                output_under(outputs.iter(), corner.into()).as_ref(),
+                Some(&small),
+                "corner {corner:?} of a {size:?} output"
+            );
+        }
+        assert_eq!(output_under(outputs.iter(), (x1 + 0.5, y1).into()), None);
+    }
+}
+
+#[test]
+fn test_summarize_unhandled() {
+    assert_eq!(
+        variant_name("TextKeysym(TextKeysym { keysym: 97 })"),
+        "TextKeysym"
+    );
+    assert_eq!(variant_name("Disconnect"), "Disconnect");
+
+    let unhandled = BTreeMap::from([("TextKeysym".to_string(), 3), ("TextUtf8".to_string(), 1)]);
+    assert_eq!(
+        summarize_unhandled(&unhandled),
+        "4 unhandled EIS requests of kinds: TextKeysym (3), TextUtf8 (1)"
+    );
+}
+
+#[test]
+fn test_capability_names() {
+    assert_eq!(
+        capability_names(DeviceCapability::Pointer | DeviceCapability::Button),
+        BTreeSet::from(["button", "pointer"])
+    );
+    assert_eq!(
+        capability_names(DeviceCapability::PointerAbsolute | DeviceCapability::Touch),
+        BTreeSet::from(["pointer_absolute", "touchscreen"])
+    );
+    assert!(capability_names(BitFlags::empty()).is_empty());
+}
+
+#[test]
+fn test_nearest_output() {
+    let left = output("left", (0, 0), (1920, 1080));
+    let right = output("right", (1920, 0), (1920, 1080));
+    let outputs = [left.clone(), right.clone()];
+
+    // Beyond the right edge of the rightmost output
+    assert_eq!(output_under(outputs.iter(), (5000.0, 500.0).into()), None);
+    assert_eq!(
+        nearest_output(outputs.iter(), (5000.0, 500.0).into()),
+        Some((right.clone(), (3839.0, 500.0).into()))
+    );
+    assert_eq!(
+        nearest_output(outputs.iter(), (2000.0, 3000.0).into()),
+        Some((right, (2000.0, 1079.0).into()))
+    );
+    assert_eq!(
+        nearest_output(outputs.iter(), (-10.0, -10.0).into()),
+        Some((left, (0.0, 0.0).into()))
+    );
+    assert_eq!(nearest_output([].iter(), (0.0, 0.0).into()), None);
+}
+
+#[test]
+fn test_resolve_keysym_levels() {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_names(
+        &context,
+        "",
+        "",
+        "de",
+        "",
+        None,
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .expect("German keymap");
+    // KEY_E, as XKB keycode
+    let key_e = Keycode::new(18 + 8);
+    let shift = find_key(&keymap, 0, Keysym::Shift_L).unwrap();
+    let level3 = find_key(&keymap, 0, Keysym::ISO_Level3_Shift).unwrap();
+
+    assert_eq!(
+        resolve_keysym(&keymap, 0, 0, Keysym::e),
+        Some(KeysymPress {
+            modifiers: Vec::new(),
+            key: key_e
+        })
+    );
+    assert_eq!(
+        resolve_keysym(&keymap, 0, 0, Keysym::E),
+        Some(KeysymPress {
+            modifiers: vec![shift],
+            key: key_e
+        })
+    );
+    // The euro sign needs AltGr on a German layout
+    assert_eq!(
+        resolve_keysym(&keymap, 0, 0, Keysym::EuroSign),
+        Some(KeysymPress {
+            modifiers: vec![level3],
+            key: key_e
+        })
+    );
+}
+
+#[test]
+fn test_scratch_keymap() {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_names(
+        &context,
+        "",
+        "",
+        "us",
+        "",
+        None,
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .expect("US keymap");
+    // No key of a US layout produces the euro sign
+    assert_eq!(resolve_keysym(&keymap, 0, 0, Keysym::EuroSign), None);
+
+    let (scratch, key) = scratch_keymap(&keymap, Keysym::EuroSign).unwrap();
+    assert_eq!(keymap.key_get_name(key), None);
+    let scratch = xkb::Keymap::new_from_string(
+        &context,
+        scratch,
+        xkb::KEYMAP_FORMAT_TEXT_V1,
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .expect("scratch keymap");
+    assert_eq!(
+        resolve_keysym(&scratch, 0, 0, Keysym::EuroSign),
+        Some(KeysymPress {
+            modifiers: Vec::new(),
+            key
+        })
+    );
+    // The other keys are left alone
+    assert_eq!(
+        find_key(&scratch, 0, Keysym::e),
+        find_key(&keymap, 0, Keysym::e)
+    );
+
+    assert_eq!(scratch_keymap(&keymap, Keysym::NoSymbol), None);
+}
+
+#[test]
+fn test_interleaved_local_and_remote_modifiers() {
+    const SHIFT: u32 = 42;
+    const CTRL: u32 = 29;
+    let mut keys = HeldKeys::default();
+
+    // Local Shift is held: the remote press and release of Shift must not
+    // reach the seat, or the release would clear the local press
+    assert!(!keys.filter(SHIFT, true, true));
+    assert!(!keys.filter(SHIFT, false, true));
+
+    // Remote Shift while the key is up locally is forwarded both ways
+    assert!(keys.filter(SHIFT, true, false));
+    assert!(keys.filter(SHIFT, false, true));
+
+    // Repeated presses only reach the seat once
+    assert!(keys.filter(CTRL, true, false));
+    assert!(!keys.filter(CTRL, true, true));
+
+    // Releases of keys the client never pressed are dropped
+    assert!(!keys.filter(SHIFT, false, true));
+
+    // Local Shift goes down while remote Ctrl is held, then the remote
+    // presses Shift too: on disconnect only Ctrl is released
+    assert!(!keys.filter(SHIFT, true, true));
+    assert_eq!(keys.release_all(), vec![CTRL]);
+    assert_eq!(keys.release_all(), Vec::<u32>::new());
+}
+
+#[test]
+fn test_check_socket() {
+    use std::os::{
+        fd::OwnedFd,
+        unix::net::{UnixDatagram, UnixListener, UnixStream},
+    };
+
+    let (server, _client) = UnixStream::pair().unwrap();
+    assert!(check_socket(&server).is_ok());
+
+    // A datagram socket passed off as a stream
+    let (datagram, _peer) = UnixDatagram::pair().unwrap();
+    let datagram = UnixStream::from(OwnedFd::from(datagram));
+    assert!(check_socket(&datagram).is_err());
+
+    // A stream socket that was never connected
+    let dir = std::env::temp_dir().join(format!("eis-check-socket-{}", std::process::id()));
+    let listener = UnixListener::bind(&dir).unwrap();
+    let unconnected = UnixStream::from(OwnedFd::from(listener));
+    let result = check_socket(&unconnected);
+    let _ = std::fs::remove_file(&dir);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_latency_histogram() {
+    let mut histogram = LatencyHistogram::default();
+    histogram.record(Duration::ZERO);
+    histogram.record(Duration::from_micros(3));
+    histogram.record(Duration::from_micros(300));
+    histogram.record(Duration::from_micros(511));
+    histogram.record(Duration::from_secs(60));
+    assert_eq!(
+        histogram.buckets(),
+        vec![(1, 1), (4, 1), (512, 2), (u64::MAX, 1)]
+    );
+}
+
+#[test]
+fn test_frame_time() {
+    // The client stamps frames from the same clock, in µs
+    assert_eq!(frame_time(4_990_000, 5_000), 4_990);
+    assert_eq!(frame_time(5_000_999, 5_000), 5_000);
+    // Wayland timestamps wrap after ~49 days
+    let wrapped = (u64::from(u32::MAX) + 11) * 1000;
+    assert_eq!(frame_time(wrapped, 20), 10);
+
+    // Missing, future and stale stamps fall back to the server time
+    assert_eq!(frame_time(0, 5_000), 5_000);
+    assert_eq!(frame_time(5_001_000, 5_000), 5_000);
+    assert_eq!(frame_time(1_000_000, 5_000), 5_000);
+}
+
+#[test]
+fn test_validate_input() {
+    let max = 1000.0;
+    assert_eq!(
+        RemoteInput::Key {
+            key: 0x300,
+            pressed: true
+        }
+        .validate(max),
+        Err(InvalidInput::OutOfRange)
+    );
+    assert_eq!(
+        RemoteInput::TouchUp {
+            touch_id: MAX_TOUCH_ID + 1
+        }
+        .validate(max),
+        Err(InvalidInput::OutOfRange)
+    );
+    assert_eq!(
+        RemoteInput::PointerMotion {
+            dx: f32::NAN,
+            dy: 0.0,
+            utime: 0
+        }
+        .validate(max),
+        Err(InvalidInput::NonFinite)
+    );
+    assert_eq!(
+        RemoteInput::ScrollDelta {
+            dx: 0.0,
+            dy: f32::INFINITY,
+            utime: 0
+        }
+        .validate(max),
+        Err(InvalidInput::NonFinite)
+    );
+    assert_eq!(
+        RemoteInput::TouchDown {
+            touch_id: 0,
+            x: -1001.0,
+            y: 0.0
+        }
+        .validate(max),
+        Err(InvalidInput::OutOfRange)
+    );
+    assert_eq!(
+        RemoteInput::PointerMotionAbsolute { x: 1000.0, y: 0.0 }.validate(max),
+        Ok(())
+    );
+    assert_eq!(RemoteInput::Frame.validate(max), Ok(()));
+}
+
+#[test]
+fn test_remote_shortcuts() {
+    let logo = ModifiersState {
+        logo: true,
+        ..Default::default()
+    };
+    let binding = |key| shortcuts::Binding {
+        modifiers: cosmic_modifiers_from_smithay(logo),
+        keycode: None,
+        key: Some(key),
+        description: None,
+    };
+    let bound = [
+        (binding(Keysym::Right), shortcuts::Action::NextWorkspace),
+        (
+            binding(Keysym::t),
+            shortcuts::Action::Spawn("terminal".into()),
+        ),
+        (binding(Keysym::Escape), shortcuts::Action::Terminate),
+    ];
+    let shortcut = |mode, modifiers: &ModifiersState, sym| {
+        remote_shortcut(bound.iter().map(|(b, a)| (b, a)), mode, modifiers, &[sym])
+            .map(|(action, _)| action)
+    };
+
+    // By default remote keys go to the focused client
+    assert_eq!(
+        shortcut(EisRemoteShortcuts::None, &logo, Keysym::Right),
+        None
+    );
+
+    assert_eq!(
+        shortcut(EisRemoteShortcuts::WindowManagement, &logo, Keysym::Right),
+        Some(shortcuts::Action::NextWorkspace)
+    );
+    assert_eq!(
+        shortcut(EisRemoteShortcuts::WindowManagement, &logo, Keysym::t),
+        None
+    );
+    assert_eq!(
+        shortcut(
+            EisRemoteShortcuts::WindowManagement,
+            &ModifiersState::default(),
+            Keysym::Right
+        ),
+        None
+    );
+
+    assert_eq!(
+        shortcut(EisRemoteShortcuts::All, &logo, Keysym::t),
+        Some(shortcuts::Action::Spawn("terminal".into()))
+    );
+    // Ending the session is never left to remote keys
+    assert_eq!(
+        shortcut(EisRemoteShortcuts::All, &logo, Keysym::Escape),
+        None
+    );
+}
+
+#[test]
+fn test_teardown_reason() {
+    use reis::eis::connection::DisconnectReason;
+
+    assert!(matches!(
+        TeardownReason::ProtocolErrors.disconnect_reason(),
+        (DisconnectReason::Protocol, _)
+    ));
+    for reason in [TeardownReason::ConsentTimeout, TeardownReason::Shutdown] {
+        let (reason, explanation) = reason.disconnect_reason();
+        assert!(matches!(reason, DisconnectReason::Disconnected));
+        assert!(!explanation.is_empty());
+    }
+}
+
+#[test]
+fn test_unique_name() {
+    let taken = ["rdp", "rdp #2", "vnc"];
+    assert_eq!(unique_name("other", taken.iter().copied()), "other");
+    assert_eq!(unique_name("rdp", taken.iter().copied()), "rdp #3");
+    assert_eq!(unique_name("vnc", taken.iter().copied()), "vnc #2");
+}
+
+#[test]
+fn test_peer_application() {
+    let (server, _client) = std::os::unix::net::UnixStream::pair().unwrap();
+    let exe = std::env::current_exe().unwrap();
+    let expected = truncate_client_name(&exe.file_name().unwrap().to_string_lossy()).to_owned();
+    let peer = peer_credentials(&server).unwrap();
+    assert_eq!(peer.pid, std::process::id());
+    assert_eq!(peer.uid, unsafe { libc::getuid() });
+    assert_eq!(peer_application(peer.pid), Some(expected));
+
+    assert_eq!(truncate_client_name(&"x".repeat(500)).len(), 128);
+}
+
+#[test]
+fn test_truncate_client_name() {
+    assert_eq!(truncate_client_name(""), "");
+    assert_eq!(truncate_client_name("rdp"), "rdp");
+    let exact = "x".repeat(128);
+    assert_eq!(truncate_client_name(&exact), exact);
+
+    // Multi-byte characters count as one and are never split
+    let wide = "é".repeat(200);
+    let truncated = truncate_client_name(&wide);
+    assert_eq!(truncated.chars().count(), 128);
+    assert_eq!(truncated.len(), 256);
+    let mixed = format!("{}€€", "x".repeat(127));
+    assert_eq!(
+        truncate_client_name(&mixed),
+        format!("{}€", "x".repeat(127))
+    );
+}
+
+#[test]
+fn test_clamp_to_zero_size_output() {
+    let geom = Rectangle::<i32, Global>::new((1920, 0).into(), (1920, 1080).into());
+    let clamped = clamp_to_output(Point::from((5000.0, -10.0)), geom);
+    assert_eq!(clamped, Point::from((3839.0, 0.0)));
+
+    // Transient empty geometry during a mode change
+    for size in [(0, 1080), (1920, 0), (-1, -1)] {
+        let geom = Rectangle::<i32, Global>::new((1920, 0).into(), size.into());
+        let position = Point::from((2000.0, 500.0));
+        assert_eq!(clamp_to_output(position, geom), position);
+    }
+}
+
+#[test]
+fn test_check_abstract_socket() {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+
+    let name = format!("cosmic-comp-eis-test-{}", std::process::id());
+    let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+    let listener = UnixListener::bind_addr(&addr).unwrap();
+    let client = UnixStream::connect_addr(&addr).unwrap();
+    let (server, _) = listener.accept().unwrap();
+
+    assert!(check_socket(&server).is_ok());
+    assert!(check_socket(&client).is_ok());
+    assert_eq!(
+        client.peer_addr().unwrap().as_abstract_name(),
+        Some(name.as_bytes())
+    );
+}
+
+#[test]
+fn test_protocol_error_throttling() {
+    let start = std::time::Instant::now();
+    let mut errors = ProtocolErrors::default();
+    assert_eq!(errors.record("bad", start), Some(0));
+    assert_eq!(errors.record("bad", start + Duration::from_secs(1)), None);
+    assert_eq!(errors.record("bad", start + Duration::from_secs(2)), None);
+    // A different error is logged right away, with the count of the
+    // previous one
+    assert_eq!(
+        errors.record("worse", start + Duration::from_secs(3)),
+        Some(2)
+    );
+    // As is the same error once the interval passed
+    assert_eq!(
+        errors.record("worse", start + Duration::from_secs(9)),
+        Some(0)
+    );
+
+    assert!(!errors.limit_reached());
+    for _ in 0..95 {
+        errors.record("bad", start);
+    }
+    assert!(errors.limit_reached());
+}
+
+#[test]
+fn test_statistics() {
+    use super::{EisError, EisShared, EisState};
+    use crate::state::State;
+    use std::os::{
+        fd::OwnedFd,
+        unix::net::{UnixDatagram, UnixStream},
+    };
+
+    let event_loop = calloop::EventLoop::<State>::try_new().unwrap();
+    let config = EisConfig {
+        max_connections: 1,
+        ..Default::default()
+    };
+    let mut eis_state = EisState::new(
+        &event_loop.handle(),
+        &config,
+        std::sync::Arc::new(EisShared::default()),
+    )
+    .unwrap();
+    assert_eq!(eis_state.statistics(), Default::default());
+
+    let (datagram, _peer) = UnixDatagram::pair().unwrap();
+    assert!(matches!(
+        eis_state.add_connection(UnixStream::from(OwnedFd::from(datagram)), None),
+        Err(EisError::InvalidSocket(_))
+    ));
+    let (server, _client) = UnixStream::pair().unwrap();
+    eis_state.add_connection(server, None).unwrap();
+    let (server, _client2) = UnixStream::pair().unwrap();
+    assert!(matches!(
+        eis_state.add_connection(server, None),
+        Err(EisError::ConnectionLimit { max: 1 })
+    ));
+
+    let statistics = eis_state.statistics();
+    assert_eq!(statistics.active_connections, 1);
+    assert_eq!(statistics.total_connections, 1);
+    assert_eq!(statistics.rejected_connections, 2);
+
+    let json = serde_json::to_value(&statistics).unwrap();
+    assert_eq!(json["active_connections"], 1);
+    assert_eq!(json["keyboard_grab"], serde_json::Value::Null);
+}
+
+#[test]
+fn test_map_button() {
+    const BTN_MIDDLE: u32 = 0x112;
+    const BTN_SIDE: u32 = 0x113;
+    const BTN_BACK: u32 = 0x116;
+
+    let mut config = EisConfig::default();
+    assert_eq!(map_button(&config, BTN_LEFT), BTN_LEFT);
+
+    config.swap_left_right_buttons = true;
+    assert_eq!(map_button(&config, BTN_LEFT), BTN_RIGHT);
+    assert_eq!(map_button(&config, BTN_RIGHT), BTN_LEFT);
+    assert_eq!(map_button(&config, BTN_MIDDLE), BTN_MIDDLE);
+
+    // A client sending its side button as BTN_SIDE where apps expect
+    // BTN_BACK, and middle click as left which the swap then applies to
+    config.button_map.insert(BTN_SIDE, BTN_BACK);
+    config.button_map.insert(BTN_MIDDLE, BTN_LEFT);
+    assert_eq!(map_button(&config, BTN_SIDE), BTN_BACK);
+    assert_eq!(map_button(&config, BTN_MIDDLE), BTN_RIGHT);
+
+    // Logical indices are normalized before the map applies
+    assert_eq!(map_button(&config, 3), BTN_BACK);
+}
+
+#[test]
+fn test_normalize_button() {
+    const BTN_MIDDLE: u32 = 0x112;
+    const BTN_TASK: u32 = 0x117;
+
+    assert_eq!(normalize_button(0), BTN_LEFT);
+    assert_eq!(normalize_button(1), BTN_RIGHT);
+    assert_eq!(normalize_button(2), BTN_MIDDLE);
+    assert_eq!(normalize_button(7), BTN_TASK);
+    for evdev in [BTN_LEFT, BTN_RIGHT, BTN_MIDDLE, BTN_TASK, 0x118, 0x2FF] {
+        assert_eq!(normalize_button(evdev), evdev);
+    }
+}
+
+#[test]
+fn test_edge_position() {
+    use cosmic_comp_config::eis::EisPointerEdge;
+
+    let layout = Some(Rectangle::<i32, Global>::new(
+        (0, 0).into(),
+        (3840, 1080).into(),
+    ));
+    let current = Point::from((3830.0, 500.0));
+    let beyond = Point::from((3850.0, 1090.0));
+
+    assert_eq!(
+        edge_position(EisPointerEdge::Clamp, current, beyond, layout),
+        beyond
+    );
+    assert_eq!(
+        edge_position(EisPointerEdge::Release, current, beyond, layout),
+        current
+    );
+    assert_eq!(
+        edge_position(EisPointerEdge::Wrap, current, beyond, layout),
+        Point::from((10.0, 10.0))
+    );
+    assert_eq!(
+        edge_position(
+            EisPointerEdge::Wrap,
+            Point::from((5.0, 5.0)),
+            Point::from((-5.0, -5.0)),
+            layout
+        ),
+        Point::from((3835.0, 1075.0))
+    );
+    // Nothing to wrap around without outputs
+    assert_eq!(
+        edge_position(EisPointerEdge::Wrap, current, beyond, None),
+        beyond
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Injected {
+    Missing(&'static str),
+    Dropped(&'static str),
+    Key(u32, KeyState),
+    Button(u32, ButtonState),
+    Axis((f64, f64), (i32, i32)),
+    PointerFrame,
+    TouchDown(u32),
+    TouchMotion(u32),
+    TouchUp(u32),
+    TouchCancel,
+    TouchFrame,
+}
+
+/// Records what would be injected into a seat.
+#[derive(Default)]
+struct RecordingInjector {
+    config: EisConfig,
+    /// Capabilities the seat lacks.
+    lacking: Vec<&'static str>,
+    /// Keys down on the seat.
+    keys_down: Vec<u32>,
+    /// Buttons down on the seat.
+    buttons_down: Vec<u32>,
+    grabbed: bool,
+    events: Vec<Injected>,
+    /// Serials of the injected events, in order.
+    serials: Vec<Serial>,
+}
+
+impl RecordingInjector {
+    fn take(&mut self) -> Vec<Injected> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+impl InputInjector for RecordingInjector {
+    fn config(&self) -> &EisConfig {
+        &self.config
+    }
+    fn has_capability(&self, capability: &'static str) -> bool {
+        !self.lacking.contains(&capability)
+    }
+    fn missing_capability(&mut self, capability: &'static str) {
+        self.events.push(Injected::Missing(capability));
+    }
+    fn dropped(&mut self, reason: &'static str) {
+        self.events.push(Injected::Dropped(reason));
+    }
+    fn key_down(&self, key: u32) -> bool {
+        self.keys_down.contains(&key)
+    }
+    fn pointer_grabbed(&self) -> bool {
+        self.grabbed
+    }
+    fn key(&mut self, _connection: u64, key: u32, key_state: KeyState, serial: Serial, _time: u32) {
+        self.serials.push(serial);
+        // Keep track of the seat, which must never see unbalanced keys
+        if key_state == KeyState::Pressed {
+            assert!(!self.keys_down.contains(&key), "key {key} pressed twice");
+            self.keys_down.push(key);
+        } else {
+            let index = self.keys_down.iter().position(|down| *down == key);
+            self.keys_down
+                .remove(index.unwrap_or_else(|| panic!("key {key} released while up")));
+        }
+        self.events.push(Injected::Key(key, key_state));
+    }
+    fn button(&mut self, button: u32, button_state: ButtonState, serial: Serial, _time: u32) {
+        self.serials.push(serial);
+        if button_state == ButtonState::Pressed {
+            if !self.buttons_down.contains(&button) {
+                self.buttons_down.push(button);
+            }
+        } else {
+            let index = self.buttons_down.iter().position(|down| *down == button);
+            self.buttons_down
+                .remove(index.unwrap_or_else(|| panic!("button {button} released while up")));
+        }
+        self.events.push(Injected::Button(button, button_state));
+    }
+    fn axis(&mut self, scroll: ScrollFrame) {
+        self.events.push(Injected::Axis(
+            (scroll.dx, scroll.dy),
+            (scroll.v120_x, scroll.v120_y),
+        ));
+    }
+    fn pointer_frame(&mut self) {
+        self.events.push(Injected::PointerFrame);
+    }
+    fn remote_pointer_activity(&mut self) {}
+    fn touch_down(&mut self, slot: u32, _position: Point<f64, Global>, serial: Serial, _time: u32) {
+        self.serials.push(serial);
+        self.events.push(Injected::TouchDown(slot));
+    }
+    fn touch_motion(&mut self, slot: u32, _position: Point<f64, Global>, _time: u32) {
+        self.events.push(Injected::TouchMotion(slot));
+    }
+    fn touch_up(&mut self, slot: u32, serial: Serial, _time: u32) {
+        self.serials.push(serial);
+        self.events.push(Injected::TouchUp(slot));
+    }
+    fn touch_cancel(&mut self) {
+        self.events.push(Injected::TouchCancel);
+    }
+    fn touch_frame(&mut self) {
+        self.events.push(Injected::TouchFrame);
+    }
+}
+
+#[test]
+fn test_injected_buttons() {
+    use Injected::*;
+    let mut injector = RecordingInjector::default();
+    let mut connection = EisConnection::default();
+
+    handle_button(&mut injector, &mut connection, BTN_LEFT, true, 0);
+    handle_button(&mut injector, &mut connection, BTN_LEFT, false, 1);
+    assert_eq!(
+        injector.take(),
+        vec![
+            Button(BTN_LEFT, ButtonState::Pressed),
+            PointerFrame,
+            Button(BTN_LEFT, ButtonState::Released),
+            PointerFrame
+        ]
+    );
+
+    // A release for a button the client never pressed must not end a
+    // local grab
+    injector.grabbed = true;
+    handle_button(&mut injector, &mut connection, BTN_RIGHT, false, 2);
+    assert_eq!(injector.take(), vec![]);
+
+    injector.config.drop_buttons_during_grab = true;
+    handle_button(&mut injector, &mut connection, BTN_RIGHT, true, 3);
+    assert_eq!(injector.take(), vec![]);
+
+    injector.grabbed = false;
+    injector.config.swap_left_right_buttons = true;
+    handle_button(&mut injector, &mut connection, BTN_RIGHT, true, 4);
+    assert_eq!(
+        injector.take(),
+        vec![Button(BTN_LEFT, ButtonState::Pressed), PointerFrame]
+    );
+
+    injector.lacking.push("pointer");
+    handle_button(&mut injector, &mut connection, BTN_RIGHT, false, 5);
+    handle_scroll(&mut injector, &mut connection, 0.0, 10.0, 5);
+    assert_eq!(
+        injector.take(),
+        vec![Missing("pointer"), Missing("pointer")]
+    );
+}
+
+#[test]
+fn test_release_held_input() {
+    use Injected::*;
+    const KEY_A: u32 = 30;
+    const KEY_LEFTSHIFT: u32 = 42;
+    let mut injector = RecordingInjector::default();
+    let mut connection = EisConnection::default();
+
+    handle_key(&mut injector, &mut connection, KEY_LEFTSHIFT, true, 0);
+    handle_key(&mut injector, &mut connection, KEY_A, true, 0);
+    handle_button(&mut injector, &mut connection, BTN_LEFT, true, 0);
+    handle_scroll(&mut injector, &mut connection, 0.0, 10.0, 0);
+    connection.active_touches.extend([3, 1]);
+    injector.take();
+
+    release_held_input(&mut injector, &mut connection, 1);
+    assert_eq!(
+        injector.take(),
+        vec![
+            Key(KEY_A, KeyState::Released),
+            Key(KEY_LEFTSHIFT, KeyState::Released),
+            Button(BTN_LEFT, ButtonState::Released),
+            PointerFrame,
+            TouchUp(1),
+            TouchUp(3),
+            TouchFrame
+        ]
+    );
+
+    // Nothing is left to release
+    release_held_input(&mut injector, &mut connection, 2);
+    assert_eq!(injector.take(), vec![]);
+}
+
+/// Drive the injection logic with long pseudo-random streams of remote
+/// and local input, checking that the seat never sees a press twice or a
+/// release of something up, and that a disconnect leaves exactly the
+/// local keys down.
+#[test]
+fn test_random_input_streams() {
+    use std::collections::HashSet;
+
+    for seed in 1..=64u64 {
+        // xorshift64, deterministic and dependency free
+        let mut rng = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        let mut next = move |bound: u64| {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            rng % bound
+        };
+
+        let mut injector = RecordingInjector::default();
+        let mut connection = EisConnection::default();
+        let mut local = HashSet::new();
+        for time in 0..2000 {
+            match next(8) {
+                0 | 1 => {
+                    let key = next(16) as u32;
+                    handle_key(&mut injector, &mut connection, key, next(2) == 0, time);
+                }
+                2 => {
+                    // The local user presses or releases a key
+                    let key = next(16) as u32;
+                    if local.remove(&key) {
+                        injector.key(
+                            0,
+                            key,
+                            KeyState::Released,
+                            SERIAL_COUNTER.next_serial(),
+                            time,
+                        );
+                    } else if !injector.keys_down.contains(&key) {
+                        local.insert(key);
+                        injector.key(
+                            0,
+                            key,
+                            KeyState::Pressed,
+                            SERIAL_COUNTER.next_serial(),
+                            time,
+                        );
+                    }
+                }
+                3 | 4 => {
+                    let button = BTN_LEFT + next(4) as u32;
+                    handle_button(&mut injector, &mut connection, button, next(2) == 0, time);
+                }
+                5 => {
+                    let delta = f64::from_bits(next(u64::MAX));
+                    if delta.is_finite() {
+                        handle_scroll(&mut injector, &mut connection, delta, -delta, time);
+                    }
+                }
+                6 => {
+                    injector.grabbed = !injector.grabbed;
+                    injector.config.drop_buttons_during_grab = next(2) == 0;
+                    injector.config.swap_left_right_buttons = next(2) == 0;
+                }
+                _ => {
+                    if next(50) == 0 {
+                        release_held_input(&mut injector, &mut connection, time);
+                        assert!(injector.buttons_down.is_empty());
+                    }
+                }
+            }
+            assert!(injector.keys_down.len() <= 16);
+            assert!(connection.held_buttons.len() <= 4);
+            injector.take();
+        }
+
+        release_held_input(&mut injector, &mut connection, 2000);
+        let down = injector.keys_down.iter().copied().collect::<HashSet<_>>();
+        assert_eq!(down, local, "seed {seed}");
+        assert!(injector.buttons_down.is_empty(), "seed {seed}");
+    }
+}
+
+#[test]
+fn test_touch_frames() {
+    use Injected::*;
+    let mut injector = RecordingInjector::default();
+    let mut connection = EisConnection::default();
+
+    // Touch requests are injected in order, with one frame at the end
+    injector.touch_down(0, (10.0, 10.0).into(), connection.next_serial(), 0);
+    injector.touch_down(1, (20.0, 20.0).into(), connection.next_serial(), 0);
+    connection.touch_frame_pending = true;
+    handle_frame(&mut injector, &mut connection);
+    assert_eq!(
+        injector.take(),
+        vec![TouchDown(0), TouchDown(1), TouchFrame]
+    );
+
+    // Frames without touch events send no touch frame
+    handle_frame(&mut injector, &mut connection);
+    assert_eq!(injector.take(), vec![]);
+
+    // A disconnect in the middle of a frame still ends it
+    connection.active_touches.insert(1);
+    connection.touch_frame_pending = true;
+    release_held_input(&mut injector, &mut connection, 1);
+    assert_eq!(injector.take(), vec![TouchUp(1), TouchFrame]);
+    assert!(!connection.touch_frame_pending);
+}
+
+#[test]
+fn test_touch_up_of_touch_not_down() {
+    use Injected::*;
+    let mut injector = RecordingInjector::default();
+    let mut connection = EisConnection::default();
+
+    // An up for a slot that never went down is dropped, without a frame
+    for input in [RemoteInput::TouchUp { touch_id: 3 }, RemoteInput::Frame] {
+        inject_seat_input(&mut injector, &mut connection, input, 0);
+    }
+    assert_eq!(injector.take(), vec![Dropped("touch not down")]);
+
+    // As is a second up of a slot that went down once
+    for input in [
+        RemoteInput::TouchDown {
+            touch_id: 3,
+            x: 1.0,
+            y: 1.0,
+        },
+        RemoteInput::TouchUp { touch_id: 3 },
+        RemoteInput::TouchUp { touch_id: 3 },
+        RemoteInput::Frame,
+    ] {
+        inject_seat_input(&mut injector, &mut connection, input, 0);
+    }
+    assert_eq!(
+        injector.take(),
+        vec![
+            TouchDown(3),
+            TouchUp(3),
+            Dropped("touch not down"),
+            TouchFrame
+        ]
+    );
+}
+
+#[test]
+fn test_touch_slots_per_connection() {
+    use Injected::*;
+    let mut injector = RecordingInjector::default();
+    let mut first = EisConnection {
+        touch_slot_base: free_touch_slot_base([]),
+        ..Default::default()
+    };
+    let mut second = EisConnection {
+        touch_slot_base: free_touch_slot_base([first.touch_slot_base]),
+        ..Default::default()
+    };
+    assert_ne!(first.touch_slot_base, second.touch_slot_base);
+
+    // Both clients use touch id 1, which ends up in different slots
+    first.active_touches.insert(1);
+    second.active_touches.insert(1);
+    release_held_input(&mut injector, &mut first, 0);
+    release_held_input(&mut injector, &mut second, 0);
+    assert_eq!(
+        injector.take(),
+        vec![
+            TouchUp(1),
+            TouchFrame,
+            TouchUp(TOUCH_SLOTS_PER_CONNECTION + 1),
+            TouchFrame
+        ]
+    );
+    assert!(first.touch_slot(MAX_TOUCH_ID) < second.touch_slot(0));
+    assert_eq!(second.touch_slot(u32::MAX), None);
+
+    // The range of a closed connection is reused
+    let third = EisConnection {
+        touch_slot_base: free_touch_slot_base([second.touch_slot_base]),
+        ..Default::default()
+    };
+    assert_eq!(third.touch_slot_base, 0);
+}
+
+#[test]
+fn test_replay_touch_slots_reserved() {
+    use super::{EisShared, EisState};
+    use crate::state::State;
+    use std::os::unix::net::UnixStream;
+
+    let event_loop = calloop::EventLoop::<State>::try_new().unwrap();
+    let mut eis_state = EisState::new(
+        &event_loop.handle(),
+        &EisConfig::default(),
+        std::sync::Arc::new(EisShared::default()),
+    )
+    .unwrap();
+    eis_state.replay_touch_slots.insert(0);
+
+    // A client connecting during a replay does not share its slots
+    let (server, _client) = UnixStream::pair().unwrap();
+    eis_state.add_connection(server, None).unwrap();
+    let connection = eis_state.connections.values().next().unwrap();
+    assert_eq!(connection.touch_slot_base, TOUCH_SLOTS_PER_CONNECTION);
+}
+
+#[test]
+fn test_max_touch_ids_and_keycodes() {
+    use Injected::*;
+    let mut injector = RecordingInjector::default();
+    let mut connection = EisConnection {
+        touch_slot_base: TOUCH_SLOTS_PER_CONNECTION,
+        ..Default::default()
+    };
+
+    // Validation drops them before injection already, but injecting them
+    // anyway must not panic or wrap around into another client's slots
+    for input in [
+        RemoteInput::TouchDown {
+            touch_id: u32::MAX,
+            x: 1.0,
+            y: 1.0,
+        },
+        RemoteInput::TouchMotion {
+            touch_id: u32::MAX,
+            x: 2.0,
+            y: 2.0,
+        },
+        RemoteInput::TouchUp { touch_id: u32::MAX },
+        RemoteInput::Key {
+            key: u32::MAX,
+            pressed: true,
+        },
+        RemoteInput::Key {
+            key: u32::MAX,
+            pressed: false,
+        },
+        RemoteInput::Button {
+            button: u32::MAX,
+            pressed: true,
+        },
+        RemoteInput::Frame,
+    ] {
+        assert_eq!(
+            input.validate(1000.0),
+            match input {
+                RemoteInput::Frame => Ok(()),
+                _ => Err(InvalidInput::OutOfRange),
+            }
+        );
+        inject_seat_input(&mut injector, &mut connection, input, 0);
+    }
+    assert_eq!(
+        injector.take(),
+        vec![
+            Dropped("touch slot out of range"),
+            Dropped("touch slot out of range"),
+            Dropped("touch not down"),
+            Key(u32::MAX, KeyState::Pressed),
+            Key(u32::MAX, KeyState::Released),
+            Button(u32::MAX, ButtonState::Pressed),
+            PointerFrame,
+        ]
+    );
+    assert!(connection.active_touches.is_empty());
+}
+
+#[test]
+fn test_allowed_pointer_motion() {
+    let mut config = EisConfig::default();
+    assert_eq!(
+        capability_names(allowed_capabilities(&config)),
+        BTreeSet::from([
+            "button",
+            "keyboard",
+            "pointer",
+            "pointer_absolute",
+            "scroll",
+            "touchscreen"
+        ])
+    );
+
+    config.allow_absolute_pointer = false;
+    let allowed = allowed_capabilities(&config);
+    assert!(allowed.contains(DeviceCapability::Pointer | DeviceCapability::Button));
+    assert!(!allowed.contains(DeviceCapability::PointerAbsolute));
+
+    config.allow_absolute_pointer = true;
+    config.allow_relative_pointer = false;
+    let allowed = allowed_capabilities(&config);
+    assert!(allowed.contains(DeviceCapability::PointerAbsolute | DeviceCapability::Button));
+    assert!(!allowed.contains(DeviceCapability::Pointer));
+
+    // Without a pointer at all, neither motion type is offered
+    config.allow_relative_pointer = true;
+    config.allow_pointer = false;
+    let allowed = allowed_capabilities(&config);
+    assert!(!allowed.intersects(
+        DeviceCapability::Pointer | DeviceCapability::PointerAbsolute | DeviceCapability::Button
+    ));
+}
+
+#[test]
+fn test_session_last_activity() {
+    let connection = EisConnection::default();
+    let session = EisSession {
+        id: 1,
+        name: "remote".to_string(),
+        application: None,
+        capabilities: BTreeSet::from(["keyboard"]),
+        last_activity: connection.last_activity.clone(),
+    };
+    // Stores of the connection show in the listing without touching it
+    connection
+        .last_activity
+        .store(1_700_000_000_000, Ordering::Relaxed);
+    assert_eq!(
+        serde_json::to_value(&session).unwrap(),
+        serde_json::json!({
+            "id": 1,
+            "name": "remote",
+            "application": null,
+            "capabilities": ["keyboard"],
+            "last_activity_ms": 1_700_000_000_000u64,
+        })
+    );
+}
+
+#[test]
+fn test_rejected_connections_close_sockets() {
+    use super::{EisError, EisShared, EisState, FD_EXHAUSTION_ATTEMPTS};
+    use crate::state::State;
+    use std::io::{ErrorKind, Read};
+    use std::os::{
+        fd::OwnedFd,
+        unix::net::{UnixDatagram, UnixStream},
+    };
+
+    fn open_fds() -> usize {
+        std::fs::read_dir("/proc/self/fd").unwrap().count()
+    }
+
+    let event_loop = calloop::EventLoop::<State>::try_new().unwrap();
+    let config = EisConfig {
+        max_connections: 1,
+        ..Default::default()
+    };
+    let mut eis_state = EisState::new(
+        &event_loop.handle(),
+        &config,
+        std::sync::Arc::new(EisShared::default()),
+    )
+    .unwrap();
+    let (server, mut accepted) = UnixStream::pair().unwrap();
+    eis_state.add_connection(server, None).unwrap();
+    accepted.set_nonblocking(true).unwrap();
+    assert_eq!(
+        accepted.read(&mut [0; 1]).unwrap_err().kind(),
+        ErrorKind::WouldBlock
+    );
+
+    const REJECTED: usize = 200;
+    let before = open_fds();
+    for _ in 0..REJECTED {
+        // Over the limit: the peer of a closed socket reads end of file
+        // right away, where a leaked one would block
+        let (server, mut client) = UnixStream::pair().unwrap();
+        client.set_nonblocking(true).unwrap();
+        assert!(matches!(
+            eis_state.add_connection(server, None),
+            Err(EisError::ConnectionLimit { .. })
+        ));
+        assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+
+        // Unusable socket
+        let (datagram, _peer) = UnixDatagram::pair().unwrap();
+        assert!(matches!(
+            eis_state.add_connection(UnixStream::from(OwnedFd::from(datagram)), None),
+            Err(EisError::InvalidSocket(_))
+        ));
+    }
+    // Tests running alongside open files of their own, but far fewer
+    // than a leak of two per iteration
+    assert!(open_fds() < before + REJECTED / 2);
+    assert_eq!(
+        eis_state.statistics().rejected_connections,
+        2 * REJECTED as u64
+    );
+
+    // Out of file descriptors, a connection is retried until the last
+    // attempt
+    let emfile = || std::io::Error::from_raw_os_error(libc::EMFILE);
+    let (server, _client) = UnixStream::pair().unwrap();
+    eis_state
+        .retry_add_connection(server, None, 1, emfile())
+        .unwrap();
+    let (server, mut client) = UnixStream::pair().unwrap();
+    client.set_nonblocking(true).unwrap();
+    assert!(matches!(
+        eis_state.retry_add_connection(server, None, FD_EXHAUSTION_ATTEMPTS, emfile()),
+        Err(EisError::FdExhausted {
+            attempts: FD_EXHAUSTION_ATTEMPTS,
+            ..
+        })
+    ));
+    assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn test_fd_exhausted() {
+    assert!(fd_exhausted(&std::io::Error::from_raw_os_error(
+        libc::EMFILE
+    )));
+    assert!(fd_exhausted(&std::io::Error::from_raw_os_error(
+        libc::ENFILE
+    )));
+    assert!(!fd_exhausted(&std::io::Error::from_raw_os_error(
+        libc::ENOTSOCK
+    )));
+    assert!(!fd_exhausted(&std::io::Error::other("protocol error")));
+}
+
+#[test]
+fn test_seat_capabilities() {
+    assert_eq!(
+        capability_names(seat_capabilities(true, true, true)),
+        capability_names(BitFlags::all())
+    );
+    // A seat without a touchscreen offers none to clients either
+    assert_eq!(
+        capability_names(seat_capabilities(true, true, false)),
+        BTreeSet::from([
+            "button",
+            "keyboard",
+            "pointer",
+            "pointer_absolute",
+            "scroll"
+        ])
+    );
+    assert_eq!(
+        capability_names(seat_capabilities(true, false, false)),
+        BTreeSet::from(["keyboard"])
+    );
+    assert!(seat_capabilities(false, false, false).is_empty());
+}
+
+#[test]
+fn test_connection_limit_crossed() {
+    let mut warned = false;
+    // 75% of 8 connections
+    assert!(!connection_limit_crossed(5, 8, 75, &mut warned));
+    assert!(connection_limit_crossed(6, 8, 75, &mut warned));
+    // Warned once while above
+    assert!(!connection_limit_crossed(7, 8, 75, &mut warned));
+    assert!(!connection_limit_crossed(8, 8, 75, &mut warned));
+    assert!(!connection_limit_crossed(6, 8, 75, &mut warned));
+    // And again after dropping below
+    assert!(!connection_limit_crossed(5, 8, 75, &mut warned));
+    assert!(!warned);
+    assert!(connection_limit_crossed(6, 8, 75, &mut warned));
+
+    // The threshold is rounded up, and at least one connection
+    let mut warned = false;
+    assert!(!connection_limit_crossed(2, 3, 75, &mut warned));
+    assert!(connection_limit_crossed(3, 3, 75, &mut warned));
+    let mut warned = false;
+    assert!(connection_limit_crossed(1, 1, 1, &mut warned));
+    let mut warned = false;
+    assert!(!connection_limit_crossed(0, 8, 100, &mut warned));
+    assert!(connection_limit_crossed(8, 8, 100, &mut warned));
+
+    let mut warned = false;
+    assert!(!connection_limit_crossed(8, 8, 0, &mut warned));
+    assert!(!warned);
+}
+
+#[test]
+fn test_smooth_motion() {
+    // Off moves 1:1 and holds nothing back
+    let mut held = (0.0, 0.0);
+    assert_eq!(smooth_motion(0.0, &mut held, 10.0, -4.0), (10.0, -4.0));
+    assert_eq!(held, (0.0, 0.0));
+
+    // Held back motion is carried into the next event, none gets lost
+    let mut held = (0.0, 0.0);
+    assert_eq!(smooth_motion(0.5, &mut held, 10.0, -4.0), (5.0, -2.0));
+    assert_eq!(held, (5.0, -2.0));
+    assert_eq!(smooth_motion(0.5, &mut held, 10.0, 0.0), (7.5, -1.0));
+    assert_eq!(held, (7.5, -1.0));
+
+    // Turning it off applies what is still held back
+    assert_eq!(smooth_motion(0.0, &mut held, 0.0, 0.0), (7.5, -1.0));
+    assert_eq!(held, (0.0, 0.0));
+    assert_eq!(smooth_motion(f64::NAN, &mut held, 1.0, 1.0), (1.0, 1.0));
+}
+
+#[test]
+fn test_revalidate_pointer_on_scale_change() {
+    let left = output("left", (0, 0), (3840, 2160));
+    let right = output("right", (3840, 0), (1920, 1080));
+    let position = Point::<f64, Global>::from((3800.0, 2100.0));
+    assert_eq!(
+        revalidate_pointer(|| [&left, &right].into_iter(), position),
+        None
+    );
+
+    // Scaling the left output up shrinks it to 1920x1080 logical pixels,
+    // leaving the pointer near its bottom right corner off-surface
+    left.change_current_state(None, None, Some(Scale::Fractional(2.0)), None);
+    let (output, revalidated) =
+        revalidate_pointer(|| [&left, &right].into_iter(), position).unwrap();
+    assert_eq!(output, left);
+    assert_eq!(revalidated, Point::from((1919.0, 1079.0)));
+    assert_eq!(
+        revalidate_pointer(|| [&left, &right].into_iter(), revalidated),
+        None
+    );
+
+    // Scaling back down leaves a pointer that is still on it alone
+    left.change_current_state(None, None, Some(Scale::Fractional(1.0)), None);
+    assert_eq!(
+        revalidate_pointer(|| [&left, &right].into_iter(), revalidated),
+        None
+    );
+
+    // Nor is a pointer on the other output moved
+    left.change_current_state(None, None, Some(Scale::Fractional(2.0)), None);
+    let position = Point::<f64, Global>::from((3850.0, 10.0));
+    assert_eq!(
+        revalidate_pointer(|| [&left, &right].into_iter(), position),
+        None
+    );
+    assert_eq!(revalidate_pointer(std::iter::empty, position), None);
+}
+
+#[test]
+fn test_negotiated_capabilities() {
+    let mut connection = EisConnection::default();
+    assert!(connection.negotiated_capabilities().is_empty());
+    connection.interface_versions.extend([
+        ("ei_keyboard", 1),
+        ("ei_touchscreen", 1),
+        ("ei_callback", 1),
+    ]);
+    assert_eq!(
+        connection.negotiated_capabilities(),
+        DeviceCapability::Keyboard | DeviceCapability::Touch
+    );
+    // Touch is announced once the seat has a touchscreen
+    assert_eq!(
+        capability_names(
+            connection.negotiated_capabilities() & seat_capabilities(true, true, false)
+        ),
+        BTreeSet::from(["keyboard"])
+    );
+    assert_eq!(
+        capability_names(
+            connection.negotiated_capabilities() & seat_capabilities(true, true, true)
+        ),
+        BTreeSet::from(["keyboard", "touchscreen"])
+    );
+}
+
+#[test]
+fn test_protocol() {
+    let protocol = EisProtocol::current();
+    assert_eq!(protocol.interfaces.len(), 6);
+    assert!(protocol.interfaces.contains(&"ei_touchscreen"));
+    assert_eq!(protocol.capabilities.len(), 6);
+    assert_eq!(
+        HANDLED_REQUESTS.iter().collect::<BTreeSet<_>>().len(),
+        HANDLED_REQUESTS.len()
+    );
+    assert!(!protocol.requests.contains(&"other"));
+
+    let json = serde_json::to_value(&protocol).unwrap();
+    assert!(json["reis_version"].is_string());
+    assert_eq!(json["requests"][0], "disconnect");
+}
+
+#[test]
+fn test_early_input_bounded() {
+    let mut connection = EisConnection::default();
+    let key = |pressed| RemoteInput::Key { key: 30, pressed };
+    for i in 0..MAX_EARLY_INPUT {
+        assert!(connection.queue_early_input(key(i % 2 == 0)));
+    }
+    // A client that never starts emulating cannot grow the queue further
+    assert!(!connection.queue_early_input(key(true)));
+    assert_eq!(connection.early_input.len(), MAX_EARLY_INPUT);
+    // Input is kept in the order it arrived
+    assert_eq!(connection.early_input[0], key(true));
+    assert_eq!(connection.early_input[1], key(false));
+}
+
+#[test]
+fn test_queue_flush() {
+    let mut connection = EisConnection::default();
+    assert_eq!(connection.unflushed, None);
+    connection.queue_flush("device announcement");
+    connection.queue_flush("modifier state");
+    assert_eq!(connection.unflushed.take(), Some("device announcement"));
+    assert_eq!(connection.unflushed, None);
+}
+
+#[test]
+fn test_peer_hung_up() {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    let (server, mut client) = UnixStream::pair().unwrap();
+    server.set_nonblocking(true).unwrap();
+    assert!(!peer_hung_up(&server));
+
+    // Unread data is not a hangup, even if the client left after it
+    client.write_all(b"x").unwrap();
+    drop(client);
+    assert!(!peer_hung_up(&server));
+
+    let (server, client) = UnixStream::pair().unwrap();
+    server.set_nonblocking(true).unwrap();
+    drop(client);
+    assert!(peer_hung_up(&server));
+}
+
+/// A remote Ctrl+C, alone and crossing a modifier boundary, reaches the
+/// seat as exactly the client's key events, in order.
+#[test]
+fn test_remote_key_sequences() {
+    use Injected::*;
+    use KeyState::{Pressed, Released};
+    const CTRL: u32 = 29;
+    const SHIFT: u32 = 42;
+    const KEY_C: u32 = 46;
+    let mut injector = RecordingInjector::default();
+    let mut connection = EisConnection::default();
+
+    for (key, pressed) in [(CTRL, true), (KEY_C, true), (KEY_C, false), (CTRL, false)] {
+        handle_key(&mut injector, &mut connection, key, pressed, 0);
+    }
+    assert_eq!(
+        injector.take(),
+        vec![
+            Key(CTRL, Pressed),
+            Key(KEY_C, Pressed),
+            Key(KEY_C, Released),
+            Key(CTRL, Released)
+        ]
+    );
+
+    // Ctrl is let go before C, with a local Shift held throughout
+    injector.key(0, SHIFT, Pressed, SERIAL_COUNTER.next_serial(), 0);
+    injector.take();
+    for (key, pressed) in [(CTRL, true), (KEY_C, true), (CTRL, false), (KEY_C, false)] {
+        handle_key(&mut injector, &mut connection, key, pressed, 0);
+    }
+    assert_eq!(
+        injector.take(),
+        vec![
+            Key(CTRL, Pressed),
+            Key(KEY_C, Pressed),
+            Key(CTRL, Released),
+            Key(KEY_C, Released)
+        ]
+    );
+    assert_eq!(injector.keys_down, vec![SHIFT]);
+
+    // Nothing is left for a disconnect to release
+    release_held_input(&mut injector, &mut connection, 0);
+    assert_eq!(injector.take(), vec![]);
+}
+
+#[test]
+fn test_discrete_scroll() {
+    use Injected::*;
+    let mut injector = RecordingInjector::default();
+    let mut connection = EisConnection::default();
+
+    // Wheel clicks stay clicks, apart from continuous scrolling
+    handle_discrete_scroll(&mut injector, &mut connection, 0, -120, 0);
+    handle_frame(&mut injector, &mut connection);
+    handle_scroll(&mut injector, &mut connection, 0.0, 2.5, 1000);
+    handle_frame(&mut injector, &mut connection);
+    assert_eq!(
+        injector.take(),
+        vec![
+            Axis((0.0, 0.0), (0, -120)),
+            PointerFrame,
+            Axis((0.0, 2.5), (0, 0)),
+            PointerFrame
+        ]
+    );
+
+    // Multipliers apply to clicks too, and what rounds to nothing is dropped
+    injector.config.scroll_multiplier_vertical = 2.0;
+    injector.config.scroll_multiplier_horizontal = 0.001;
+    handle_discrete_scroll(&mut injector, &mut connection, 120, 60, 2000);
+    handle_frame(&mut injector, &mut connection);
+    handle_discrete_scroll(&mut injector, &mut connection, 120, 0, 3000);
+    handle_frame(&mut injector, &mut connection);
+    assert_eq!(
+        injector.take(),
+        vec![Axis((0.0, 0.0), (0, 120)), PointerFrame]
+    );
+}
+
+#[test]
+fn test_diagonal_scroll() {
+    use Injected::*;
+    let mut injector = RecordingInjector::default();
+    let mut connection = EisConnection::default();
+
+    // Each axis sent as a request of its own still makes one axis frame
+    for input in [
+        RemoteInput::ScrollDelta {
+            dx: 0.0,
+            dy: 5.0,
+            utime: 0,
+        },
+        RemoteInput::ScrollDelta {
+            dx: -3.0,
+            dy: 0.0,
+            utime: 0,
+        },
+    ] {
+        inject_seat_input(&mut injector, &mut connection, input, 0);
+    }
+    assert_eq!(injector.take(), vec![]);
+    inject_seat_input(&mut injector, &mut connection, RemoteInput::Frame, 0);
+    assert_eq!(
+        injector.take(),
+        vec![Axis((-3.0, 5.0), (0, 0)), PointerFrame]
+    );
+
+    // Wheel clicks on both axes go along with the distance scrolled
+    handle_scroll(&mut injector, &mut connection, 1.5, 0.0, 100);
+    handle_scroll(&mut injector, &mut connection, 0.0, -1.5, 100);
+    handle_discrete_scroll(&mut injector, &mut connection, 120, 0, 100);
+    handle_discrete_scroll(&mut injector, &mut connection, 0, -120, 100);
+    handle_frame(&mut injector, &mut connection);
+    assert_eq!(
+        injector.take(),
+        vec![Axis((1.5, -1.5), (120, -120)), PointerFrame]
+    );
+
+    // Frames without scrolling have no axis frame, and scrolling of a
+    // frame the client never finished is dropped on disconnect
+    handle_frame(&mut injector, &mut connection);
+    handle_scroll(&mut injector, &mut connection, 0.0, 2.0, 200);
+    release_held_input(&mut injector, &mut connection, 300);
+    handle_frame(&mut injector, &mut connection);
+    assert_eq!(injector.take(), vec![]);
+}
+
+#[test]
+fn test_pointer_confinement() {
+    let left = output("left", (0, 0), (1920, 1080));
+    let right = output("right", (1920, 0), (1920, 1080));
+    let outputs = [left.clone(), right.clone()];
+
+    // A window straddling both outputs
+    let region = Rectangle::<f64, Global>::new((1800.0, 100.0).into(), (400.0, 300.0).into());
+    assert_eq!(
+        confine((1000.0, 50.0).into(), region),
+        Point::from((1800.0, 100.0))
+    );
+    assert_eq!(
+        confine((3000.0, 900.0).into(), region),
+        Point::from((2200.0, 400.0))
+    );
+    let inside = Point::from((2000.0, 200.0));
+    assert_eq!(confine(inside, region), inside);
+    assert_eq!(
+        place_on_outputs(|| outputs.iter(), confine(inside, region)),
+        Some((right, inside))
+    );
+
+    // A region reaching past the layout still keeps the cursor visible
+    let region = Rectangle::<f64, Global>::new((-100.0, 0.0).into(), (50.0, 50.0).into());
+    assert_eq!(
+        place_on_outputs(|| outputs.iter(), confine((500.0, 20.0).into(), region)),
+        Some((left, (0.0, 20.0).into()))
+    );
+}
+
+/// Serials of a connection increase with every injected event, across
+/// frames and including what a disconnect releases, so releases always
+/// come after their press.
+#[test]
+fn test_serial_monotonicity() {
+    const KEY_A: u32 = 30;
+    const KEY_B: u32 = 48;
+    let mut injector = RecordingInjector::default();
+    let mut connection = EisConnection::default();
+    // Another connection injecting in between
+    let mut other = EisConnection::default();
+
+    handle_button(&mut injector, &mut connection, BTN_LEFT, true, 0);
+    handle_key(&mut injector, &mut other, KEY_B, true, 0);
+    handle_key(&mut injector, &mut connection, KEY_A, true, 0);
+    handle_frame(&mut injector, &mut connection);
+    handle_button(&mut injector, &mut connection, BTN_LEFT, false, 1);
+    handle_button(&mut injector, &mut connection, BTN_RIGHT, true, 1);
+    let touch = connection.next_serial();
+    injector.touch_down(0, (10.0, 10.0).into(), touch, 1);
+    connection.active_touches.insert(0);
+    release_held_input(&mut injector, &mut connection, 2);
+
+    let serials = std::mem::take(&mut injector.serials);
+    assert_eq!(serials.len(), 9);
+    let ours = serials
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 1)
+        .map(|(_, serial)| *serial)
+        .collect::<Vec<_>>();
+    assert!(ours.windows(2).all(|pair| pair[0] < pair[1]), "{ours:?}");
+    assert_eq!(connection.last_serial, ours.last().copied());
+    assert_eq!(other.last_serial, Some(serials[1]));
+}
+
+#[test]
+fn test_replay_recording() {
+    use Injected::*;
+    const KEY_A: u32 = 30;
+    let recorded = [
+        RecordedInput {
+            connection: 3,
+            elapsed_us: 0,
+            input: RemoteInput::Key {
+                key: KEY_A,
+                pressed: true,
+            },
+        },
+        RecordedInput {
+            connection: 3,
+            elapsed_us: 1500,
+            input: RemoteInput::TouchDown {
+                touch_id: 2,
+                x: 10.0,
+                y: 20.5,
+            },
+        },
+        RecordedInput {
+            connection: 3,
+            elapsed_us: 1600,
+            input: RemoteInput::Frame,
+        },
+    ];
+    let recording = recorded
+        .iter()
+        .map(|event| serde_json::to_string(event).unwrap() + "\n")
+        .collect::<String>();
+    assert!(recording.starts_with(r#"{"connection":3,"elapsed_us":0,"type":"key","#));
+    let events = parse_recording(&recording).unwrap();
+    assert_eq!(events, recorded);
+    assert!(parse_recording("{\"connection\":1}").is_err());
+
+    let mut injector = RecordingInjector::default();
+    let mut connection = EisConnection::default();
+    for event in events {
+        inject_seat_input(&mut injector, &mut connection, event.input, 0);
+    }
+    assert_eq!(
+        injector.take(),
+        vec![Key(KEY_A, KeyState::Pressed), TouchDown(2), TouchFrame]
+    );
+    // Whatever the recording left held is released at its end
+    release_held_input(&mut injector, &mut connection, 0);
+    assert_eq!(
+        injector.take(),
+        vec![Key(KEY_A, KeyState::Released), TouchUp(2), TouchFrame]
+    );
+}
+
+#[test]
+fn test_client_key_repeat() {
+    use cosmic_comp_config::eis::EisKeyRepeat;
+    const KEY_A: u32 = 30;
+
+    // The client holds A and repeats it five times on its own
+    let presses = |key_repeat| {
+        let mut injector = RecordingInjector::default();
+        injector.config.key_repeat = key_repeat;
+        let mut connection = EisConnection::default();
+        for _ in 0..6 {
+            handle_key(&mut injector, &mut connection, KEY_A, true, 0);
+        }
+        handle_key(&mut injector, &mut connection, KEY_A, false, 0);
+        assert!(injector.keys_down.is_empty());
+        injector
+            .take()
+            .into_iter()
+            .filter(|event| *event == Injected::Key(KEY_A, KeyState::Pressed))
+            .count()
+    };
+    // The seat repeats the held key itself
+    assert_eq!(presses(EisKeyRepeat::Compositor), 1);
+    // Every repeat of the client arrives as a press of its own
+    assert_eq!(presses(EisKeyRepeat::Client), 6);
+}
@@ -81,6 +81,7 @@ use std::{
 };
 
 pub mod actions;
+#[cfg(feature = "eis")]
 pub mod eis;
 pub mod gestures;
 
@@ -166,6 +167,8 @@ impl State {
         <B as InputBackend>::Device: 'static,
     {
         crate::wayland::handlers::output_power::set_all_surfaces_dpms_on(self);
+        #[cfg(feature = "eis")]
+        self.common.eis_forget_surface_under();
 
         use smithay::backend::input::Event;
         match event {
@@ -180,6 +183,9 @@ impl State {
                         &TabletDescriptor::from(&device),
                     );
                 }
+                std::mem::drop(shell);
+                #[cfg(feature = "eis")]
+                self.eis_seat_capabilities_changed();
             }
             InputEvent::DeviceRemoved { device } => {
                 for seat in &mut self.common.shell.read().seats.iter() {
@@ -196,6 +202,8 @@ impl State {
                         break;
                     }
                 }
+                #[cfg(feature = "eis")]
+                self.eis_seat_capabilities_changed();
             }
 
             InputEvent::Keyboard { event, .. } => {
@@ -1862,6 +1870,20 @@ impl State {
             return FilterResult::Intercept(None);
         }
 
+        // A remote session holding an exclusive keyboard grab keeps local key presses
+        // from reaching clients or triggering shortcuts, except on the lock screen
+        #[cfg(feature = "eis")]
+        if event.state() == KeyState::Pressed
+            && keyboard.with_grab(|_, grab| grab.is::<eis::EisKeyboardGrab>()) == Some(true)
+            && !matches!(
+                keyboard.current_focus(),
+                Some(KeyboardFocusTarget::LockSurface(_))
+            )
+        {
+            seat.supressed_keys().add(&handle, None);
+            return FilterResult::Intercept(None);
+        }
+
         // handle the rest of the global shortcuts
         let mut clear_queue = true;
         if !shortcuts_inhibited {
@@ -165,6 +165,8 @@ pub fn run(hooks: crate::hooks::Hooks) -> Result<(), Box<dyn Error>> {
         // shall we shut down?
         if state.common.should_stop {
             info!("Shutting down");
+            #[cfg(feature = "eis")]
+            state.eis_shutdown();
             state.common.event_loop_signal.stop();
             state.common.event_loop_signal.wakeup();
             return;
@@ -273,7 +275,10 @@ fn init_wayland_display(
             Generic::new(display, Interest::READ, Mode::Level),
             move |_, display, state| {
                 // SAFETY: We don't drop the display
-                match unsafe { display.get_mut().dispatch_clients(state) } {
+                let result = unsafe { display.get_mut().dispatch_clients(state) };
+                #[cfg(feature = "eis")]
+                state.common.eis_forget_surface_under();
+                match result {
                     Ok(_) => Ok(PostAction::Continue),
                     Err(err) => {
                         error!(?err, "I/O error on the Wayland display");
@@ -289,6 +289,9 @@ pub struct Shell {
     appearance_conf: AppearanceConfig,
     tiling_exceptions: TilingExceptions,
 
+    /// Set while the local cursor is hidden for a remote (EIS) pointer
+    #[cfg(feature = "eis")]
+    pub hide_local_cursor: bool,
     #[cfg(feature = "debug")]
     pub debug_active: bool,
 }
@@ -1441,6 +1444,8 @@ impl Common {
 
         std::mem::drop(shell);
         self.refresh(); // fixes indicies of any moved workspaces
+        #[cfg(feature = "eis")]
+        self.eis_outputs_changed();
     }
 
     pub fn remove_output(&mut self, output: &Output) {
@@ -1455,6 +1460,8 @@ impl Common {
 
         std::mem::drop(shell);
         self.refresh(); // cleans up excess of workspaces and empty workspaces
+        #[cfg(feature = "eis")]
+        self.eis_outputs_changed();
     }
 
     pub fn update_config(&mut self) {
@@ -1598,6 +1605,8 @@ impl Shell {
             zoom_state: None,
             tiling_exceptions,
 
+            #[cfg(feature = "eis")]
+            hide_local_cursor: false,
             #[cfg(feature = "debug")]
             debug_active: false,
         }
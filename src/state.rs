@@ -295,6 +295,7 @@ pub struct Common {
     pub pointer_focus_state: Option<PointerFocusState>,
 
     // EIS input injection (remote desktop)
+    #[cfg(feature = "eis")]
     pub eis_state: Option<crate::input::eis::EisState>,
 
     #[cfg(feature = "systemd")]
@@ -588,6 +589,8 @@ impl LockedBackend<'_> {
             state.update_inhibitor_locks();
             state.common.update_xwayland_settings();
             state.common.update_xwayland_primary_output();
+            #[cfg(feature = "eis")]
+            state.common.eis_outputs_changed();
         });
 
         Ok(())
@@ -802,6 +805,7 @@ impl State {
                 xwayland_shell_state,
                 pointer_focus_state: None,
 
+                #[cfg(feature = "eis")]
                 eis_state: None,
 
                 #[cfg(feature = "systemd")]
@@ -9,15 +9,38 @@ use smithay::{
 use std::os::unix::io::OwnedFd;
 use tracing::warn;
 
+/// Owner of a selection the compositor set on behalf of someone other than a
+/// Wayland client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionOwner {
+    /// Selection of an Xwayland client.
+    Xwm(XwmId),
+    /// Selection of a remote desktop client, set through the EIS D-Bus
+    /// interface.
+    #[cfg(feature = "eis")]
+    Remote,
+}
+
 impl SelectionHandler for State {
-    type SelectionUserData = XwmId;
+    type SelectionUserData = SelectionOwner;
 
     fn new_selection(
         &mut self,
         target: SelectionTarget,
         source: Option<SelectionSource>,
-        _seat: Seat<State>,
+        seat: Seat<State>,
     ) {
+        #[cfg(feature = "eis")]
+        if target == SelectionTarget::Clipboard {
+            let mime_types = source
+                .as_ref()
+                .map(SelectionSource::mime_types)
+                .unwrap_or_default();
+            self.eis_selection_changed(&seat, mime_types);
+        }
+        #[cfg(not(feature = "eis"))]
+        let _ = seat;
+
         let Some(xwm_id) = self
             .common
             .xwayland_state
@@ -63,17 +86,23 @@ impl SelectionHandler for State {
         mime_type: String,
         fd: OwnedFd,
         _seat: Seat<State>,
-        _user_data: &Self::SelectionUserData,
+        user_data: &Self::SelectionUserData,
     ) {
-        if let Some(xwm) = self
-            .common
-            .xwayland_state
-            .as_mut()
-            .and_then(|xstate| xstate.xwm.as_mut())
-        {
-            if let Err(err) = xwm.send_selection(target, mime_type, fd) {
-                warn!(?err, "Failed to send selection (X11 -> Wayland).");
+        match user_data {
+            SelectionOwner::Xwm(_) => {
+                if let Some(xwm) = self
+                    .common
+                    .xwayland_state
+                    .as_mut()
+                    .and_then(|xstate| xstate.xwm.as_mut())
+                {
+                    if let Err(err) = xwm.send_selection(target, mime_type, fd) {
+                        warn!(?err, "Failed to send selection (X11 -> Wayland).");
+                    }
+                }
             }
+            #[cfg(feature = "eis")]
+            SelectionOwner::Remote => self.eis_send_selection(mime_type, fd),
         }
     }
 }
@@ -13,7 +13,7 @@ use crate::{
     },
     state::State,
     utils::prelude::*,
-    wayland::handlers::xdg_activation::ActivationContext,
+    wayland::handlers::{selection::SelectionOwner, xdg_activation::ActivationContext},
 };
 use cosmic_comp_config::{EavesdroppingKeyboardMode, XwaylandDescaling};
 use smithay::{
@@ -1215,11 +1215,21 @@ impl XwmHandler for State {
         let seat = self.common.shell.read().seats.last_active().clone();
         match selection {
             SelectionTarget::Clipboard => {
-                set_data_device_selection(&self.common.display_handle, &seat, mime_types, xwm)
-            }
-            SelectionTarget::Primary => {
-                set_primary_selection(&self.common.display_handle, &seat, mime_types, xwm)
+                #[cfg(feature = "eis")]
+                self.eis_selection_changed(&seat, mime_types.clone());
+                set_data_device_selection(
+                    &self.common.display_handle,
+                    &seat,
+                    mime_types,
+                    SelectionOwner::Xwm(xwm),
+                )
             }
+            SelectionTarget::Primary => set_primary_selection(
+                &self.common.display_handle,
+                &seat,
+                mime_types,
+                SelectionOwner::Xwm(xwm),
+            ),
         }
     }
 
@@ -1228,12 +1238,16 @@ impl XwmHandler for State {
         for seat in shell.seats.iter() {
             match selection {
                 SelectionTarget::Clipboard => {
-                    if current_data_device_selection_userdata(seat).as_deref() == Some(&xwm) {
+                    if current_data_device_selection_userdata(seat).as_deref()
+                        == Some(&SelectionOwner::Xwm(xwm))
+                    {
                         clear_data_device_selection(&self.common.display_handle, seat)
                     }
                 }
                 SelectionTarget::Primary => {
-                    if current_primary_selection_userdata(seat).as_deref() == Some(&xwm) {
+                    if current_primary_selection_userdata(seat).as_deref()
+                        == Some(&SelectionOwner::Xwm(xwm))
+                    {
                         clear_primary_selection(&self.common.display_handle, seat)
                     }
                 }